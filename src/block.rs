@@ -58,6 +58,10 @@ impl Block {
 
             key: Vec::new(),
             val_offset: 0,
+
+            prev_entries: Vec::new(),
+            prev_keys: Vec::new(),
+            prev_restart_ix: 0,
         }
     }
 
@@ -95,6 +99,23 @@ pub struct BlockIter {
     key: Vec<u8>,
     /// Offset of the current value within the block.
     val_offset: usize,
+
+    /// Entries preceding the current one within a restart interval, decoded by prev(), so that
+    /// iterating backwards doesn't rescan the restart interval for every entry.
+    prev_entries: Vec<PrevEntry>,
+    /// The full keys of prev_entries, concatenated.
+    prev_keys: Vec<u8>,
+    /// Index of the restart interval that prev_entries belong to.
+    prev_restart_ix: usize,
+}
+
+/// PrevEntry describes an entry decoded by BlockIter::prev().
+struct PrevEntry {
+    entry_offset: usize,
+    val_offset: usize,
+    next_offset: usize,
+    /// Offset of the entry's key within BlockIter::prev_keys.
+    key_offset: usize,
 }
 
 impl BlockIter {
@@ -134,22 +155,71 @@ impl BlockIter {
     /// above.
     /// Advances self.offset to the beginning of the next entry.
     fn parse_entry_and_advance(&mut self) -> (usize, usize, usize, usize) {
+        let (shared, non_shared, valsize, i) = self.parse_entry(self.offset);
+
+        self.val_offset = self.offset + i + non_shared;
+        self.offset = self.val_offset + valsize;
+
+        (shared, non_shared, valsize, i)
+    }
+
+    /// Like parse_entry_and_advance(), but parses the entry at `off` without modifying the
+    /// iterator.
+    fn parse_entry(&self, off: usize) -> (usize, usize, usize, usize) {
         let mut i = 0;
-        let (shared, sharedlen) = usize::decode_var(&self.block[self.offset..]);
+        let (shared, sharedlen) = usize::decode_var(&self.block[off..]);
         i += sharedlen;
 
-        let (non_shared, non_sharedlen) = usize::decode_var(&self.block[self.offset + i..]);
+        let (non_shared, non_sharedlen) = usize::decode_var(&self.block[off + i..]);
         i += non_sharedlen;
 
-        let (valsize, valsizelen) = usize::decode_var(&self.block[self.offset + i..]);
+        let (valsize, valsizelen) = usize::decode_var(&self.block[off + i..]);
         i += valsizelen;
 
-        self.val_offset = self.offset + i + non_shared;
-        self.offset = self.val_offset + valsize;
-
         (shared, non_shared, valsize, i)
     }
 
+    /// Decodes all entries of the restart interval containing the entry before `off` up to
+    /// `off` into prev_entries/prev_keys.
+    fn decode_entries_before(&mut self, off: usize) {
+        let num_restarts = self.number_restarts();
+        let mut ix = self.current_restart_ix.min(num_restarts - 1);
+        while ix > 0 && self.get_restart_point(ix) >= off {
+            ix -= 1;
+        }
+        while ix + 1 < num_restarts && self.get_restart_point(ix + 1) < off {
+            ix += 1;
+        }
+
+        self.prev_entries.clear();
+        self.prev_keys.clear();
+        self.prev_restart_ix = ix;
+
+        let mut entry_offset = self.get_restart_point(ix);
+        let mut last_key_offset = 0;
+        while entry_offset < off {
+            let (shared, non_shared, valsize, head_len) = self.parse_entry(entry_offset);
+            let key_offset = self.prev_keys.len();
+            for i in last_key_offset..last_key_offset + shared {
+                let b = self.prev_keys[i];
+                self.prev_keys.push(b);
+            }
+            let key_start = entry_offset + head_len;
+            self.prev_keys
+                .extend_from_slice(&self.block[key_start..key_start + non_shared]);
+
+            let val_offset = key_start + non_shared;
+            self.prev_entries.push(PrevEntry {
+                entry_offset,
+                val_offset,
+                next_offset: val_offset + valsize,
+                key_offset,
+            });
+            last_key_offset = key_offset;
+            entry_offset = val_offset + valsize;
+        }
+    }
+
     /// Assemble the current key from shared and non-shared parts (an entry usually contains only
     /// the part of the key that is different from the previous key).
     ///
@@ -162,24 +232,6 @@ impl BlockIter {
         self.key
             .extend_from_slice(&self.block[off..off + non_shared]);
     }
-
-    pub fn seek_to_last(&mut self) {
-        if self.number_restarts() > 0 {
-            let num_restarts = self.number_restarts();
-            self.seek_to_restart_point(num_restarts - 1);
-        } else {
-            self.reset();
-        }
-
-        // Stop at last entry, before the iterator becomes invalid.
-        //
-        // We're checking the position before calling advance; if a restart point points to the
-        // last entry, calling advance() will directly reset the iterator.
-        while self.offset < self.restarts_off {
-            self.advance();
-        }
-        assert!(self.valid());
-    }
 }
 
 impl LdbIterator for BlockIter {
@@ -211,10 +263,11 @@ impl LdbIterator for BlockIter {
         self.val_offset = 0;
         self.current_restart_ix = 0;
         self.key.clear();
+        self.prev_entries.clear();
+        self.prev_keys.clear();
     }
 
     fn prev(&mut self) -> bool {
-        // as in the original implementation -- seek to last restart point, then look for key
         let orig_offset = self.current_entry_offset;
 
         // At the beginning, can't go further back
@@ -223,30 +276,50 @@ impl LdbIterator for BlockIter {
             return false;
         }
 
-        while self.get_restart_point(self.current_restart_ix) >= orig_offset {
-            // todo: double check this
-            if self.current_restart_ix == 0 {
-                self.offset = self.restarts_off;
-                self.current_restart_ix = self.number_restarts();
-                break;
-            }
-            self.current_restart_ix -= 1;
+        // Decode the restart interval before the current entry, unless that was already done by
+        // the previous call.
+        let cached = self
+            .prev_entries
+            .last()
+            .map(|e| e.next_offset == orig_offset)
+            .unwrap_or(false);
+        if !cached {
+            self.decode_entries_before(orig_offset);
         }
 
-        self.offset = self.get_restart_point(self.current_restart_ix);
-        assert!(self.offset < orig_offset);
+        let e = self.prev_entries.pop().unwrap();
+        self.key.clear();
+        self.key.extend_from_slice(&self.prev_keys[e.key_offset..]);
+        self.prev_keys.truncate(e.key_offset);
 
-        let mut result;
+        self.current_entry_offset = e.entry_offset;
+        self.val_offset = e.val_offset;
+        self.offset = e.next_offset;
+        self.current_restart_ix = self.prev_restart_ix;
+        true
+    }
 
-        // Stop if the next entry would be the original one (self.offset always points to the start
-        // of the next entry)
-        loop {
-            result = self.advance();
-            if self.offset >= orig_offset {
-                break;
-            }
+    fn seek_to_last(&mut self) {
+        if self.restarts_off == 0 {
+            // Empty block.
+            self.reset();
+            return;
+        }
+        if self.number_restarts() > 0 {
+            let num_restarts = self.number_restarts();
+            self.seek_to_restart_point(num_restarts - 1);
+        } else {
+            self.reset();
+        }
+
+        // Stop at last entry, before the iterator becomes invalid.
+        //
+        // We're checking the position before calling advance; if a restart point points to the
+        // last entry, calling advance() will directly reset the iterator.
+        while self.offset < self.restarts_off {
+            self.advance();
         }
-        result
+        assert!(self.valid());
     }
 
     fn seek(&mut self, to: &[u8]) {
@@ -300,6 +373,24 @@ impl LdbIterator for BlockIter {
             false
         }
     }
+
+    /// key returns the full key of the current entry. Keys are prefix-compressed, so this is the
+    /// key assembled by the last move; it is not copied again.
+    fn key(&self) -> &[u8] {
+        if self.valid() {
+            &self.key
+        } else {
+            &[]
+        }
+    }
+    /// value returns the current value as a slice of the block contents.
+    fn value(&self) -> &[u8] {
+        if self.valid() {
+            &self.block[self.val_offset..self.offset]
+        } else {
+            &[]
+        }
+    }
 }
 
 #[cfg(feature = "enclave_unit_test")]
@@ -319,6 +410,7 @@ pub mod tests {
             test_block_iterate_reverse,
             test_block_seek,
             test_block_seek_to_last,
+            test_block_iterate_reverse_full,
         )
     }
 
@@ -519,4 +611,42 @@ pub mod tests {
             );
         }
     }
+
+    fn test_block_iterate_reverse_full() {
+        let mut o = options::for_test();
+        let data: Vec<(Vec<u8>, Vec<u8>)> = (0..50)
+            .map(|i| {
+                (
+                    format!("key{:03}", i).into_bytes(),
+                    format!("value{}", i).into_bytes(),
+                )
+            })
+            .collect();
+
+        for block_restart_interval in vec![1, 3, 16, 100] {
+            o.block_restart_interval = block_restart_interval;
+            let mut builder = BlockBuilder::new(o.clone());
+            for &(ref k, ref v) in data.iter() {
+                builder.add(k, v);
+            }
+            let mut block = Block::new(o.clone(), builder.finish()).iter();
+
+            block.seek_to_last();
+            for i in (0..data.len()).rev() {
+                assert_eq!(Some(data[i].clone()), current_key_val(&block));
+                assert_eq!(i > 0, block.prev());
+            }
+            assert!(!block.valid());
+
+            // Change directions in the middle of a restart interval.
+            block.seek(&data[20].0);
+            assert!(block.prev());
+            assert!(block.prev());
+            assert_eq!(Some(data[18].clone()), current_key_val(&block));
+            assert!(block.advance());
+            assert_eq!(Some(data[19].clone()), current_key_val(&block));
+            assert!(block.prev());
+            assert_eq!(Some(data[18].clone()), current_key_val(&block));
+        }
+    }
 }
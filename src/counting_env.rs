@@ -0,0 +1,391 @@
+//! An Env wrapping another Env, which counts the operations and bytes going to the files of a
+//! database, by type of file. The counters show the read and write amplification of a workload.
+#[cfg(feature = "mesalock_sgx")]
+use std::prelude::v1::*;
+
+use crate::env::{Env, FileLock, Logger, RandomAccess};
+use crate::error::Result;
+use crate::options::Options;
+use crate::types::{parse_file_name, share, FileType, Shared};
+
+use std::fmt::Write as FmtWrite;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// IoFileType is the class of files that I/O operations are counted for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IoFileType {
+    Log = 0,
+    Table = 1,
+    Manifest = 2,
+    Other = 3,
+}
+
+const NUM_IO_FILE_TYPES: usize = 4;
+const IO_FILE_TYPES: [IoFileType; NUM_IO_FILE_TYPES] = [
+    IoFileType::Log,
+    IoFileType::Table,
+    IoFileType::Manifest,
+    IoFileType::Other,
+];
+
+impl IoFileType {
+    /// Classifies a file by its name, as returned by types::parse_file_name().
+    pub fn of(p: &Path) -> IoFileType {
+        let name = match p.file_name() {
+            Some(n) => n,
+            None => return IoFileType::Other,
+        };
+        match parse_file_name(name) {
+            Ok((_, FileType::Log)) => IoFileType::Log,
+            Ok((_, FileType::Table)) => IoFileType::Table,
+            Ok((_, FileType::Descriptor)) => IoFileType::Manifest,
+            _ => IoFileType::Other,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            IoFileType::Log => "log",
+            IoFileType::Table => "table",
+            IoFileType::Manifest => "manifest",
+            IoFileType::Other => "other",
+        }
+    }
+}
+
+/// IoCounters contains the I/O operations on one type of file. Flushing a file counts as a sync.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct IoCounters {
+    pub opens: usize,
+    pub reads: usize,
+    pub bytes_read: usize,
+    pub writes: usize,
+    pub bytes_written: usize,
+    pub syncs: usize,
+    pub deletes: usize,
+}
+
+/// IoStats is a handle to the counters of a CountingEnv. Clones share the counters.
+#[derive(Clone)]
+pub struct IoStats(Shared<[IoCounters; NUM_IO_FILE_TYPES]>);
+
+impl IoStats {
+    fn new() -> IoStats {
+        IoStats(share(Default::default()))
+    }
+
+    /// Returns the counters for files of type t.
+    pub fn get(&self, t: IoFileType) -> IoCounters {
+        self.0.borrow()[t as usize]
+    }
+
+    /// Sets all counters to zero.
+    pub fn reset(&self) {
+        *self.0.borrow_mut() = Default::default();
+    }
+
+    /// Returns a table of all counters, with one line per file type.
+    pub fn summary(&self) -> String {
+        let mut s = String::from(
+            "type      opens  reads   bytes read  writes  bytes written  syncs  deletes\n",
+        );
+        for t in IO_FILE_TYPES.iter() {
+            let c = self.get(*t);
+            let _ = writeln!(
+                s,
+                "{:8} {:6} {:6} {:12} {:7} {:14} {:6} {:8}",
+                t.name(),
+                c.opens,
+                c.reads,
+                c.bytes_read,
+                c.writes,
+                c.bytes_written,
+                c.syncs,
+                c.deletes
+            );
+        }
+        s
+    }
+
+    fn update<F: FnOnce(&mut IoCounters)>(&self, t: IoFileType, f: F) {
+        f(&mut self.0.borrow_mut()[t as usize])
+    }
+}
+
+/// CountingEnv wraps another Env and counts the I/O going through it.
+pub struct CountingEnv {
+    inner: Rc<Box<dyn Env>>,
+    stats: IoStats,
+}
+
+impl CountingEnv {
+    pub fn new(inner: Rc<Box<dyn Env>>) -> CountingEnv {
+        CountingEnv {
+            inner,
+            stats: IoStats::new(),
+        }
+    }
+
+    /// Replaces the env in opt with a CountingEnv wrapping it, and sets opt.io_stats to its
+    /// counters, so that they are reported by DB::get_property("leveldb.io-stats").
+    pub fn install(opt: &mut Options) -> IoStats {
+        let env = CountingEnv::new(opt.env.clone());
+        let stats = env.stats();
+        opt.env = Rc::new(Box::new(env));
+        opt.io_stats = Some(stats.clone());
+        stats
+    }
+
+    pub fn stats(&self) -> IoStats {
+        self.stats.clone()
+    }
+
+    fn opened(&self, p: &Path) -> IoFileType {
+        let t = IoFileType::of(p);
+        self.stats.update(t, |c| c.opens += 1);
+        t
+    }
+}
+
+struct CountingReader {
+    typ: IoFileType,
+    inner: Box<dyn Read>,
+    stats: IoStats,
+}
+
+impl Read for CountingReader {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(dst)?;
+        self.stats.update(self.typ, |c| {
+            c.reads += 1;
+            c.bytes_read += n;
+        });
+        Ok(n)
+    }
+}
+
+struct CountingRandomAccess {
+    typ: IoFileType,
+    inner: Box<dyn RandomAccess>,
+    stats: IoStats,
+}
+
+impl RandomAccess for CountingRandomAccess {
+    fn read_at(&self, off: usize, dst: &mut [u8]) -> Result<usize> {
+        let n = self.inner.read_at(off, dst)?;
+        self.stats.update(self.typ, |c| {
+            c.reads += 1;
+            c.bytes_read += n;
+        });
+        Ok(n)
+    }
+}
+
+struct CountingWriter {
+    typ: IoFileType,
+    inner: Box<dyn Write>,
+    stats: IoStats,
+}
+
+impl Write for CountingWriter {
+    fn write(&mut self, src: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(src)?;
+        self.stats.update(self.typ, |c| {
+            c.writes += 1;
+            c.bytes_written += n;
+        });
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()?;
+        self.stats.update(self.typ, |c| c.syncs += 1);
+        Ok(())
+    }
+}
+
+impl Env for CountingEnv {
+    fn open_sequential_file(&self, p: &Path) -> Result<Box<dyn Read>> {
+        let f = self.inner.open_sequential_file(p)?;
+        Ok(Box::new(CountingReader {
+            typ: self.opened(p),
+            inner: f,
+            stats: self.stats(),
+        }))
+    }
+    fn open_random_access_file(&self, p: &Path) -> Result<Box<dyn RandomAccess>> {
+        let f = self.inner.open_random_access_file(p)?;
+        Ok(Box::new(CountingRandomAccess {
+            typ: self.opened(p),
+            inner: f,
+            stats: self.stats(),
+        }))
+    }
+    fn open_writable_file(&self, p: &Path) -> Result<Box<dyn Write>> {
+        let f = self.inner.open_writable_file(p)?;
+        Ok(Box::new(CountingWriter {
+            typ: self.opened(p),
+            inner: f,
+            stats: self.stats(),
+        }))
+    }
+    fn open_appendable_file(&self, p: &Path) -> Result<Box<dyn Write>> {
+        let f = self.inner.open_appendable_file(p)?;
+        Ok(Box::new(CountingWriter {
+            typ: self.opened(p),
+            inner: f,
+            stats: self.stats(),
+        }))
+    }
+
+    fn exists(&self, p: &Path) -> Result<bool> {
+        self.inner.exists(p)
+    }
+    fn children(&self, p: &Path) -> Result<Vec<PathBuf>> {
+        self.inner.children(p)
+    }
+    fn size_of(&self, p: &Path) -> Result<usize> {
+        self.inner.size_of(p)
+    }
+
+    fn delete(&self, p: &Path) -> Result<()> {
+        self.inner.delete(p)?;
+        self.stats.update(IoFileType::of(p), |c| c.deletes += 1);
+        Ok(())
+    }
+    fn mkdir(&self, p: &Path) -> Result<()> {
+        self.inner.mkdir(p)
+    }
+    fn rmdir(&self, p: &Path) -> Result<()> {
+        self.inner.rmdir(p)
+    }
+    fn rename(&self, old: &Path, new: &Path) -> Result<()> {
+        self.inner.rename(old, new)
+    }
+
+    fn lock(&self, p: &Path) -> Result<FileLock> {
+        self.inner.lock(p)
+    }
+    fn unlock(&self, l: FileLock) -> Result<()> {
+        self.inner.unlock(l)
+    }
+
+    fn new_logger(&self, p: &Path) -> Result<Logger> {
+        self.inner.new_logger(p)
+    }
+
+    fn micros(&self) -> u64 {
+        self.inner.micros()
+    }
+    fn sleep_for(&self, micros: u32) -> Result<()> {
+        self.inner.sleep_for(micros)
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use crate::db_impl::DB;
+    use crate::mem_env::MemEnv;
+    use crate::options;
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_counting_env_file_types,
+            test_counting_env_counters,
+            test_counting_env_db_property,
+        )
+    }
+
+    fn test_counting_env_file_types() {
+        for (name, t) in vec![
+            ("db/000003.log", IoFileType::Log),
+            ("db/000012.ldb", IoFileType::Table),
+            ("000012.sst", IoFileType::Table),
+            ("db/MANIFEST-000002", IoFileType::Manifest),
+            ("db/CURRENT", IoFileType::Other),
+            ("db/000004.dbtmp", IoFileType::Other),
+            ("db/LOG", IoFileType::Other),
+            ("db/xyz", IoFileType::Other),
+        ] {
+            assert_eq!(t, IoFileType::of(Path::new(name)), "{}", name);
+        }
+    }
+
+    fn test_counting_env_counters() {
+        let env = CountingEnv::new(Rc::new(Box::new(MemEnv::new())));
+        let stats = env.stats();
+        let (log, table) = (Path::new("db/000001.log"), Path::new("db/000002.ldb"));
+
+        {
+            let mut w = env.open_writable_file(log).unwrap();
+            w.write_all(b"hello").unwrap();
+            w.write_all(b"world").unwrap();
+            w.flush().unwrap();
+            env.open_writable_file(table)
+                .unwrap()
+                .write_all(b"abc")
+                .unwrap();
+        }
+        let mut v = vec![];
+        env.open_sequential_file(log)
+            .unwrap()
+            .read_to_end(&mut v)
+            .unwrap();
+        let f = env.open_random_access_file(table).unwrap();
+        let mut buf = [0; 2];
+        f.read_at(1, &mut buf).unwrap();
+        env.delete(table).unwrap();
+
+        let c = stats.get(IoFileType::Log);
+        assert_eq!(2, c.opens);
+        assert_eq!(2, c.writes);
+        assert_eq!(10, c.bytes_written);
+        assert_eq!(1, c.syncs);
+        assert_eq!(10, c.bytes_read);
+        assert_eq!(0, c.deletes);
+        assert_eq!(
+            IoCounters {
+                opens: 2,
+                reads: 1,
+                bytes_read: 2,
+                writes: 1,
+                bytes_written: 3,
+                syncs: 0,
+                deletes: 1,
+            },
+            stats.get(IoFileType::Table)
+        );
+        assert_eq!(IoCounters::default(), stats.get(IoFileType::Manifest));
+
+        stats.reset();
+        assert_eq!(IoCounters::default(), stats.get(IoFileType::Log));
+    }
+
+    fn test_counting_env_db_property() {
+        let mut opt = options::for_test();
+        let stats = CountingEnv::install(&mut opt);
+        let mut db = DB::open("db", opt).unwrap();
+
+        for i in 0..100 {
+            db.put(format!("key{:03}", i).as_bytes(), b"value").unwrap();
+        }
+        db.flush().unwrap();
+        assert!(stats.get(IoFileType::Log).bytes_written > 100 * 5);
+        assert!(stats.get(IoFileType::Manifest).bytes_written > 0);
+        assert_eq!(0, stats.get(IoFileType::Table).bytes_written);
+
+        db.compact_range(b"key000", b"key999").unwrap();
+        assert_eq!(Some(b"value".to_vec()), db.get(b"key050"));
+        let tables = stats.get(IoFileType::Table);
+        assert!(tables.opens > 0);
+        assert!(tables.bytes_written > 0);
+
+        let summary = db.get_property("leveldb.io-stats").unwrap();
+        assert_eq!(stats.summary(), summary);
+        assert!(summary.contains("table"));
+    }
+}
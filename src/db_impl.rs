@@ -8,25 +8,31 @@ use std::prelude::v1::*;
 #[cfg(feature = "mesalock_sgx")]
 use std::untrusted::path::PathEx;
 
-use crate::db_iter::DBIterator;
+use crate::db_iter::{DBIterator, DBRange};
+use crate::disk_env::{DBPersistKey, KeyRing};
 
 use crate::cmp::{Cmp, InternalKeyCmp};
-use crate::env::{Env, FileLock};
+use crate::env::{Env, FileLock, RandomAccessReader};
 use crate::error::{err, Result, StatusCode};
 use crate::filter::{BoxedFilterPolicy, InternalFilterPolicy};
 use crate::infolog::Logger;
+use crate::key_provider::BoxedKeyProvider;
 use crate::key_types::{parse_internal_key, InternalKey, LookupKey, ValueType};
 use crate::log::{LogReader, LogWriter};
 use crate::memtable::MemTable;
 use crate::merging_iter::MergingIter;
 use crate::options::Options;
+use crate::slice_transform::SliceTransform;
 use crate::snapshot::{Snapshot, SnapshotList};
+use crate::state_digest::{table_hash, StateDigest};
 use crate::table_builder::TableBuilder;
 use crate::table_cache::{table_file_name, TableCache};
+use crate::transaction::Transaction;
 use crate::types::{
-    parse_file_name, share, FileMetaData, FileNum, FileType, LdbIterator, SequenceNumber, Shared,
-    MAX_SEQUENCE_NUMBER, NUM_LEVELS,
+    parse_file_name, share, FileMetaData, FileNum, FileType, KeyGeneration, LdbIterator,
+    SequenceNumber, Shared, TableHash, MAX_SEQUENCE_NUMBER, NUM_LEVELS,
 };
+use crate::updates_iter::UpdatesIter;
 use crate::version::Version;
 use crate::version_edit::VersionEdit;
 use crate::version_set::{
@@ -35,9 +41,9 @@ use crate::version_set::{
 use crate::write_batch::WriteBatch;
 
 use std::cmp::Ordering;
-use std::io::{self, BufWriter, Write};
+use std::io::{self, BufWriter, Read, Write};
 use std::mem;
-use std::ops::Drop;
+use std::ops::{Bound, Drop, RangeBounds};
 use std::path::Path;
 use std::path::PathBuf;
 use std::rc::Rc;
@@ -48,6 +54,10 @@ pub struct DB {
     name: PathBuf,
     path: PathBuf,
     lock: Option<FileLock>,
+    // Set by open_read_only(); no files are written, and writes are rejected.
+    read_only: bool,
+    // How far a read-only database has replayed the log files into its memtable.
+    replayed: Option<ReplayedLogs>,
 
     internal_cmp: Rc<Box<dyn Cmp>>,
     fpol: InternalFilterPolicy<BoxedFilterPolicy>,
@@ -86,6 +96,8 @@ impl DB {
             name: name.to_owned(),
             path,
             lock: None,
+            read_only: false,
+            replayed: None,
             internal_cmp: Rc::new(Box::new(InternalKeyCmp(opt.cmp.clone()))),
             fpol: InternalFilterPolicy::new(opt.filter_policy.clone()),
 
@@ -116,6 +128,10 @@ impl DB {
     pub fn open<P: AsRef<Path>>(name: P, opt: Options) -> Result<DB> {
         let name = name.as_ref();
         let mut db = DB::new(name, opt);
+        if db.opt.rate_limiter.is_some() {
+            // Fail now rather than on the first table write if the env can't sleep.
+            db.opt.env.sleep_for(0)?;
+        }
         let mut ve = VersionEdit::new();
         let save_manifest = db.recover(&mut ve)?;
 
@@ -141,6 +157,95 @@ impl DB {
         Ok(db)
     }
 
+    /// Opens an existing database for reading only, without modifying any of its files: the lock
+    /// isn't taken, no log or manifest is written, and no compactions are run. Writes still in
+    /// log files are replayed into the memtable. All writes fail with StatusCode::NotSupported.
+    ///
+    /// Unless `opt.log` is set, nothing is logged, as the info log is kept in the database
+    /// directory. Another instance may have the database open as the primary; the read-only
+    /// instance then follows it with try_catch_up_with_primary().
+    pub fn open_read_only<P: AsRef<Path>>(name: P, mut opt: Options) -> Result<DB> {
+        if opt.log.is_none() {
+            opt.log = Some(share(Logger(Box::new(io::sink()))));
+        }
+        // Both would open files for appending.
+        opt.reuse_manifest = false;
+        opt.reuse_logs = false;
+        let mut db = DB::new(name.as_ref(), opt);
+        db.read_only = true;
+        db.vset.borrow_mut().read_only = true;
+        db.recover(&mut VersionEdit::new())?;
+        Ok(db)
+    }
+
+    /// try_catch_up_with_primary brings a database opened with open_read_only() up to date with
+    /// the primary instance writing to it: the manifest is read again to pick up new tables, and
+    /// the primary's live log files are replayed into the memtable. Replaying continues where the
+    /// last catch-up stopped, unless the primary has since moved log files into tables; then the
+    /// memtable is rebuilt from the live log files.
+    ///
+    /// If it fails, e.g. because the primary switched to a new manifest or deleted files in the
+    /// meantime, the database keeps its previous state and the call can be retried. As the
+    /// primary doesn't know about them, snapshots and iterators of this instance don't keep it
+    /// from compacting away their entries and deleting their tables.
+    pub fn try_catch_up_with_primary(&mut self) -> Result<()> {
+        if !self.read_only {
+            return err(
+                StatusCode::NotSupported,
+                "only read-only databases can follow a primary",
+            );
+        }
+        let mut vset = VersionSet::new(&self.name, self.opt.clone(), self.cache.clone());
+        vset.read_only = true;
+        vset.recover()?;
+        // Entries of log files that are now covered by tables must not stay in the memtable, as
+        // they may hide newer entries in those tables.
+        let keep_mem = match self.replayed {
+            Some(ref r) => r.first >= vset.log_num,
+            None => false,
+        };
+        let old_replayed = self.replayed;
+        let old_last_seq = self.vset.borrow().last_seq;
+        let old_vset = mem::replace(&mut *self.vset.borrow_mut(), vset);
+        let old_mem = if keep_mem {
+            None
+        } else {
+            self.replayed = None;
+            Some(mem::replace(
+                &mut self.mem,
+                MemTable::new(self.opt.cmp.clone()),
+            ))
+        };
+        if let Err(e) = self.recover_logs(&mut VersionEdit::new()) {
+            // Entries already replayed into a kept memtable stay there; they are newer than
+            // the restored last sequence number, and thus not visible.
+            *self.vset.borrow_mut() = old_vset;
+            if let Some(m) = old_mem {
+                self.mem = m;
+                self.replayed = old_replayed;
+            }
+            return Err(e);
+        }
+        if keep_mem && self.vset.borrow().last_seq < old_last_seq {
+            self.vset.borrow_mut().last_seq = old_last_seq;
+        }
+        log!(
+            self.opt.log,
+            "Caught up with primary at sequence number {}",
+            self.vset.borrow().last_seq
+        );
+        Ok(())
+    }
+
+    /// Returns an error with StatusCode::NotSupported if the database was opened read-only.
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            err(StatusCode::NotSupported, "the database is opened read-only")
+        } else {
+            Ok(())
+        }
+    }
+
     /// initialize_db initializes a new database.
     fn initialize_db(&mut self) -> Result<()> {
         let mut ve = VersionEdit::new();
@@ -162,29 +267,39 @@ impl DB {
     /// recover recovers from the existing state on disk. If the wrapped result is `true`, then
     /// log_and_apply() should be called after recovery has finished.
     fn recover(&mut self, ve: &mut VersionEdit) -> Result<bool> {
-        if self.opt.error_if_exists && self.opt.env.exists(&self.path.as_ref()).unwrap_or(false) {
-            return err(StatusCode::AlreadyExists, "database already exists");
-        }
-
-        let _ = self.opt.env.mkdir(Path::new(&self.path));
-        self.acquire_lock()?;
-
-        if let Err(e) = read_current_file(&self.opt.env, &self.path) {
-            if e.code == StatusCode::NotFound && self.opt.create_if_missing {
-                self.initialize_db()?;
-            } else {
-                return err(
-                    StatusCode::InvalidArgument,
-                    "database does not exist and create_if_missing is false",
-                );
+        if self.read_only {
+            if let Err(e) = read_current_file(&self.opt.env, &self.path) {
+                if e.code == StatusCode::NotFound {
+                    return err(StatusCode::NotFound, "database does not exist");
+                }
+                return Err(e);
             }
+        } else {
+            self.create_or_lock()?;
         }
 
         // If save_manifest is true, we should log_and_apply() later in order to write the new
         // manifest.
-        let mut save_manifest = self.vset.borrow_mut().recover()?;
+        let save_manifest = self.vset.borrow_mut().recover()?;
+        let save_manifest_ = self.recover_logs(ve)?;
+        Ok(save_manifest || save_manifest_)
+    }
 
-        // Recover from all log files not in the descriptor.
+    /// recover_logs checks that all files of the current version exist, and recovers from all log
+    /// files not in the descriptor. If the wrapped result is `true`, then log_and_apply() should
+    /// be called after recovery has finished.
+    fn recover_logs(&mut self, ve: &mut VersionEdit) -> Result<bool> {
+        if let Some(ref ring) = self.opt.key_ring {
+            let current = self.current();
+            for level in 0..NUM_LEVELS {
+                for f in &current.borrow().files[level] {
+                    let f = f.borrow();
+                    ring.set_file_generation(&table_file_name(&self.path, f.num), f.key_gen);
+                }
+            }
+        }
+
+        let mut save_manifest = false;
         let mut max_seq = 0;
         let filenames = self.opt.env.children(&self.path)?;
         let mut expected = self.vset.borrow().live_files();
@@ -204,8 +319,15 @@ impl DB {
             log!(self.opt.log, "Missing at least these files: {:?}", expected);
             return err(StatusCode::Corruption, "missing live files (see log)");
         }
+        if self.opt.paranoid_checks {
+            self.verify_table_hashes()?;
+        }
 
         log_files.sort();
+        // A read-only database continues with the last log file it replayed.
+        if let Some(ref r) = self.replayed {
+            log_files.retain(|&num| num >= r.last);
+        }
         for i in 0..log_files.len() {
             let (save_manifest_, max_seq_) =
                 self.recover_log_file(log_files[i], i == log_files.len() - 1, ve)?;
@@ -225,9 +347,42 @@ impl DB {
         Ok(save_manifest)
     }
 
+    /// create_or_lock creates the database if it is missing and allowed to, and acquires the lock.
+    fn create_or_lock(&mut self) -> Result<()> {
+        if self.opt.error_if_exists && self.opt.env.exists(&self.path.as_ref()).unwrap_or(false) {
+            return err(StatusCode::AlreadyExists, "database already exists");
+        }
+
+        let _ = self.opt.env.mkdir(Path::new(&self.path));
+        self.acquire_lock()?;
+
+        if let Err(e) = read_current_file(&self.opt.env, &self.path) {
+            if e.code == StatusCode::NotFound && self.opt.create_if_missing {
+                // A counter in use means that the database existed, and its files were removed.
+                if let Some(ref c) = self.opt.monotonic_counter {
+                    if c.read()? > 0 {
+                        return err(
+                            StatusCode::Rollback,
+                            "database does not exist, but its monotonic counter is in use",
+                        );
+                    }
+                }
+                self.initialize_db()?;
+            } else {
+                return err(
+                    StatusCode::InvalidArgument,
+                    "database does not exist and create_if_missing is false",
+                );
+            }
+        }
+        Ok(())
+    }
+
     /// recover_log_file reads a single log file into a memtable, writing new L0 tables if
     /// necessary. If is_last is true, it checks whether the log file can be reused, and sets up
-    /// the database's logging handles appropriately if that's the case.
+    /// the database's logging handles appropriately if that's the case. A read-only database
+    /// keeps the contents of all log files in its memtable instead, and records in self.replayed
+    /// how far it got.
     fn recover_log_file(
         &mut self,
         log_num: FileNum,
@@ -240,50 +395,78 @@ impl DB {
         let mut save_manifest = false;
         let cmp: Rc<Box<dyn Cmp>> = self.opt.cmp.clone();
         let mut mem = MemTable::new(cmp.clone());
+        let first = self.replayed.map_or(log_num, |r| r.first);
+        let start = match self.replayed {
+            Some(ref r) if r.last == log_num => r.offset,
+            _ => 0,
+        };
         {
-            let logfile = self.opt.env.open_sequential_file(Path::new(&filename))?;
+            let logfile: Box<dyn Read> = if start > 0 {
+                let f = self.opt.env.open_random_access_file(Path::new(&filename))?;
+                Box::new(RandomAccessReader::new(f, start))
+            } else {
+                self.opt.env.open_sequential_file(Path::new(&filename))?
+            };
             // Use the user-supplied comparator; it will be wrapped inside a MemtableKeyCmp.
 
-            let mut logreader = LogReader::new(
-                logfile, // checksum=
-                true,
-            );
+            let checksum = true;
+            let mut logreader = LogReader::new_with_off(logfile, checksum, start);
             log!(self.opt.log, "Recovering log file {:?}", filename);
             let mut scratch = vec![];
-            let mut batch = WriteBatch::new();
 
             while let Ok(len) = logreader.read(&mut scratch) {
                 if len == 0 {
                     break;
                 }
-                if len < 12 {
-                    log!(
-                        self.opt.log,
-                        "corruption in log file {:06}: record shorter than 12B",
-                        log_num
-                    );
+                let batch = match WriteBatch::from_bytes(&scratch) {
+                    Ok(batch) => batch,
+                    Err(e) => {
+                        log!(self.opt.log, "corruption in log file {:06}: {}", log_num, e);
+                        if self.opt.paranoid_checks {
+                            return Err(e);
+                        }
+                        continue;
+                    }
+                };
+                if batch.count() == 0 {
                     continue;
                 }
-
-                batch.set_contents(&scratch);
-                batch.insert_into_memtable(batch.sequence(), &mut mem);
+                if self.read_only {
+                    // Entries go straight into the memtable, so that they are accounted for in
+                    // self.replayed even if a later record fails.
+                    batch.insert_into_memtable(batch.sequence(), &mut self.mem);
+                    self.replayed = Some(ReplayedLogs {
+                        first,
+                        last: log_num,
+                        offset: logreader.offset(),
+                    });
+                } else {
+                    batch.insert_into_memtable(batch.sequence(), &mut mem);
+                }
 
                 let last_seq = batch.sequence() + batch.count() as u64 - 1;
                 if last_seq > max_seq {
                     max_seq = last_seq
                 }
-                if mem.approx_mem_usage() > self.opt.write_buffer_size {
+                if !self.read_only && mem.approx_mem_usage() > self.opt.write_buffer_size {
                     compactions += 1;
                     self.write_l0_table(&mem, ve, None)?;
                     save_manifest = true;
                     mem = MemTable::new(cmp.clone());
                 }
-                batch.clear();
+            }
+            if self.read_only {
+                self.replayed = Some(ReplayedLogs {
+                    first,
+                    last: log_num,
+                    offset: logreader.offset(),
+                });
             }
         }
 
-        // Check if we can reuse the last log file.
-        if self.opt.reuse_logs && is_last && compactions == 0 {
+        // A read-only database has put everything into self.mem already, and mem is empty.
+        if !self.read_only && self.opt.reuse_logs && is_last && compactions == 0 {
+            // Reuse the last log file.
             assert!(self.log.is_none());
             log!(self.opt.log, "reusing log file {:?}", filename);
             let oldsize = self.opt.env.size_of(Path::new(&filename))?;
@@ -305,11 +488,26 @@ impl DB {
     fn delete_obsolete_files(&mut self) -> Result<()> {
         let files = self.vset.borrow().live_files();
         let filenames = self.opt.env.children(Path::new(&self.path))?;
+
+        // The newest opt.keep_log_files log files not needed anymore are kept for
+        // get_updates_since().
+        let log_num = self.vset.borrow().log_num;
+        let mut old_logs: Vec<FileNum> = filenames
+            .iter()
+            .filter_map(|name| match parse_file_name(name) {
+                Ok((num, FileType::Log)) if num < log_num => Some(num),
+                _ => None,
+            })
+            .collect();
+        old_logs.sort();
+        let keep_from = old_logs.len().saturating_sub(self.opt.keep_log_files);
+        let oldest_kept_log = old_logs.get(keep_from).cloned().unwrap_or(log_num);
+
         for name in filenames {
             if let Ok((num, typ)) = parse_file_name(&name) {
                 match typ {
                     FileType::Log => {
-                        if num >= self.vset.borrow().log_num {
+                        if num >= oldest_kept_log {
                             continue;
                         }
                     }
@@ -394,6 +592,7 @@ impl DB {
     /// Writes an entire WriteBatch. `sync` determines whether the write should be flushed to
     /// disk.
     pub fn write(&mut self, batch: WriteBatch, sync: bool) -> Result<()> {
+        self.check_writable()?;
         assert!(self.log.is_some());
 
         self.make_room_for_write(false)?;
@@ -411,8 +610,28 @@ impl DB {
         Ok(())
     }
 
+    /// write_replicated writes a batch received from another database, keeping the sequence
+    /// numbers assigned to it there. The batch must directly follow the latest write to this
+    /// database; otherwise, an error with StatusCode::InvalidArgument is returned and nothing is
+    /// written.
+    pub fn write_replicated(&mut self, batch: WriteBatch, sync: bool) -> Result<()> {
+        let expected = self.vset.borrow().last_seq + 1;
+        if batch.sequence() != expected {
+            return err(
+                StatusCode::InvalidArgument,
+                &format!(
+                    "replicated batch starts at sequence number {}, expected {}",
+                    batch.sequence(),
+                    expected
+                ),
+            );
+        }
+        self.write(batch, sync)
+    }
+
     /// flush makes sure that all pending changes (e.g. from put()) are stored on disk.
     pub fn flush(&mut self) -> Result<()> {
+        self.check_writable()?;
         assert!(self.log.is_some());
         self.log.as_mut().unwrap().flush()
     }
@@ -426,45 +645,160 @@ impl DB {
         // will compare "Lesser" using the InternalKeyCmp
         let lkey = LookupKey::new(key, seq);
 
-        match self.mem.get(&lkey) {
-            (Some(v), _) => return Ok(Some(v)),
+        if let Some(v) = self.get_from_memtables(&lkey) {
+            return Ok(v);
+        }
+
+        let mut do_compaction = false;
+        let mut result = None;
+
+        // Limiting the borrow scope of self.current.
+        {
+            let current = self.current();
+            let mut current = current.borrow_mut();
+            if let Ok(Some((v, st))) = current.get(lkey.internal_key()) {
+                if current.update_stats(st) {
+                    do_compaction = true;
+                }
+                result = Some(v)
+            }
+        }
+
+        if do_compaction {
+            if let Err(e) = self.maybe_do_compaction() {
+                log!(self.opt.log, "error while doing compaction in get: {}", e);
+            }
+        }
+        Ok(result)
+    }
+
+    /// get_from_memtables looks up lkey in the memtable and the immutable memtable. It returns
+    /// None if neither contains an entry for the key, and Some(None) if the key was deleted.
+    fn get_from_memtables(&self, lkey: &LookupKey) -> Option<Option<Vec<u8>>> {
+        match self.mem.get(lkey) {
+            (Some(v), _) => return Some(Some(v)),
             // deleted entry
-            (None, true) => return Ok(None),
+            (None, true) => return Some(None),
             // not found entry
             (None, false) => {}
         }
 
         if let Some(imm) = self.imm.as_ref() {
-            match imm.get(&lkey) {
-                (Some(v), _) => return Ok(Some(v)),
+            match imm.get(lkey) {
+                (Some(v), _) => return Some(Some(v)),
                 // deleted entry
-                (None, true) => return Ok(None),
+                (None, true) => return Some(None),
                 // not found entry
                 (None, false) => {}
             }
         }
+        None
+    }
+
+    /// multi_get reads the values for several keys at once, at the given snapshot or (if None) at
+    /// the current state of the database. All keys are read at the same sequence number, and the
+    /// table lookups are batched so that every table is consulted once per call. The results are
+    /// returned in the order of `keys`.
+    pub fn multi_get(
+        &mut self,
+        keys: &[&[u8]],
+        snapshot: Option<&Snapshot>,
+    ) -> Vec<Result<Option<Vec<u8>>>> {
+        let seq = match snapshot {
+            Some(ss) => ss.sequence(),
+            None => self.vset.borrow().last_seq,
+        };
+        let mut order: Vec<usize> = (0..keys.len()).collect();
+        let cmp = self.opt.cmp.clone();
+        order.sort_by(|&a, &b| cmp.cmp(keys[a], keys[b]));
+
+        let lkeys: Vec<LookupKey> = keys.iter().map(|k| LookupKey::new(k, seq)).collect();
+        let mut results: Vec<Result<Option<Vec<u8>>>> = keys.iter().map(|_| Ok(None)).collect();
+        // Indices of keys not found in the memtables, in sorted key order.
+        let mut pending = Vec::with_capacity(keys.len());
+        for i in order {
+            match self.get_from_memtables(&lkeys[i]) {
+                Some(v) => results[i] = Ok(v),
+                None => pending.push(i),
+            }
+        }
+        if pending.is_empty() {
+            return results;
+        }
 
         let mut do_compaction = false;
-        let mut result = None;
 
         // Limiting the borrow scope of self.current.
         {
             let current = self.current();
             let mut current = current.borrow_mut();
-            if let Ok(Some((v, st))) = current.get(lkey.internal_key()) {
-                if current.update_stats(st) {
-                    do_compaction = true;
-                }
-                result = Some(v)
+            let ikeys: Vec<InternalKey> =
+                pending.iter().map(|&i| lkeys[i].internal_key()).collect();
+            for (&i, r) in pending.iter().zip(current.multi_get(&ikeys).into_iter()) {
+                results[i] = match r {
+                    Ok(Some((v, st))) => {
+                        if current.update_stats(st) {
+                            do_compaction = true;
+                        }
+                        Ok(Some(v))
+                    }
+                    Ok(None) => Ok(None),
+                    Err(e) => Err(e),
+                };
             }
         }
 
         if do_compaction {
             if let Err(e) = self.maybe_do_compaction() {
-                log!(self.opt.log, "error while doing compaction in get: {}", e);
+                log!(
+                    self.opt.log,
+                    "error while doing compaction in multi_get: {}",
+                    e
+                );
             }
         }
-        Ok(result)
+        results
+    }
+
+    /// get_updates_since returns an iterator over all batches written to the database, starting
+    /// with the batch containing sequence number `seq`. The batches are read from the log files;
+    /// see Options::keep_log_files for keeping log files around after their contents have been
+    /// written to tables. If seq isn't contained in the log files anymore, an error with
+    /// StatusCode::NotFound is returned.
+    pub fn get_updates_since(&mut self, seq: SequenceNumber) -> Result<UpdatesIter> {
+        // Make sure that all writes have reached the current log file.
+        if let Some(ref mut log) = self.log {
+            log.flush()?;
+        }
+        let mut logs = vec![];
+        for name in self.opt.env.children(&self.path)? {
+            if let Ok((num, FileType::Log)) = parse_file_name(&name) {
+                logs.push(num);
+            }
+        }
+        let last_seq = self.vset.borrow().last_seq;
+        UpdatesIter::new(self.opt.env.clone(), &self.path, logs, seq, last_seq)
+    }
+
+    /// latest_sequence_for_key returns the sequence number of the newest entry for key (which may
+    /// be a deletion), or None if the database doesn't contain any entry for it.
+    pub fn latest_sequence_for_key(&mut self, key: &[u8]) -> Result<Option<SequenceNumber>> {
+        let lkey = LookupKey::new(key, MAX_SEQUENCE_NUMBER);
+        if let Some(seq) = self.mem.get_sequence(&lkey) {
+            return Ok(Some(seq));
+        }
+        if let Some(seq) = self.imm.as_ref().and_then(|imm| imm.get_sequence(&lkey)) {
+            return Ok(Some(seq));
+        }
+        let current = self.current();
+        let current = current.borrow();
+        current.get_sequence(lkey.internal_key())
+    }
+
+    /// latest_sequence_number returns the sequence number of the most recent write. A snapshot
+    /// taken now has this sequence number, and get_at_sequence() reads the current state with it.
+    pub fn latest_sequence_number(&self) -> SequenceNumber {
+        self.vset.borrow().last_seq
     }
 
     /// get_at reads the value for a given key at or before snapshot. It returns Ok(None) if the
@@ -473,9 +807,16 @@ impl DB {
         self.get_internal(snapshot.sequence(), key)
     }
 
+    /// get_at_sequence reads the value for a given key as of sequence number seq. Unlike with
+    /// get_at(), the version visible at seq may already have been removed by a compaction, unless
+    /// a snapshot with that sequence number is alive.
+    pub fn get_at_sequence(&mut self, seq: SequenceNumber, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.get_internal(seq, key)
+    }
+
     /// get is a simplified version of get_at(), translating errors to None.
     pub fn get(&mut self, key: &[u8]) -> Option<Vec<u8>> {
-        let seq = self.vset.borrow().last_seq;
+        let seq = self.latest_sequence_number();
         if let Ok(v) = self.get_internal(seq, key) {
             v
         } else {
@@ -499,14 +840,101 @@ impl DB {
         Ok(DBIterator::new(
             self.opt.cmp.clone(),
             self.vset.clone(),
-            self.merge_iterators()?,
+            self.merge_iterators(|v| v.new_iters())?,
+            ss,
+        ))
+    }
+
+    /// new_iter_with_bounds returns a DBIterator over the current state of the database that only
+    /// yields keys >= `lower` and < `upper`. Table files outside of that range are not opened.
+    pub fn new_iter_with_bounds(
+        &mut self,
+        lower: Option<&[u8]>,
+        upper: Option<&[u8]>,
+    ) -> Result<DBIterator> {
+        let ss = self.get_snapshot();
+        self.new_bounded_iter(
+            ss,
+            lower.map(Bound::Included).unwrap_or(Bound::Unbounded),
+            upper.map(Bound::Excluded).unwrap_or(Bound::Unbounded),
+        )
+    }
+
+    fn new_bounded_iter(
+        &mut self,
+        ss: Snapshot,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<DBIterator> {
+        let iter = self.merge_iterators(|v| v.new_range_iters(lower, upper))?;
+        Ok(DBIterator::new_bounded(
+            self.opt.cmp.clone(),
+            self.vset.clone(),
+            iter,
+            ss,
+            lower,
+            upper,
+        ))
+    }
+
+    /// range returns a double-ended std::iter::Iterator over the entries whose key is within
+    /// `bounds` (e.g. `(Bound::Included(&b"a"[..]), Bound::Excluded(&b"c"[..]))`), at the current
+    /// state of the database.
+    pub fn range<R: RangeBounds<[u8]>>(&mut self, bounds: R) -> Result<DBRange> {
+        let ss = self.get_snapshot();
+        let front = self.new_bounded_iter(ss.clone(), bounds.start_bound(), bounds.end_bound())?;
+        let back = self.new_bounded_iter(ss, bounds.start_bound(), bounds.end_bound())?;
+        Ok(DBRange::new(self.opt.cmp.clone(), front, back))
+    }
+
+    /// prefix returns a double-ended std::iter::Iterator over the entries whose key starts with
+    /// `prefix`. See new_prefix_iter().
+    pub fn prefix(&mut self, prefix: &[u8]) -> Result<DBRange> {
+        let ss = self.get_snapshot();
+        let front = self.new_prefix_iter_at(ss.clone(), prefix)?;
+        let back = self.new_prefix_iter_at(ss, prefix)?;
+        Ok(DBRange::new(self.opt.cmp.clone(), front, back))
+    }
+
+    /// new_prefix_iter returns a DBIterator over the entries whose key starts with `prefix`. If
+    /// `prefix` is a prefix produced by opt.prefix_extractor, tables and blocks that can't contain
+    /// such keys are skipped using their filters.
+    pub fn new_prefix_iter(&mut self, prefix: &[u8]) -> Result<DBIterator> {
+        let ss = self.get_snapshot();
+        self.new_prefix_iter_at(ss, prefix)
+    }
+
+    /// Returns whether the table filters can be used to skip tables without keys starting with
+    /// `prefix`.
+    fn use_prefix_filters(&self, prefix: &[u8]) -> bool {
+        match self.opt.prefix_extractor {
+            Some(ref pe) => pe.in_domain(prefix) && pe.transform(prefix) == prefix,
+            None => false,
+        }
+    }
+
+    fn new_prefix_iter_at(&mut self, ss: Snapshot, prefix: &[u8]) -> Result<DBIterator> {
+        let use_filters = self.use_prefix_filters(prefix);
+        let iter = if use_filters {
+            self.merge_iterators(|v| v.new_prefix_iters(prefix))?
+        } else {
+            self.merge_iterators(|v| v.new_iters())?
+        };
+        Ok(DBIterator::new_prefix(
+            self.opt.cmp.clone(),
+            self.vset.clone(),
+            iter,
             ss,
+            prefix,
         ))
     }
 
     /// merge_iterators produces a MergingIter merging the entries in the memtable, the immutable
-    /// memtable, and table files from all levels.
-    fn merge_iterators(&mut self) -> Result<MergingIter> {
+    /// memtable, and the table file iterators returned by `table_iters` for the current version.
+    fn merge_iterators<F>(&mut self, table_iters: F) -> Result<MergingIter>
+    where
+        F: FnOnce(&Version) -> Result<Vec<Box<dyn LdbIterator>>>,
+    {
         let mut iters: Vec<Box<dyn LdbIterator>> = vec![];
         if self.mem.len() > 0 {
             iters.push(Box::new(self.mem.iter()));
@@ -520,7 +948,7 @@ impl DB {
         // Add iterators for table files.
         let current = self.current();
         let current = current.borrow();
-        iters.extend(current.new_iters()?);
+        iters.extend(table_iters(&current)?);
 
         Ok(MergingIter::new(self.internal_cmp.clone(), iters))
     }
@@ -532,7 +960,24 @@ impl DB {
     /// Returns a snapshot at the current state. It can be used to retrieve entries from the
     /// database as they were at an earlier point in time.
     pub fn get_snapshot(&mut self) -> Snapshot {
-        self.snaps.new_snapshot(self.vset.borrow().last_seq)
+        let seq = self.latest_sequence_number();
+        self.snaps.new_snapshot(seq)
+    }
+
+    /// snapshots returns the sequence numbers of all live snapshots, oldest first.
+    pub fn snapshots(&self) -> Vec<SequenceNumber> {
+        self.snaps.sequences()
+    }
+}
+
+impl DB {
+    // TRANSACTIONS //
+
+    /// begin starts a new optimistic transaction, reading from a snapshot of the current state.
+    /// See Transaction for details.
+    pub fn begin(&mut self) -> Transaction {
+        let ss = self.get_snapshot();
+        Transaction::new(self.opt.cmp.clone(), ss)
     }
 }
 
@@ -543,6 +988,26 @@ impl DB {
         self.cstats[level].add(cs);
     }
 
+    /// get_property returns the value of a property describing the state of the database, or
+    /// None if the property is unknown. Supported properties are:
+    ///
+    /// * `leveldb.num-files-at-level<N>`: the number of tables at level N.
+    /// * `leveldb.io-stats`: I/O counters per file type, if `Options::io_stats` is set.
+    pub fn get_property(&self, name: &str) -> Option<String> {
+        const NUM_FILES_AT_LEVEL: &str = "leveldb.num-files-at-level";
+        if name.starts_with(NUM_FILES_AT_LEVEL) {
+            let level = name[NUM_FILES_AT_LEVEL.len()..].parse::<usize>().ok()?;
+            if level >= NUM_LEVELS {
+                return None;
+            }
+            return Some(self.current().borrow().num_level_files(level).to_string());
+        }
+        match name {
+            "leveldb.io-stats" => self.opt.io_stats.as_ref().map(|s| s.summary()),
+            _ => None,
+        }
+    }
+
     /// Trigger a compaction based on where this key is located in the different levels.
     fn record_read_sample<'a>(&mut self, k: InternalKey<'a>) {
         let current = self.current();
@@ -554,6 +1019,236 @@ impl DB {
     }
 }
 
+impl DB {
+    // STATE DIGEST //
+
+    /// state_digest returns a digest authenticating the live state of the database: the number,
+    /// size and content hash of every table, and the last sequence number. Writes still in the
+    /// memtable are first written to a table, so that the digest covers all data. The digest is
+    /// recorded in the manifest and verified by DB::open(), so it can be put into an attestation
+    /// report; see the state_digest module for how it is computed.
+    pub fn state_digest(&mut self) -> Result<StateDigest> {
+        if self.mem.len() > 0 {
+            self.check_writable()?;
+            self.make_room_for_write(true)?;
+        }
+        Ok(self.vset.borrow().state_digest())
+    }
+
+    /// Checks that the contents of every live table match the hash in the manifest.
+    fn verify_table_hashes(&self) -> Result<()> {
+        let current = self.current();
+        for level in 0..NUM_LEVELS {
+            for f in &current.borrow().files[level] {
+                let f = f.borrow();
+                let expected = match f.content_hash {
+                    Some(ref h) => h,
+                    None => continue,
+                };
+                let mut contents = Vec::with_capacity(f.size);
+                self.opt
+                    .env
+                    .open_sequential_file(Path::new(&table_file_name(&self.path, f.num)))?
+                    .read_to_end(&mut contents)?;
+                if table_hash(&contents) != *expected {
+                    return err(
+                        StatusCode::Corruption,
+                        &format!("contents of table {:06} don't match its hash", f.num),
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl DB {
+    // KEY ROTATION //
+
+    fn key_ring(&self) -> Result<KeyRing> {
+        match self.opt.key_ring {
+            Some(ref ring) => Ok(ring.clone()),
+            None => err(StatusCode::NotSupported, "the options contain no key ring"),
+        }
+    }
+
+    /// rotate_key adds key to the key ring as a new generation, and reseals the log, the manifest
+    /// and CURRENT with it. Tables are resealed when compactions rewrite them, or explicitly with
+    /// reseal_tables(); until then, the older keys must stay in the ring. Returns the generation
+    /// of the new key.
+    pub fn rotate_key(&mut self, key: DBPersistKey) -> Result<KeyGeneration> {
+        self.check_writable()?;
+        let gen = self.key_ring()?.add_key(key);
+        log!(self.opt.log, "Rotating to key generation {}", gen);
+        self.reseal_log_and_manifest()?;
+        Ok(gen)
+    }
+
+    /// Like rotate_key(), but the new generation is the master key of provider, from which the
+    /// key of every file is derived.
+    pub fn rotate_master_key(&mut self, provider: &BoxedKeyProvider) -> Result<KeyGeneration> {
+        self.check_writable()?;
+        let master = provider.master_key()?;
+        let gen = self.key_ring()?.add_master_key(&master);
+        log!(self.opt.log, "Rotating to master key generation {}", gen);
+        self.reseal_log_and_manifest()?;
+        Ok(gen)
+    }
+
+    /// Switches to a new log and a new manifest, which are encrypted with the current key.
+    fn reseal_log_and_manifest(&mut self) -> Result<()> {
+        if self.mem.len() > 0 {
+            // The memtable is written to a table, too.
+            self.make_room_for_write(true)?;
+        } else {
+            let logn = self.vset.borrow_mut().new_file_number();
+            let logf = self
+                .opt
+                .env
+                .open_writable_file(Path::new(&log_file_name(&self.path, logn)))?;
+            self.log = Some(LogWriter::new(BufWriter::new(logf)));
+            self.log_num = Some(logn);
+
+            let mut ve = VersionEdit::new();
+            ve.set_log_num(logn);
+            self.vset.borrow_mut().log_and_apply(ve)?;
+        }
+        self.vset.borrow_mut().write_new_manifest()?;
+        self.delete_obsolete_files()
+    }
+
+    /// Returns the live tables not encrypted with the key of generation gen.
+    fn tables_not_using(&self, gen: KeyGeneration) -> Vec<(usize, FileMetaData)> {
+        let current = self.current();
+        let current = current.borrow();
+        let mut tables = vec![];
+        for level in 0..NUM_LEVELS {
+            for f in &current.files[level] {
+                if f.borrow().key_gen != gen {
+                    tables.push((level, f.borrow().clone()));
+                }
+            }
+        }
+        tables
+    }
+
+    /// Returns the number of live tables that are not encrypted with the current key.
+    pub fn tables_with_old_keys(&self) -> Result<usize> {
+        let gen = self.key_ring()?.current_generation();
+        Ok(self.tables_not_using(gen).len())
+    }
+
+    /// reseal_tables rewrites up to max tables that are encrypted with older keys under the
+    /// current key, and returns the number of such tables remaining. Calling it repeatedly with a
+    /// small max spreads the work of a key rotation over time.
+    ///
+    /// Tables in level 0 may overlap, and their file numbers tell which of them is newer. As a
+    /// resealed table gets a new number, all level 0 tables are resealed at once, in the order of
+    /// their numbers, whenever one of them needs it; this may exceed max.
+    pub fn reseal_tables(&mut self, max: usize) -> Result<usize> {
+        self.check_writable()?;
+        let gen = self.key_ring()?.current_generation();
+        let tables = self.tables_not_using(gen);
+        let mut budget = max;
+
+        if max > 0 && tables.iter().any(|&(level, _)| level == 0) {
+            let mut level0: Vec<FileMetaData> = self.current().borrow().files[0]
+                .iter()
+                .map(|f| f.borrow().clone())
+                .collect();
+            level0.sort_by_key(|f| f.num);
+            let mut ve = VersionEdit::new();
+            let mut written = vec![];
+            for f in level0.iter() {
+                match self.reseal_table(f, gen) {
+                    Ok(resealed) => {
+                        written.push(resealed.num);
+                        ve.delete_file(0, f.num);
+                        ve.add_file(0, resealed);
+                    }
+                    Err(e) => {
+                        for num in written {
+                            let _ = self
+                                .opt
+                                .env
+                                .delete(Path::new(&table_file_name(&self.path, num)));
+                        }
+                        return Err(e);
+                    }
+                }
+            }
+            self.vset.borrow_mut().log_and_apply(ve)?;
+            let stale = tables.iter().filter(|&&(level, _)| level == 0).count();
+            budget = budget.saturating_sub(stale);
+        }
+
+        for (level, f) in tables.iter().filter(|&&(level, _)| level > 0).take(budget) {
+            let resealed = self.reseal_table(f, gen)?;
+            let mut ve = VersionEdit::new();
+            ve.delete_file(*level, f.num);
+            ve.add_file(*level, resealed);
+            self.vset.borrow_mut().log_and_apply(ve)?;
+        }
+        self.delete_obsolete_files()?;
+        Ok(self.tables_not_using(gen).len())
+    }
+
+    /// reseal_table copies table f to a new file encrypted with key generation gen, and returns
+    /// the new file's metadata. The version is not changed.
+    fn reseal_table(&mut self, f: &FileMetaData, gen: KeyGeneration) -> Result<FileMetaData> {
+        let mut contents = Vec::with_capacity(f.size);
+        self.opt
+            .env
+            .open_sequential_file(Path::new(&table_file_name(&self.path, f.num)))?
+            .read_to_end(&mut contents)?;
+
+        let num = self.vset.borrow_mut().new_file_number();
+        let filename = table_file_name(&self.path, num);
+        let r = (|| -> Result<()> {
+            let mut w = open_table_file(&self.opt, Path::new(&filename))?;
+            w.write_all(&contents)?;
+            w.flush()?;
+            Ok(())
+        })();
+        if let Err(e) = r {
+            let _ = self.opt.env.delete(Path::new(&filename));
+            return Err(e);
+        }
+
+        log!(
+            self.opt.log,
+            "Resealed table {:06} as {:06} with key generation {}",
+            f.num,
+            num,
+            gen
+        );
+        let mut resealed = f.clone();
+        resealed.num = num;
+        resealed.key_gen = gen;
+        Ok(resealed)
+    }
+
+    /// retire_old_keys removes all keys older than the current one from the key ring. It fails if
+    /// live tables are still encrypted with an older key. Logs kept for get_updates_since() and
+    /// an old info log remain encrypted with the older keys, and can't be read anymore.
+    pub fn retire_old_keys(&mut self) -> Result<()> {
+        self.check_writable()?;
+        let ring = self.key_ring()?;
+        let gen = ring.current_generation();
+        let remaining = self.tables_not_using(gen).len();
+        if remaining > 0 {
+            return err(
+                StatusCode::InvalidArgument,
+                &format!("{} tables are still encrypted with older keys", remaining),
+            );
+        }
+        // The log and manifest may have been reused from before the rotation.
+        self.reseal_log_and_manifest()?;
+        ring.remove_keys_before(gen);
+        Ok(())
+    }
+}
+
 impl DB {
     // COMPACTIONS //
 
@@ -586,9 +1281,12 @@ impl DB {
         }
     }
 
-    /// maybe_do_compaction starts a blocking compaction if it makes sense.
+    /// maybe_do_compaction starts a blocking compaction if it makes sense. Read-only databases are
+    /// never compacted.
     fn maybe_do_compaction(&mut self) -> Result<()> {
-        if self.imm.is_some() {
+        if self.read_only {
+            Ok(())
+        } else if self.imm.is_some() {
             self.compact_memtable()
         } else if self.vset.borrow().needs_compaction() {
             let c = self.vset.borrow_mut().pick_compaction();
@@ -608,6 +1306,7 @@ impl DB {
     /// Compactions in general will cause the database to find entries more quickly, and take up
     /// less space on disk.
     pub fn compact_range(&mut self, from: &[u8], to: &[u8]) -> Result<()> {
+        self.check_writable()?;
         let mut max_level = 1;
         {
             let v = self.vset.borrow().current();
@@ -680,15 +1379,12 @@ impl DB {
                 Ok(())
             }
         } else {
-            let smallest = if self.snaps.empty() {
-                self.vset.borrow().last_seq
-            } else {
-                self.snaps.oldest()
-            };
-            let mut state = CompactionState::new(compaction, smallest);
+            let mut state = self.new_compaction_state(compaction);
             if let Err(e) = self.do_compaction_work(&mut state) {
                 state.cleanup(&self.opt.env, &self.path);
                 log!(self.opt.log, "Compaction work failed: {}", e);
+                // The inputs must stay in place, as there are no outputs replacing them.
+                return Err(e);
             }
             self.install_compaction_results(state)?;
             log!(
@@ -701,6 +1397,17 @@ impl DB {
         }
     }
 
+    /// new_compaction_state returns the state for compaction c, retaining the versions needed by
+    /// all live snapshots.
+    fn new_compaction_state(&self, c: Compaction) -> CompactionState {
+        // Every snapshot, and the current state of the database, needs the newest version of each
+        // key at or below its sequence number.
+        let mut snapshots = self.snaps.sequences();
+        snapshots.push(self.vset.borrow().last_seq);
+        snapshots.dedup();
+        CompactionState::new(c, snapshots)
+    }
+
     fn compact_memtable(&mut self) -> Result<()> {
         assert!(self.imm.is_some());
 
@@ -792,7 +1499,8 @@ impl DB {
         input.seek_to_first();
 
         let (mut key, mut val) = (vec![], vec![]);
-        let mut last_seq_for_key = MAX_SEQUENCE_NUMBER;
+        // The snapshot stripe of the previous (newer) entry for the current key.
+        let mut last_stripe_for_key = None;
 
         let mut have_ukey = false;
         let mut current_ukey = vec![];
@@ -808,7 +1516,7 @@ impl DB {
             if seq == 0 {
                 // Parsing failed.
                 log!(self.opt.log, "Encountered seq=0 in key: {:?}", &key);
-                last_seq_for_key = MAX_SEQUENCE_NUMBER;
+                last_stripe_for_key = None;
                 have_ukey = false;
                 current_ukey.clear();
                 input.advance();
@@ -820,35 +1528,35 @@ impl DB {
                 current_ukey.clear();
                 current_ukey.extend_from_slice(ukey);
                 have_ukey = true;
-                last_seq_for_key = MAX_SEQUENCE_NUMBER;
+                last_stripe_for_key = None;
             }
 
             // We can omit the key under the following conditions:
-            if last_seq_for_key <= cs.smallest_seq {
-                last_seq_for_key = seq;
+            // A newer entry for the same key is visible to every snapshot that can see this one.
+            let stripe = cs.stripe(seq);
+            if last_stripe_for_key == Some(stripe) {
                 input.advance();
                 continue;
             }
-            // Entry is deletion; no older version is observable by any snapshot; and all entries
-            // in compacted levels with smaller sequence numbers will
+            last_stripe_for_key = Some(stripe);
+            // Entry is deletion; no older version is observable by any snapshot; and there are no
+            // entries for the key in higher levels that the deletion would have to hide.
             if ktyp == ValueType::TypeDeletion
-                && seq <= cs.smallest_seq
+                && stripe == 0
                 && cs.compaction.is_base_level_for(ukey)
             {
-                last_seq_for_key = seq;
                 input.advance();
                 continue;
             }
 
-            last_seq_for_key = seq;
-
             if cs.builder.is_none() {
                 let fnum = self.vset.borrow_mut().new_file_number();
                 let mut fmd = FileMetaData::default();
                 fmd.num = fnum;
+                fmd.key_gen = current_key_generation(&self.opt);
 
                 let fname = table_file_name(&self.path, fnum);
-                let f = self.opt.env.open_writable_file(Path::new(&fname))?;
+                let f = open_table_file(&self.opt, Path::new(&fname))?;
                 let f = Box::new(BufWriter::new(f));
                 cs.builder = Some(TableBuilder::new(self.opt.clone(), f));
                 cs.outputs.push(fmd);
@@ -897,11 +1605,12 @@ impl DB {
         // (it's not good for corruptions, in any case)
         let b = cs.builder.take().unwrap();
         let entries = b.entries();
-        let bytes = b.finish()?;
+        let (bytes, hash) = b.finish_with_hash()?;
         cs.total_bytes += bytes;
 
         cs.current_output().largest = largest;
         cs.current_output().size = bytes;
+        cs.current_output().content_hash = Some(hash);
 
         if entries > 0 {
             // Verify that table can be used. (Separating get_table() because borrowing in an if
@@ -949,25 +1658,45 @@ impl Drop for DB {
     }
 }
 
+/// ReplayedLogs describes the log files a read-only database has replayed into its memtable: all
+/// from first up to offset bytes into last.
+#[derive(Clone, Copy)]
+struct ReplayedLogs {
+    first: FileNum,
+    last: FileNum,
+    offset: usize,
+}
+
 struct CompactionState {
     compaction: Compaction,
-    smallest_seq: SequenceNumber,
+    // The sequence numbers of all live snapshots and of the latest write, in ascending order and
+    // without duplicates.
+    snapshots: Vec<SequenceNumber>,
     outputs: Vec<FileMetaData>,
     builder: Option<TableBuilder<Box<dyn Write>>>,
     total_bytes: usize,
 }
 
 impl CompactionState {
-    fn new(c: Compaction, smallest: SequenceNumber) -> CompactionState {
+    fn new(c: Compaction, snapshots: Vec<SequenceNumber>) -> CompactionState {
         CompactionState {
             compaction: c,
-            smallest_seq: smallest,
+            snapshots,
             outputs: vec![],
             builder: None,
             total_bytes: 0,
         }
     }
 
+    /// stripe returns the index of the oldest snapshot that can see an entry with sequence number
+    /// seq. Of several entries for a key in the same stripe, only the newest one is visible.
+    fn stripe(&self, seq: SequenceNumber) -> usize {
+        match self.snapshots.binary_search(&seq) {
+            Ok(i) => i,
+            Err(i) => i,
+        }
+    }
+
     fn current_output(&mut self) -> &mut FileMetaData {
         let len = self.outputs.len();
         &mut self.outputs[len - 1]
@@ -997,6 +1726,16 @@ impl CompactionStats {
     }
 }
 
+/// open_table_file opens a new table file for writing, limiting the write rate if opt contains a
+/// rate limiter.
+fn open_table_file(opt: &Options, path: &Path) -> Result<Box<dyn Write>> {
+    let f = opt.env.open_writable_file(path)?;
+    match opt.rate_limiter {
+        Some(ref rl) => Ok(rl.wrap(opt.env.clone(), f)),
+        None => Ok(f),
+    }
+}
+
 pub fn build_table<I: LdbIterator, P: AsRef<Path>>(
     dbname: P,
     opt: &Options,
@@ -1013,8 +1752,8 @@ pub fn build_table<I: LdbIterator, P: AsRef<Path>>(
     // Clean up file if write fails at any point.
     //
     // TODO: Replace with catch {} when available.
-    let r = (|| -> Result<()> {
-        let f = opt.env.open_writable_file(Path::new(&filename))?;
+    let r = (|| -> Result<TableHash> {
+        let f = open_table_file(opt, Path::new(&filename))?;
         let f = BufWriter::new(f);
         let mut builder = TableBuilder::new(opt.clone(), f);
         while from.advance() {
@@ -1024,14 +1763,17 @@ pub fn build_table<I: LdbIterator, P: AsRef<Path>>(
             }
             builder.add(&kbuf, &vbuf)?;
         }
-        builder.finish()?;
-        Ok(())
+        let (_, hash) = builder.finish_with_hash()?;
+        Ok(hash)
     })();
 
-    if let Err(e) = r {
-        let _ = opt.env.delete(Path::new(&filename));
-        return Err(e);
-    }
+    let hash = match r {
+        Ok(hash) => hash,
+        Err(e) => {
+            let _ = opt.env.delete(Path::new(&filename));
+            return Err(e);
+        }
+    };
 
     let mut md = FileMetaData::default();
     if firstkey.is_none() {
@@ -1041,11 +1783,21 @@ pub fn build_table<I: LdbIterator, P: AsRef<Path>>(
         md.size = opt.env.size_of(Path::new(&filename))?;
         md.smallest = firstkey.unwrap();
         md.largest = kbuf;
+        md.key_gen = current_key_generation(opt);
+        md.content_hash = Some(hash);
     }
     Ok(md)
 }
 
-fn log_file_name(db: &Path, num: FileNum) -> PathBuf {
+/// Returns the generation of the key that new files are encrypted with.
+fn current_key_generation(opt: &Options) -> KeyGeneration {
+    opt.key_ring
+        .as_ref()
+        .map(|r| r.current_generation())
+        .unwrap_or(0)
+}
+
+pub fn log_file_name(db: &Path, num: FileNum) -> PathBuf {
     db.join(format!("{:06}.log", num))
 }
 
@@ -1135,12 +1887,19 @@ pub mod tests {
     use super::testutil::{build_db, set_file_to_compact};
     use super::*;
 
+    use crate::counting_env::{CountingEnv, IoFileType};
     use crate::error::Status;
+    use crate::key_provider::{StaticKeyProvider, TestKeyProvider};
     use crate::key_types::LookupKey;
     use crate::mem_env::MemEnv;
+    use crate::monotonic_counter::{MemMonotonicCounter, MonotonicCounter};
     use crate::options;
+    use crate::slice_transform::FixedPrefixTransform;
+    use crate::state_digest::state_digest;
     use crate::test_util::LdbIteratorIter;
+    use crate::types::current_key_val;
     use crate::version::testutil::make_version;
+    use std::collections::BTreeMap;
     use teaclave_test_utils::*;
 
     pub fn run_tests() -> bool {
@@ -1153,6 +1912,8 @@ pub mod tests {
             test_db_impl_build_table,
             test_db_impl_build_db_sanity,
             test_db_impl_get_from_table_with_snapshot,
+            test_db_impl_multi_get,
+            test_db_impl_snapshots,
             test_db_impl_delete,
             test_db_impl_compact_single_file,
             test_db_impl_compaction_trivial_move,
@@ -1160,7 +1921,18 @@ pub mod tests {
             test_db_impl_compaction,
             test_db_impl_compaction_trivial,
             test_db_impl_compaction_state_cleanup,
+            test_db_impl_compaction_snapshot_retention,
+            test_db_impl_compaction_snapshot_model,
             test_db_impl_open_close_reopen,
+            test_db_impl_open_read_only,
+            test_db_impl_secondary,
+            test_db_impl_prefix_iter,
+            test_db_impl_get_property,
+            test_db_impl_key_rotation,
+            test_db_impl_reseal_level0,
+            test_db_impl_per_file_keys,
+            test_db_impl_rollback_protection,
+            test_db_impl_state_digest,
         )
     }
 
@@ -1464,6 +2236,100 @@ pub mod tests {
         assert!(env.exists(Path::new(&log_file_name(name, 12))).unwrap());
     }
 
+    fn test_db_impl_multi_get() {
+        let mut db = build_db().0;
+        let old_ss = db.get_snapshot();
+        db.put(b"xyz", b"123").unwrap();
+        db.put(b"aab", b"new").unwrap();
+        db.delete(b"eab").unwrap();
+
+        // Unsorted keys, duplicates and keys from memtable and tables.
+        let keys: &[&[u8]] = &[
+            b"xyz", b"gca", b"eab", b"aab", b"iba", b"aaa", b"zzz", b"aab",
+        ];
+        let vals: Vec<Option<Vec<u8>>> = db
+            .multi_get(keys, None)
+            .into_iter()
+            .map(|r| r.unwrap())
+            .collect();
+        let expected: Vec<Option<Vec<u8>>> = keys.iter().map(|k| db.get(k)).collect();
+        assert_eq!(expected, vals);
+        assert_eq!(Some(b"new".to_vec()), vals[3]);
+        assert_eq!(None, vals[2]);
+
+        // At the old snapshot, the new writes are invisible.
+        let vals: Vec<Option<Vec<u8>>> = db
+            .multi_get(keys, Some(&old_ss))
+            .into_iter()
+            .map(|r| r.unwrap())
+            .collect();
+        let expected: Vec<Option<Vec<u8>>> = keys
+            .iter()
+            .map(|k| db.get_at(&old_ss, k).unwrap())
+            .collect();
+        assert_eq!(expected, vals);
+        assert_eq!(None, vals[0]);
+        assert_eq!(Some(b"val2".to_vec()), vals[2]);
+
+        // After flushing, everything is read from tables.
+        db.flush().unwrap();
+        let vals: Vec<Option<Vec<u8>>> = db
+            .multi_get(keys, None)
+            .into_iter()
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(Some(b"123".to_vec()), vals[0]);
+        assert_eq!(Some(b"new".to_vec()), vals[7]);
+        assert!(db.multi_get(&[], None).is_empty());
+    }
+
+    fn test_db_impl_snapshots() {
+        let mut db = build_db().0;
+        assert_eq!(30, db.latest_sequence_number());
+        assert!(db.snapshots().is_empty());
+
+        let ss30 = db.get_snapshot();
+        // seq = 31, 32
+        db.put(b"aaa", b"new").unwrap();
+        db.delete(b"aab").unwrap();
+        let ss32 = db.get_snapshot();
+        let ss32_2 = db.get_snapshot();
+        assert_eq!(32, db.latest_sequence_number());
+        assert_eq!(32, ss32.sequence());
+        assert_eq!(
+            db.get(b"aaa"),
+            db.get_at_sequence(db.latest_sequence_number(), b"aaa")
+                .unwrap()
+        );
+        assert_eq!(vec![30, 32, 32], db.snapshots());
+
+        assert_eq!(
+            Some(b"val1".to_vec()),
+            db.get_at_sequence(30, b"aaa").unwrap()
+        );
+        assert_eq!(
+            Some(b"new".to_vec()),
+            db.get_at_sequence(31, b"aaa").unwrap()
+        );
+        assert_eq!(
+            Some(b"val2".to_vec()),
+            db.get_at_sequence(31, b"aab").unwrap()
+        );
+        assert_eq!(None, db.get_at_sequence(32, b"aab").unwrap());
+        assert_eq!(
+            db.get_at(&ss30, b"aab").unwrap(),
+            db.get_at_sequence(30, b"aab").unwrap()
+        );
+
+        drop(ss30);
+        assert_eq!(vec![32, 32], db.snapshots());
+        assert_eq!(32, db.snaps.oldest());
+        drop(ss32);
+        drop(ss32_2);
+        assert!(db.snapshots().is_empty());
+        assert!(db.snaps.empty());
+    }
+
     fn test_db_impl_get_from_table_with_snapshot() {
         let mut db = build_db().0;
 
@@ -1653,7 +2519,7 @@ pub mod tests {
         let mut fmd = FileMetaData::default();
         fmd.num = 1;
 
-        let mut cs = CompactionState::new(Compaction::new(&options::for_test(), 2, None), 12);
+        let mut cs = CompactionState::new(Compaction::new(&options::for_test(), 2, None), vec![12]);
         cs.outputs = vec![fmd];
         cs.cleanup(&env, name);
 
@@ -1704,4 +2570,780 @@ pub mod tests {
             assert_eq!(None, db.get_at(&ss, b"xx2").unwrap());
         }
     }
+
+    fn test_db_impl_prefix_iter() {
+        let mut opt = options::for_test();
+        opt.block_size = 64;
+        opt.prefix_extractor = Some(FixedPrefixTransform::new(2));
+        let mut db = DB::open("prefixdb", opt).unwrap();
+
+        // The keys that are live in the end.
+        let mut live = vec![];
+        for p in &["aa", "ab", "ac", "ba"] {
+            for i in 0..20 {
+                let k = format!("{}{:03}", p, i);
+                db.put(k.as_bytes(), p.as_bytes()).unwrap();
+                live.push(k);
+            }
+        }
+        db.delete(b"ab005").unwrap();
+        live.retain(|k| k != "ab005");
+        db.make_room_for_write(true).unwrap();
+        db.put(b"ab100", b"ab").unwrap();
+        live.push("ab100".to_string());
+
+        {
+            let mut iter = db.new_prefix_iter(b"ab").unwrap();
+            let entries: Vec<(Vec<u8>, Vec<u8>)> = LdbIteratorIter::wrap(&mut iter).collect();
+            assert_eq!(20, entries.len());
+            assert!(entries
+                .iter()
+                .all(|(k, v)| k.starts_with(b"ab") && v.as_slice() == b"ab"));
+            assert!(!entries.iter().any(|(k, _)| k.as_slice() == b"ab005"));
+
+            iter.seek(b"ab100");
+            assert!(iter.prev());
+            assert_eq!(b"ab019".to_vec(), current_key_val(&iter).unwrap().0);
+        }
+        {
+            // A prefix shorter than the extractor's can't use the filters, but still works.
+            let mut iter = db.new_prefix_iter(b"a").unwrap();
+            assert_eq!(
+                live.iter().filter(|k| k.starts_with("a")).count(),
+                LdbIteratorIter::wrap(&mut iter).count()
+            );
+            // A prefix without entries.
+            let mut iter = db.new_prefix_iter(b"ad").unwrap();
+            assert_eq!(0, LdbIteratorIter::wrap(&mut iter).count());
+        }
+
+        let v = db.current();
+        let f = v
+            .borrow()
+            .files
+            .iter()
+            .flat_map(|fs| fs.iter())
+            .next()
+            .unwrap()
+            .clone();
+        let tbl = db.cache.borrow_mut().get_table(f.borrow().num).unwrap();
+        assert!(tbl.prefix_may_match(b"ab"));
+        assert!(!tbl.prefix_may_match(b"ad"));
+    }
+
+    /// Compacts all table files of db, which must be in the same level, into the next level. The
+    /// compaction is run even if it could be a trivial move.
+    fn compact_all(db: &mut DB) {
+        let level = {
+            let v = db.current();
+            let v = v.borrow();
+            let levels: Vec<usize> = (0..NUM_LEVELS)
+                .filter(|l| !v.files[*l].is_empty())
+                .collect();
+            assert_eq!(1, levels.len());
+            levels[0]
+        };
+        let from = LookupKey::new(b"", MAX_SEQUENCE_NUMBER);
+        let to = LookupKey::new_full(b"\xff\xff\xff", 0, ValueType::TypeDeletion);
+        let c = db
+            .vset
+            .borrow_mut()
+            .compact_range(level, from.internal_key(), to.internal_key())
+            .unwrap();
+        let mut state = db.new_compaction_state(c);
+        db.do_compaction_work(&mut state).unwrap();
+        db.install_compaction_results(state).unwrap();
+    }
+
+    /// Returns all entries of db as (user key, sequence number, type), in internal key order.
+    fn internal_entries(db: &mut DB) -> Vec<(Vec<u8>, SequenceNumber, ValueType)> {
+        let mut iter = db.merge_iterators(|v| v.new_iters()).unwrap();
+        let mut entries = vec![];
+        iter.seek_to_first();
+        while iter.valid() {
+            let (typ, seq, ukey) = parse_internal_key(iter.key());
+            entries.push((ukey.to_vec(), seq, typ));
+            iter.advance();
+        }
+        entries
+    }
+
+    fn test_db_impl_compaction_snapshot_retention() {
+        let mut db = DB::open("db", options::for_test()).unwrap();
+        // seq = 1..=4
+        db.put(b"a", b"1").unwrap();
+        db.put(b"d", b"1").unwrap();
+        db.put(b"a", b"2").unwrap();
+        db.delete(b"d").unwrap();
+        let ss4 = db.get_snapshot();
+        // seq = 5..=7
+        db.put(b"a", b"3").unwrap();
+        db.delete(b"b").unwrap();
+        db.put(b"a", b"4").unwrap();
+        let ss7 = db.get_snapshot();
+        // seq = 8..=10
+        db.put(b"a", b"5").unwrap();
+        db.put(b"c", b"1").unwrap();
+        db.delete(b"c").unwrap();
+
+        db.make_room_for_write(true).unwrap();
+        assert_eq!(10, internal_entries(&mut db).len());
+        compact_all(&mut db);
+
+        // For each snapshot, only the newest version at or below it is kept. The deletion of d
+        // isn't needed by any snapshot.
+        assert_eq!(
+            vec![
+                (b"a".to_vec(), 8, ValueType::TypeValue),
+                (b"a".to_vec(), 7, ValueType::TypeValue),
+                (b"a".to_vec(), 3, ValueType::TypeValue),
+                (b"b".to_vec(), 6, ValueType::TypeDeletion),
+                (b"c".to_vec(), 10, ValueType::TypeDeletion),
+            ],
+            internal_entries(&mut db)
+        );
+        assert_eq!(Some(b"2".to_vec()), db.get_at(&ss4, b"a").unwrap());
+        assert_eq!(Some(b"4".to_vec()), db.get_at(&ss7, b"a").unwrap());
+        assert_eq!(Some(b"5".to_vec()), db.get(b"a"));
+        assert_eq!(None, db.get_at(&ss4, b"d").unwrap());
+
+        // Once the snapshots are released, older versions and deletions are dropped.
+        drop(ss4);
+        drop(ss7);
+        compact_all(&mut db);
+        assert_eq!(
+            vec![(b"a".to_vec(), 8, ValueType::TypeValue)],
+            internal_entries(&mut db)
+        );
+    }
+
+    /// Model keeps the full history of every key, to check the database against.
+    struct Model {
+        history: BTreeMap<Vec<u8>, Vec<(SequenceNumber, Option<Vec<u8>>)>>,
+        snapshots: Vec<Snapshot>,
+        rng: u64,
+    }
+
+    impl Model {
+        fn new(seed: u64) -> Model {
+            Model {
+                history: BTreeMap::new(),
+                snapshots: vec![],
+                rng: seed,
+            }
+        }
+
+        fn random(&mut self, n: u64) -> u64 {
+            // xorshift64
+            self.rng ^= self.rng << 13;
+            self.rng ^= self.rng >> 7;
+            self.rng ^= self.rng << 17;
+            self.rng % n
+        }
+
+        fn random_key(&mut self) -> Vec<u8> {
+            format!("key{}", self.random(12)).into_bytes()
+        }
+
+        /// Applies a random operation to db and the model.
+        fn step(&mut self, db: &mut DB) {
+            match self.random(10) {
+                0..=3 => {
+                    let (k, v) = (self.random_key(), format!("v{}", self.random(1000)));
+                    db.put(&k, v.as_bytes()).unwrap();
+                    self.record(db.latest_sequence_number(), k, Some(v.into_bytes()));
+                }
+                4 | 5 => {
+                    let k = self.random_key();
+                    db.delete(&k).unwrap();
+                    self.record(db.latest_sequence_number(), k, None);
+                }
+                6 => {
+                    let mut wb = WriteBatch::new();
+                    let mut seq = db.latest_sequence_number();
+                    for i in 0..3 {
+                        let k = self.random_key();
+                        seq += 1;
+                        if i == 1 {
+                            wb.delete(&k);
+                            self.record(seq, k, None);
+                        } else {
+                            wb.put(&k, b"batch");
+                            self.record(seq, k, Some(b"batch".to_vec()));
+                        }
+                    }
+                    db.write(wb, false).unwrap();
+                    assert_eq!(seq, db.latest_sequence_number());
+                }
+                7 | 8 => self.snapshots.push(db.get_snapshot()),
+                _ => {
+                    if !self.snapshots.is_empty() {
+                        let i = self.random(self.snapshots.len() as u64) as usize;
+                        self.snapshots.remove(i);
+                    }
+                }
+            }
+        }
+
+        fn record(&mut self, seq: SequenceNumber, k: Vec<u8>, v: Option<Vec<u8>>) {
+            self.history
+                .entry(k)
+                .or_insert_with(Vec::new)
+                .push((seq, v));
+        }
+
+        /// Returns the newest version of each key at or below seq, as (seq, value).
+        fn versions_at(
+            &self,
+            seq: SequenceNumber,
+        ) -> Vec<(Vec<u8>, SequenceNumber, Option<Vec<u8>>)> {
+            let mut result = vec![];
+            for (k, h) in self.history.iter() {
+                if let Some((s, v)) = h.iter().rev().find(|(s, _)| *s <= seq) {
+                    result.push((k.clone(), *s, v.clone()));
+                }
+            }
+            result
+        }
+
+        fn contents_at(&self, seq: SequenceNumber) -> Vec<(Vec<u8>, Vec<u8>)> {
+            self.versions_at(seq)
+                .into_iter()
+                .filter_map(|(k, _, v)| v.map(|v| (k, v)))
+                .collect()
+        }
+
+        /// Checks that every live snapshot, and the current state, read as in the model.
+        fn check(&self, db: &mut DB) {
+            let mut seqs: Vec<SequenceNumber> =
+                self.snapshots.iter().map(|s| s.sequence()).collect();
+            seqs.push(db.latest_sequence_number());
+            for (i, seq) in seqs.into_iter().enumerate() {
+                let expected = self.contents_at(seq);
+                let mut iter = match self.snapshots.get(i) {
+                    Some(ss) => db.new_iter_at(ss.clone()).unwrap(),
+                    None => db.new_iter().unwrap(),
+                };
+                let contents: Vec<(Vec<u8>, Vec<u8>)> = LdbIteratorIter::wrap(&mut iter).collect();
+                assert_eq!(expected, contents);
+                for (k, _, v) in self.versions_at(seq) {
+                    assert_eq!(v, db.get_at_sequence(seq, &k).unwrap());
+                }
+            }
+        }
+
+        /// Returns the entries that a full compaction of everything into one level must produce:
+        /// the newest version of each key for every snapshot and for the current state, except
+        /// for deletions not needed by any snapshot.
+        fn retained_entries(
+            &self,
+            last_seq: SequenceNumber,
+        ) -> Vec<(Vec<u8>, SequenceNumber, ValueType)> {
+            let mut stripes: Vec<SequenceNumber> =
+                self.snapshots.iter().map(|s| s.sequence()).collect();
+            stripes.push(last_seq);
+            stripes.sort();
+            stripes.dedup();
+
+            let mut entries = vec![];
+            for (i, seq) in stripes.iter().enumerate() {
+                for (k, s, v) in self.versions_at(*seq) {
+                    if i > 0 && s <= stripes[i - 1] {
+                        // Already retained for an older snapshot.
+                        continue;
+                    }
+                    match v {
+                        Some(_) => entries.push((k, s, ValueType::TypeValue)),
+                        None if i > 0 => entries.push((k, s, ValueType::TypeDeletion)),
+                        None => (),
+                    }
+                }
+            }
+            entries.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+            entries
+        }
+    }
+
+    fn test_db_impl_compaction_snapshot_model() {
+        for seed in 1..6 {
+            // All writes end up in one level-0 file, which is compacted at once.
+            let mut db = DB::open("db", options::for_test()).unwrap();
+            let mut model = Model::new(seed * 7919);
+            for _ in 0..150 {
+                model.step(&mut db);
+            }
+            model.check(&mut db);
+            db.make_room_for_write(true).unwrap();
+            compact_all(&mut db);
+            model.check(&mut db);
+            let last_seq = db.latest_sequence_number();
+            assert_eq!(model.retained_entries(last_seq), internal_entries(&mut db));
+
+            // Releasing snapshots allows compactions to drop more entries.
+            model.snapshots.truncate(1);
+            compact_all(&mut db);
+            model.check(&mut db);
+            assert_eq!(model.retained_entries(last_seq), internal_entries(&mut db));
+        }
+
+        // With a small write buffer, compactions run while writing.
+        let mut opt = options::for_test();
+        opt.write_buffer_size = 512;
+        let mut db = DB::open("db", opt).unwrap();
+        let mut model = Model::new(4242);
+        for i in 0..1500 {
+            model.step(&mut db);
+            if i % 100 == 99 {
+                model.check(&mut db);
+                db.compact_range(b"key", b"key9").unwrap();
+                model.check(&mut db);
+            }
+        }
+    }
+
+    fn test_db_impl_get_property() {
+        let (db, _) = build_db();
+        let files: Vec<String> = (0..NUM_LEVELS)
+            .map(|l| {
+                db.get_property(&format!("leveldb.num-files-at-level{}", l))
+                    .unwrap()
+            })
+            .collect();
+        assert!(files.iter().any(|n| n != "0"));
+        assert_eq!(None, db.get_property("leveldb.num-files-at-level7"));
+        assert_eq!(None, db.get_property("leveldb.num-files-at-levelx"));
+        assert_eq!(None, db.get_property("leveldb.unknown"));
+        // Not set in the options.
+        assert_eq!(None, db.get_property("leveldb.io-stats"));
+    }
+
+    fn test_db_impl_key_rotation() {
+        let name = "key_rotation_db";
+        let key = |i| format!("key{:03}", i).into_bytes();
+        let open = |ring: KeyRing| {
+            let mut opt = Options::new_disk_db_with_key_ring(ring);
+            opt.write_buffer_size = 2048;
+            DB::open(name, opt)
+        };
+        let _ = Options::new_disk_db_with([1; 16])
+            .env
+            .rmdir(Path::new(name));
+
+        {
+            let mut db = open(KeyRing::new([1; 16])).unwrap();
+            for i in 0..200 {
+                db.put(&key(i), b"value").unwrap();
+            }
+            assert!(db.tables_with_old_keys().unwrap() == 0);
+            assert_eq!(1, db.rotate_key([2; 16]).unwrap());
+            let old = db.tables_with_old_keys().unwrap();
+            assert!(old > 0);
+            assert!(db.retire_old_keys().is_err());
+            let left = db.reseal_tables(1).unwrap();
+            assert!(left < old);
+            assert_eq!(left, db.tables_with_old_keys().unwrap());
+            assert_eq!(Some(b"value".to_vec()), db.get(&key(10)));
+        }
+
+        // A partially rotated database opens with both keys.
+        {
+            let ring = KeyRing::new([1; 16]);
+            ring.add_key([2; 16]);
+            let mut db = open(ring.clone()).unwrap();
+            for i in 0..200 {
+                assert_eq!(Some(b"value".to_vec()), db.get(&key(i)));
+            }
+            while db.reseal_tables(2).unwrap() > 0 {}
+            assert_eq!(0, db.tables_with_old_keys().unwrap());
+            db.retire_old_keys().unwrap();
+            assert_eq!(vec![1], ring.generations());
+            db.put(b"after", b"rotation").unwrap();
+        }
+
+        {
+            let mut db = open(KeyRing::with_generation(1, [2; 16])).unwrap();
+            for i in 0..200 {
+                assert_eq!(Some(b"value".to_vec()), db.get(&key(i)));
+            }
+            assert_eq!(Some(b"rotation".to_vec()), db.get(b"after"));
+        }
+        // Without a key ring, keys can't be rotated.
+        let mut db = DB::open("db", options::for_test()).unwrap();
+        assert_eq!(
+            StatusCode::NotSupported,
+            db.rotate_key([3; 16]).unwrap_err().code
+        );
+        Options::new_disk_db_with([2; 16])
+            .env
+            .rmdir(Path::new(name))
+            .unwrap();
+    }
+
+    fn test_db_impl_reseal_level0() {
+        let name = "reseal_level0_db";
+        let _ = Options::new_disk_db_with([1; 16])
+            .env
+            .rmdir(Path::new(name));
+
+        {
+            let opt = Options::new_disk_db_with_key_ring(KeyRing::new([1; 16]));
+            let mut db = DB::open(name, opt).unwrap();
+            // Every version of k goes to a table of its own. The first two are placed in levels 2
+            // and 1, the following ones overlap them and stay in level 0.
+            for v in &[&b"v0"[..], b"v1", b"v2"] {
+                db.put(b"k", v).unwrap();
+                db.make_room_for_write(true).unwrap();
+            }
+            db.rotate_key([2; 16]).unwrap();
+            db.put(b"k", b"v3").unwrap();
+            db.make_room_for_write(true).unwrap();
+            assert_eq!(2, db.current().borrow().num_level_files(0));
+            assert_eq!(Some(b"v3".to_vec()), db.get(b"k"));
+
+            // Only the level 0 table holding v2 uses the old key. Resealing it must not make it
+            // newer than the table holding v3.
+            db.reseal_tables(1).unwrap();
+            assert_eq!(Some(b"v3".to_vec()), db.get(b"k"));
+            while db.reseal_tables(1).unwrap() > 0 {}
+            assert_eq!(0, db.tables_with_old_keys().unwrap());
+            assert_eq!(Some(b"v3".to_vec()), db.get(b"k"));
+        }
+        Options::new_disk_db_with([2; 16])
+            .env
+            .rmdir(Path::new(name))
+            .unwrap();
+    }
+
+    fn test_db_impl_per_file_keys() {
+        let name = "per_file_keys_db";
+        let provider = TestKeyProvider::new(b"master key");
+        let _ = Options::new_disk_db_with([1; 16])
+            .env
+            .rmdir(Path::new(name));
+
+        {
+            let mut opt =
+                Options::new_disk_db_with_provider(&provider.boxed(), name.as_bytes()).unwrap();
+            opt.write_buffer_size = 2048;
+            let mut db = DB::open(name, opt).unwrap();
+            for i in 0..100 {
+                db.put(format!("key{:03}", i).as_bytes(), b"value").unwrap();
+            }
+            db.flush().unwrap();
+            assert_eq!(
+                1,
+                db.rotate_master_key(&StaticKeyProvider::new(b"new master"))
+                    .unwrap()
+            );
+            while db.reseal_tables(10).unwrap() > 0 {}
+            db.retire_old_keys().unwrap();
+        }
+        assert_eq!(1, provider.requests());
+
+        // A failing provider prevents opening the database.
+        provider.set_failing(true);
+        assert!(Options::new_disk_db_with_provider(&provider.boxed(), name.as_bytes()).is_err());
+
+        {
+            let ring = |db_id: &[u8]| KeyRing::with_master_key(1, b"new master", db_id);
+            // The file keys depend on the database identifier.
+            assert!(DB::open(name, Options::new_disk_db_with_key_ring(ring(b"other"))).is_err());
+            let opt = Options::new_disk_db_with_key_ring(ring(name.as_bytes()));
+            let mut db = DB::open(name, opt).unwrap();
+            assert_eq!(Some(b"value".to_vec()), db.get(b"key042"));
+        }
+        Options::new_disk_db_with([1; 16])
+            .env
+            .rmdir(Path::new(name))
+            .unwrap();
+    }
+
+    /// Returns the names and contents of all files in dir.
+    fn save_files(env: &dyn Env, dir: &Path) -> Vec<(PathBuf, Vec<u8>)> {
+        let mut files = vec![];
+        for name in env.children(dir).unwrap() {
+            let mut contents = vec![];
+            env.open_sequential_file(&dir.join(&name))
+                .unwrap()
+                .read_to_end(&mut contents)
+                .unwrap();
+            files.push((name, contents));
+        }
+        files
+    }
+
+    /// Replaces all files in dir with files.
+    fn restore_files(env: &dyn Env, dir: &Path, files: &[(PathBuf, Vec<u8>)]) {
+        for name in env.children(dir).unwrap() {
+            env.delete(&dir.join(&name)).unwrap();
+        }
+        for (name, contents) in files {
+            env.open_writable_file(&dir.join(name))
+                .unwrap()
+                .write_all(contents)
+                .unwrap();
+        }
+    }
+
+    fn test_db_impl_rollback_protection() {
+        let env = MemEnv::new();
+        let dir = Path::new("db");
+        let counter = MemMonotonicCounter::new();
+        let mut opt = options::for_test();
+        opt.env = Rc::new(Box::new(env.clone()));
+        opt.monotonic_counter = Some(counter.boxed());
+
+        let old;
+        {
+            let mut db = DB::open("db", opt.clone()).unwrap();
+            assert!(counter.read().unwrap() > 0);
+            db.put(b"a", b"1").unwrap();
+            db.compact_range(b"a", b"z").unwrap();
+            old = save_files(&env, dir);
+            let before = counter.read().unwrap();
+            db.put(b"b", b"2").unwrap();
+            db.compact_range(b"a", b"z").unwrap();
+            assert!(counter.read().unwrap() > before);
+        }
+        let new = save_files(&env, dir);
+        let at_new = counter.read().unwrap();
+
+        // The newest state opens; an older one doesn't.
+        assert!(DB::open("db", opt.clone()).is_ok());
+        restore_files(&env, dir, &old);
+        assert_eq!(
+            StatusCode::Rollback,
+            DB::open("db", opt.clone()).err().unwrap().code
+        );
+
+        // A manifest one ahead of the counter is left by a crash before the counter was
+        // incremented; the counter catches up.
+        restore_files(&env, dir, &new);
+        let lagging = MemMonotonicCounter::new();
+        while lagging.read().unwrap() + 1 < at_new {
+            lagging.increment().unwrap();
+        }
+        let mut opt2 = opt.clone();
+        opt2.monotonic_counter = Some(lagging.boxed());
+        // Read-only instances leave the counter alone.
+        {
+            let mut ro = DB::open_read_only("db", opt2.clone()).unwrap();
+            assert_eq!(Some(b"2".to_vec()), ro.get(b"b"));
+            ro.try_catch_up_with_primary().unwrap();
+            assert_eq!(at_new - 1, lagging.read().unwrap());
+        }
+        {
+            let mut db = DB::open("db", opt2).unwrap();
+            assert_eq!(Some(b"2".to_vec()), db.get(b"b"));
+        }
+        assert!(lagging.read().unwrap() >= at_new);
+
+        // A manifest two or more ahead belongs to another counter.
+        let unrelated = MemMonotonicCounter::new();
+        while unrelated.read().unwrap() + 2 < lagging.read().unwrap() {
+            unrelated.increment().unwrap();
+        }
+        let before = unrelated.read().unwrap();
+        let mut opt3 = opt.clone();
+        opt3.monotonic_counter = Some(unrelated.boxed());
+        assert_eq!(
+            StatusCode::Corruption,
+            DB::open("db", opt3).err().unwrap().code
+        );
+        assert_eq!(before, unrelated.read().unwrap());
+
+        // Removing the database is detected, too.
+        restore_files(&env, dir, &[]);
+        assert_eq!(
+            StatusCode::Rollback,
+            DB::open("db", opt).err().unwrap().code
+        );
+    }
+
+    fn test_db_impl_open_read_only() {
+        let env = MemEnv::new();
+        let dir = Path::new("db");
+        let mut opt = options::for_test();
+        opt.env = Rc::new(Box::new(env.clone()));
+
+        // A database that doesn't exist isn't created.
+        assert_eq!(
+            StatusCode::NotFound,
+            DB::open_read_only("db", opt.clone()).err().unwrap().code
+        );
+        assert!(env.children(dir).unwrap().is_empty());
+
+        let mut db = DB::open("db", opt.clone()).unwrap();
+        for i in 0..50 {
+            db.put(format!("key{:03}", i).as_bytes(), b"table").unwrap();
+        }
+        db.compact_range(b"key000", b"key999").unwrap();
+        for i in 0..20 {
+            db.put(format!("key{:03}", i).as_bytes(), b"log").unwrap();
+        }
+        db.delete(b"key040").unwrap();
+        db.flush().unwrap();
+        let files = save_files(&env, dir);
+
+        {
+            // The lock held by db doesn't matter, and the logs stay in the memtable even though
+            // it's larger than the write buffer.
+            let mut ro_opt = opt.clone();
+            ro_opt.log = None;
+            ro_opt.write_buffer_size = 100;
+            let mut ro = DB::open_read_only("db", ro_opt).unwrap();
+            assert_eq!(db.latest_sequence_number(), ro.latest_sequence_number());
+            assert_eq!(Some(b"log".to_vec()), ro.get(b"key005"));
+            assert_eq!(Some(b"table".to_vec()), ro.get(b"key030"));
+            assert_eq!(None, ro.get(b"key040"));
+            assert_eq!(
+                49,
+                LdbIteratorIter::wrap(&mut ro.new_iter().unwrap()).count()
+            );
+
+            for e in vec![
+                ro.put(b"key", b"value"),
+                ro.delete(b"key005"),
+                ro.flush(),
+                ro.compact_range(b"key000", b"key999"),
+            ] {
+                assert_eq!(StatusCode::NotSupported, e.err().unwrap().code);
+            }
+            assert_eq!(
+                StatusCode::NotSupported,
+                ro.state_digest().err().unwrap().code
+            );
+            assert_eq!(Some(b"log".to_vec()), ro.get(b"key005"));
+        }
+        assert_eq!(files, save_files(&env, dir));
+    }
+
+    fn test_db_impl_secondary() {
+        let env = MemEnv::new();
+        let mut opt = options::for_test();
+        opt.env = Rc::new(Box::new(env.clone()));
+
+        let mut primary = DB::open("db", opt.clone()).unwrap();
+        assert_eq!(
+            StatusCode::NotSupported,
+            primary.try_catch_up_with_primary().err().unwrap().code
+        );
+        primary.put(b"a", b"1").unwrap();
+        primary.flush().unwrap();
+
+        let mut secondary_opt = opt.clone();
+        let stats = CountingEnv::install(&mut secondary_opt);
+        let mut secondary = DB::open_read_only("db", secondary_opt).unwrap();
+        assert_eq!(Some(b"1".to_vec()), secondary.get(b"a"));
+
+        // New log entries, and tables written by the primary.
+        primary.put(b"b", b"2").unwrap();
+        primary.flush().unwrap();
+        assert_eq!(None, secondary.get(b"b"));
+        secondary.try_catch_up_with_primary().unwrap();
+        assert_eq!(Some(b"2".to_vec()), secondary.get(b"b"));
+
+        // Only the part of the log written since the last catch-up is read.
+        stats.reset();
+        primary.put(b"d", b"4").unwrap();
+        primary.flush().unwrap();
+        secondary.try_catch_up_with_primary().unwrap();
+        assert_eq!(Some(b"4".to_vec()), secondary.get(b"d"));
+        assert_eq!(Some(b"2".to_vec()), secondary.get(b"b"));
+        let log = log_file_name(Path::new("db"), primary.log_num.unwrap());
+        assert!(stats.get(IoFileType::Log).bytes_read < env.size_of(&log).unwrap());
+
+        for i in 0..50 {
+            primary
+                .put(format!("key{:03}", i).as_bytes(), b"value")
+                .unwrap();
+        }
+        primary.delete(b"a").unwrap();
+        primary.compact_range(b"a", b"z").unwrap();
+        primary.put(b"c", b"3").unwrap();
+        primary.flush().unwrap();
+        secondary.try_catch_up_with_primary().unwrap();
+
+        assert_eq!(
+            primary.latest_sequence_number(),
+            secondary.latest_sequence_number()
+        );
+        assert_eq!(None, secondary.get(b"a"));
+        assert_eq!(Some(b"3".to_vec()), secondary.get(b"c"));
+        assert_eq!(Some(b"value".to_vec()), secondary.get(b"key025"));
+        assert_eq!(
+            LdbIteratorIter::wrap(&mut primary.new_iter().unwrap()).count(),
+            LdbIteratorIter::wrap(&mut secondary.new_iter().unwrap()).count()
+        );
+
+        // A failed catch-up leaves the state as it was.
+        env.delete(Path::new("db/CURRENT")).unwrap();
+        assert!(secondary.try_catch_up_with_primary().is_err());
+        assert_eq!(Some(b"3".to_vec()), secondary.get(b"c"));
+    }
+
+    fn test_db_impl_state_digest() {
+        let env = MemEnv::new();
+        let mut opt = options::for_test();
+        opt.env = Rc::new(Box::new(env.clone()));
+
+        let digest;
+        {
+            let mut db = DB::open("db", opt.clone()).unwrap();
+            for i in 0..50 {
+                db.put(format!("key{:03}", i).as_bytes(), b"value").unwrap();
+            }
+            digest = db.state_digest().unwrap();
+            assert_eq!(digest, db.state_digest().unwrap());
+
+            // The digest can be recomputed from the live tables.
+            let current = db.current();
+            let files: Vec<FileMetaData> = current
+                .borrow()
+                .files
+                .iter()
+                .flat_map(|l| l.iter().map(|f| f.borrow().clone()))
+                .collect();
+            assert!(!files.is_empty());
+            assert!(files.iter().all(|f| f.content_hash.is_some()));
+            assert_eq!(digest, state_digest(&files, db.latest_sequence_number()));
+        }
+
+        let table;
+        {
+            let mut db = DB::open("db", opt.clone()).unwrap();
+            assert_eq!(digest, db.state_digest().unwrap());
+            db.put(b"another", b"value").unwrap();
+            assert!(digest != db.state_digest().unwrap());
+            let current = db.current();
+            let num = current
+                .borrow()
+                .files
+                .iter()
+                .flat_map(|l| l.iter())
+                .next()
+                .unwrap()
+                .borrow()
+                .num;
+            table = table_file_name(Path::new("db"), num);
+        }
+
+        // Paranoid checks verify the contents of tables.
+        let mut contents = vec![];
+        env.open_sequential_file(&table)
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+        let last = contents.len() - 1;
+        contents[last] ^= 1;
+        env.open_writable_file(&table)
+            .unwrap()
+            .write_all(&contents)
+            .unwrap();
+        opt.paranoid_checks = true;
+        assert_eq!(
+            StatusCode::Corruption,
+            DB::open("db", opt).err().unwrap().code
+        );
+    }
 }
@@ -2,14 +2,14 @@
 use std::prelude::v1::*;
 
 use crate::cmp::Cmp;
-use crate::key_types::{parse_internal_key, truncate_to_userkey, LookupKey, ValueType};
+use crate::key_types::{parse_internal_key, LookupKey, ValueType};
 use crate::merging_iter::MergingIter;
 use crate::snapshot::Snapshot;
-use crate::types::{Direction, LdbIterator, Shared};
+use crate::types::{current_key_val, Direction, LdbIterator, Shared, MAX_SEQUENCE_NUMBER};
 use crate::version_set::VersionSet;
 
 use std::cmp::Ordering;
-use std::mem;
+use std::ops::Bound;
 use std::rc::Rc;
 
 use rand;
@@ -31,10 +31,13 @@ pub struct DBIterator {
     valid: bool,
     // temporarily stored user key.
     savedkey: Vec<u8>,
-    // buffer for reading internal keys
-    keybuf: Vec<u8>,
     savedval: Vec<u8>,
-    valbuf: Vec<u8>,
+
+    // If set, only keys starting with this prefix are returned.
+    prefix: Option<Vec<u8>>,
+    // Only keys within these bounds are returned.
+    lower: Bound<Vec<u8>>,
+    upper: Bound<Vec<u8>>,
 }
 
 impl DBIterator {
@@ -54,19 +57,124 @@ impl DBIterator {
 
             valid: false,
             savedkey: vec![],
-            keybuf: vec![],
             savedval: vec![],
-            valbuf: vec![],
+            prefix: None,
+            lower: Bound::Unbounded,
+            upper: Bound::Unbounded,
+        }
+    }
+
+    /// new_bounded returns a DBIterator only yielding entries whose key is within the `lower`
+    /// and `upper` bounds.
+    pub fn new_bounded(
+        cmp: Rc<Box<dyn Cmp>>,
+        vset: Shared<VersionSet>,
+        iter: MergingIter,
+        ss: Snapshot,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> DBIterator {
+        let mut it = DBIterator::new(cmp, vset, iter, ss);
+        it.lower = to_owned_bound(lower);
+        it.upper = to_owned_bound(upper);
+        it
+    }
+
+    /// new_prefix returns a DBIterator only yielding entries whose key starts with `prefix`.
+    pub fn new_prefix(
+        cmp: Rc<Box<dyn Cmp>>,
+        vset: Shared<VersionSet>,
+        iter: MergingIter,
+        ss: Snapshot,
+        prefix: &[u8],
+    ) -> DBIterator {
+        let mut it = DBIterator::new(cmp, vset, iter, ss);
+        it.prefix = Some(prefix.to_vec());
+        it
+    }
+
+    /// before_range returns true if ukey sorts before all keys this iterator may return.
+    fn before_range(&self, ukey: &[u8]) -> bool {
+        match self.lower {
+            Bound::Included(ref l) if self.cmp.cmp(ukey, l) == Ordering::Less => return true,
+            Bound::Excluded(ref l) if self.cmp.cmp(ukey, l) != Ordering::Greater => return true,
+            _ => {}
+        }
+        match self.prefix {
+            Some(ref p) => self.cmp.cmp(ukey, p) == Ordering::Less,
+            None => false,
+        }
+    }
+
+    /// after_range returns true if ukey sorts after all keys this iterator may return. For prefix
+    /// iterators, this assumes that the comparator orders keys with a common prefix contiguously.
+    fn after_range(&self, ukey: &[u8]) -> bool {
+        match self.upper {
+            Bound::Included(ref u) if self.cmp.cmp(ukey, u) == Ordering::Greater => return true,
+            Bound::Excluded(ref u) if self.cmp.cmp(ukey, u) != Ordering::Less => return true,
+            _ => {}
+        }
+        match self.prefix {
+            Some(ref p) => !ukey.starts_with(p) && self.cmp.cmp(ukey, p) == Ordering::Greater,
+            None => false,
+        }
+    }
+
+    /// range_start returns the key to start iterating at, if the iterator is bounded.
+    fn range_start(&self) -> Option<Vec<u8>> {
+        let lower = match self.lower {
+            Bound::Included(ref l) | Bound::Excluded(ref l) => Some(l),
+            Bound::Unbounded => None,
+        };
+        match (lower, self.prefix.as_ref()) {
+            (Some(l), Some(p)) => {
+                if self.cmp.cmp(l, p) == Ordering::Less {
+                    Some(p.clone())
+                } else {
+                    Some(l.clone())
+                }
+            }
+            (Some(k), None) | (None, Some(k)) => Some(k.clone()),
+            (None, None) => None,
+        }
+    }
+
+    /// range_end returns the bound to start iterating backwards from. For prefix iterators, this
+    /// is the successor of the prefix, unless the upper bound sorts before it; like after_range(),
+    /// it assumes that keys with a common prefix are ordered contiguously.
+    fn range_end(&self) -> Bound<Vec<u8>> {
+        let succ = match self.prefix.as_ref().and_then(|p| prefix_successor(p)) {
+            Some(succ) => succ,
+            None => return self.upper.clone(),
+        };
+        match self.upper {
+            Bound::Included(ref u) | Bound::Excluded(ref u)
+                if self.cmp.cmp(&succ, u) == Ordering::Greater =>
+            {
+                self.upper.clone()
+            }
+            _ => Bound::Excluded(succ),
+        }
+    }
+
+    /// seek_iter_to_first positions the underlying iterator at the first entry that may be in
+    /// range.
+    fn seek_iter_to_first(&mut self) {
+        if let Some(start) = self.range_start() {
+            self.iter
+                .seek(LookupKey::new(&start, self.ss.sequence()).internal_key());
+        } else {
+            self.iter.seek_to_first();
         }
     }
 
-    /// record_read_sample records a read sample using the current contents of self.keybuf, which
-    /// should be an InternalKey.
-    fn record_read_sample<'a>(&mut self, len: usize) {
-        self.byte_count -= len as isize;
+    /// record_read_sample records a read sample using the current entry of the underlying
+    /// iterator.
+    fn record_read_sample(&mut self) {
+        self.byte_count -= (self.iter.key().len() + self.iter.value().len()) as isize;
         if self.byte_count < 0 {
             let v = self.vset.borrow().current();
-            v.borrow_mut().record_read_sample(&self.keybuf);
+            v.borrow_mut().record_read_sample(self.iter.key());
             while self.byte_count < 0 {
                 self.byte_count += random_period();
             }
@@ -79,13 +187,14 @@ impl DBIterator {
         assert!(self.dir == Direction::Forward);
 
         while self.iter.valid() {
-            self.iter.current(&mut self.keybuf, &mut self.savedval);
-            let len = self.keybuf.len() + self.savedval.len();
-            self.record_read_sample(len);
-            let (typ, seq, ukey) = parse_internal_key(&self.keybuf);
+            self.record_read_sample();
+            let (typ, seq, ukey) = parse_internal_key(self.iter.key());
 
-            // Skip keys with a sequence number after our snapshot.
-            if seq <= self.ss.sequence() {
+            if self.after_range(ukey) {
+                break;
+            }
+            // Skip keys with a sequence number after our snapshot, and keys out of range.
+            if seq <= self.ss.sequence() && !self.before_range(ukey) {
                 if typ == ValueType::TypeDeletion {
                     // Mark current (deleted) key to be skipped.
                     self.savedkey.clear();
@@ -127,12 +236,13 @@ impl DBIterator {
         // then break. The key and value of the latest entry for the desired key have been stored
         // in the previous iteration to savedkey and savedval.
         while self.iter.valid() {
-            self.iter.current(&mut self.keybuf, &mut self.valbuf);
-            let len = self.keybuf.len() + self.valbuf.len();
-            self.record_read_sample(len);
-            let (typ, seq, ukey) = parse_internal_key(&self.keybuf);
+            self.record_read_sample();
+            let (typ, seq, ukey) = parse_internal_key(self.iter.key());
 
-            if seq > 0 && seq <= self.ss.sequence() {
+            if self.before_range(ukey) {
+                break;
+            }
+            if seq > 0 && seq <= self.ss.sequence() && !self.after_range(ukey) {
                 if value_type != ValueType::TypeDeletion
                     && self.cmp.cmp(ukey, &self.savedkey) == Ordering::Less
                 {
@@ -146,8 +256,8 @@ impl DBIterator {
                 } else {
                     self.savedkey.clear();
                     self.savedkey.extend_from_slice(ukey);
-
-                    mem::swap(&mut self.savedval, &mut self.valbuf);
+                    self.savedval.clear();
+                    self.savedval.extend_from_slice(self.iter.value());
                 }
             }
             self.iter.prev();
@@ -161,7 +271,7 @@ impl DBIterator {
         } else {
             self.valid = true;
         }
-        true
+        self.valid
     }
 }
 
@@ -175,7 +285,7 @@ impl LdbIterator for DBIterator {
         if self.dir == Direction::Reverse {
             self.dir = Direction::Forward;
             if !self.iter.valid() {
-                self.iter.seek_to_first();
+                self.seek_iter_to_first();
             } else {
                 self.iter.advance();
             }
@@ -186,8 +296,10 @@ impl LdbIterator for DBIterator {
             }
         } else {
             // Save current user key.
-            assert!(self.iter.current(&mut self.savedkey, &mut self.savedval));
-            truncate_to_userkey(&mut self.savedkey);
+            assert!(self.iter.valid());
+            self.savedkey.clear();
+            self.savedkey
+                .extend_from_slice(parse_internal_key(self.iter.key()).2);
         }
         self.find_next_user_entry(
             // skipping=
@@ -198,17 +310,30 @@ impl LdbIterator for DBIterator {
         if !self.valid() {
             return false;
         }
-        // If direction is forward, savedkey and savedval are not used.
-        if self.dir == Direction::Forward {
-            self.iter.current(key, val);
-            truncate_to_userkey(key);
-            true
+        key.clear();
+        key.extend_from_slice(self.key());
+        val.clear();
+        val.extend_from_slice(self.value());
+        true
+    }
+    /// key returns the current user key. If the iterator is moving forward, it is borrowed from
+    /// the underlying iterator; otherwise from savedkey.
+    fn key(&self) -> &[u8] {
+        if !self.valid() {
+            &[]
+        } else if self.dir == Direction::Forward {
+            parse_internal_key(self.iter.key()).2
+        } else {
+            &self.savedkey
+        }
+    }
+    fn value(&self) -> &[u8] {
+        if !self.valid() {
+            &[]
+        } else if self.dir == Direction::Forward {
+            self.iter.value()
         } else {
-            key.clear();
-            key.extend_from_slice(&self.savedkey);
-            val.clear();
-            val.extend_from_slice(&self.savedval);
-            true
+            &self.savedval
         }
     }
     fn prev(&mut self) -> bool {
@@ -221,8 +346,9 @@ impl LdbIterator for DBIterator {
             // find_prev_user_entry() wants savedkey to be the key of the entry that is supposed to
             // be left in savedkey/savedval, which is why we have to go to the previous entry before
             // calling it.
-            self.iter.current(&mut self.savedkey, &mut self.savedval);
-            truncate_to_userkey(&mut self.savedkey);
+            self.savedkey.clear();
+            self.savedkey
+                .extend_from_slice(parse_internal_key(self.iter.key()).2);
             loop {
                 self.iter.prev();
                 if !self.iter.valid() {
@@ -232,9 +358,8 @@ impl LdbIterator for DBIterator {
                     return false;
                 }
                 // Scan until we hit the next-smaller key.
-                self.iter.current(&mut self.keybuf, &mut self.savedval);
-                truncate_to_userkey(&mut self.keybuf);
-                if self.cmp.cmp(&self.keybuf, &self.savedkey) == Ordering::Less {
+                let ukey = parse_internal_key(self.iter.key()).2;
+                if self.cmp.cmp(ukey, &self.savedkey) == Ordering::Less {
                     break;
                 }
             }
@@ -246,6 +371,13 @@ impl LdbIterator for DBIterator {
         self.valid
     }
     fn seek(&mut self, to: &[u8]) {
+        let start;
+        let to = if self.before_range(to) {
+            start = self.range_start().unwrap();
+            &start
+        } else {
+            to
+        };
         self.dir = Direction::Forward;
         self.savedkey.clear();
         self.savedval.clear();
@@ -264,7 +396,7 @@ impl LdbIterator for DBIterator {
     fn seek_to_first(&mut self) {
         self.dir = Direction::Forward;
         self.savedval.clear();
-        self.iter.seek_to_first();
+        self.seek_iter_to_first();
         if self.iter.valid() {
             self.find_next_user_entry(
                 // skipping=
@@ -274,15 +406,156 @@ impl LdbIterator for DBIterator {
             self.valid = false;
         }
     }
+    fn seek_to_last(&mut self) {
+        self.dir = Direction::Reverse;
+        self.savedkey.clear();
+        self.savedval.clear();
+        match self.range_end() {
+            Bound::Excluded(ref upper) => {
+                // Start at the last entry before the upper bound.
+                self.iter
+                    .seek(LookupKey::new(upper, MAX_SEQUENCE_NUMBER).internal_key());
+                if self.iter.valid() {
+                    self.iter.prev();
+                } else {
+                    self.iter.seek_to_last();
+                }
+            }
+            Bound::Included(ref upper) => {
+                // Start at the last entry of the upper bound key.
+                self.iter
+                    .seek(LookupKey::new(upper, MAX_SEQUENCE_NUMBER).internal_key());
+                while self.iter.valid() {
+                    let ukey = parse_internal_key(self.iter.key()).2;
+                    if self.cmp.cmp(ukey, upper) != Ordering::Equal {
+                        break;
+                    }
+                    self.iter.advance();
+                }
+                if self.iter.valid() {
+                    self.iter.prev();
+                } else {
+                    self.iter.seek_to_last();
+                }
+            }
+            Bound::Unbounded => self.iter.seek_to_last(),
+        }
+        self.find_prev_user_entry();
+    }
     fn reset(&mut self) {
         self.iter.reset();
         self.valid = false;
         self.savedkey.clear();
         self.savedval.clear();
-        self.keybuf.clear();
     }
 }
 
+/// DBRange is a std::iter::Iterator over a range of database entries, as returned by DB::range()
+/// and DB::prefix(). It can be iterated from both ends; iteration stops once both ends meet.
+pub struct DBRange {
+    cmp: Rc<Box<dyn Cmp>>,
+    front: DBIterator,
+    back: DBIterator,
+    // The keys most recently returned from either end.
+    front_key: Option<Vec<u8>>,
+    back_key: Option<Vec<u8>>,
+    done: bool,
+}
+
+impl DBRange {
+    /// new returns a DBRange using front and back, two iterators over the same range at the same
+    /// snapshot, for iterating from either end.
+    pub fn new(cmp: Rc<Box<dyn Cmp>>, front: DBIterator, back: DBIterator) -> DBRange {
+        DBRange {
+            cmp,
+            front,
+            back,
+            front_key: None,
+            back_key: None,
+            done: false,
+        }
+    }
+}
+
+impl Iterator for DBRange {
+    type Item = (Vec<u8>, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        if self.front_key.is_none() {
+            self.front.seek_to_first();
+        } else {
+            self.front.advance();
+        }
+        let (k, v) = match current_key_val(&self.front) {
+            Some(kv) => kv,
+            None => {
+                self.done = true;
+                return None;
+            }
+        };
+        if let Some(ref bk) = self.back_key {
+            if self.cmp.cmp(&k, bk) != Ordering::Less {
+                self.done = true;
+                return None;
+            }
+        }
+        self.front_key = Some(k.clone());
+        Some((k, v))
+    }
+}
+
+impl DoubleEndedIterator for DBRange {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        if self.back_key.is_none() {
+            self.back.seek_to_last();
+        } else {
+            self.back.prev();
+        }
+        let (k, v) = match current_key_val(&self.back) {
+            Some(kv) => kv,
+            None => {
+                self.done = true;
+                return None;
+            }
+        };
+        if let Some(ref fk) = self.front_key {
+            if self.cmp.cmp(&k, fk) != Ordering::Greater {
+                self.done = true;
+                return None;
+            }
+        }
+        self.back_key = Some(k.clone());
+        Some((k, v))
+    }
+}
+
+fn to_owned_bound(b: Bound<&[u8]>) -> Bound<Vec<u8>> {
+    match b {
+        Bound::Included(k) => Bound::Included(k.to_vec()),
+        Bound::Excluded(k) => Bound::Excluded(k.to_vec()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+/// Returns the shortest key sorting after all keys starting with prefix, or None if there is no
+/// such key (the prefix consists of 0xff bytes only).
+fn prefix_successor(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut succ = prefix.to_vec();
+    while let Some(last) = succ.pop() {
+        if last < 0xff {
+            succ.push(last + 1);
+            return Some(succ);
+        }
+    }
+    None
+}
+
 fn random_period() -> isize {
     rand::random::<isize>() % 2 * READ_BYTES_PERIOD
 }
@@ -298,6 +571,7 @@ pub mod tests {
     use std::collections::HashMap;
     use std::collections::HashSet;
     use std::iter::FromIterator;
+    use std::ops::Bound;
     use teaclave_test_utils::*;
 
     pub fn run_tests() -> bool {
@@ -309,6 +583,11 @@ pub mod tests {
             db_iter_deleted_entry_not_returned,
             db_iter_deleted_entry_not_returned_memtable,
             db_iter_repeated_open_close,
+            db_iter_prefix,
+            db_iter_bounds,
+            db_iter_reverse_full,
+            db_iter_range,
+            db_iter_key_value,
         )
     }
 
@@ -494,4 +773,229 @@ pub mod tests {
             }
         }
     }
+
+    fn db_iter_prefix() {
+        let mut db = build_db().0;
+        let mut iter = db.new_prefix_iter(b"aa").unwrap();
+
+        let keys: Vec<Vec<u8>> = LdbIteratorIter::wrap(&mut iter).map(|(k, _)| k).collect();
+        assert_eq!(
+            vec![b"aaa".to_vec(), b"aab".to_vec(), b"aax".to_vec()],
+            keys
+        );
+
+        iter.seek(b"aax");
+        assert!(iter.valid());
+        assert!(iter.prev());
+        assert_eq!(b"aab".to_vec(), current_key_val(&iter).unwrap().0);
+        assert!(iter.prev());
+        assert_eq!(b"aaa".to_vec(), current_key_val(&iter).unwrap().0);
+        assert!(!iter.prev());
+
+        // Seeking before the prefix positions the iterator at the first key with the prefix.
+        iter.seek(b"a");
+        assert_eq!(b"aaa".to_vec(), current_key_val(&iter).unwrap().0);
+        iter.seek(b"ab");
+        assert!(!iter.valid());
+
+        let mut iter = db.new_prefix_iter(b"c").unwrap();
+        let keys: Vec<Vec<u8>> = LdbIteratorIter::wrap(&mut iter).map(|(k, _)| k).collect();
+        assert_eq!(vec![b"cab".to_vec(), b"cba".to_vec()], keys);
+
+        // gca is deleted.
+        let mut iter = db.new_prefix_iter(b"gc").unwrap();
+        assert!(!iter.advance());
+    }
+
+    fn db_iter_bounds() {
+        let mut db = build_db().0;
+        let mut iter = db.new_iter_with_bounds(Some(b"aab"), Some(b"bab")).unwrap();
+
+        let keys: Vec<Vec<u8>> = LdbIteratorIter::wrap(&mut iter).map(|(k, _)| k).collect();
+        assert_eq!(
+            vec![b"aab".to_vec(), b"aax".to_vec(), b"aba".to_vec()],
+            keys
+        );
+
+        iter.seek(b"aa");
+        assert_eq!(b"aab".to_vec(), current_key_val(&iter).unwrap().0);
+        assert!(!iter.prev());
+        iter.seek(b"bab");
+        assert!(!iter.valid());
+
+        iter.seek(b"aba");
+        assert!(iter.prev());
+        assert_eq!(b"aax".to_vec(), current_key_val(&iter).unwrap().0);
+        assert!(iter.advance());
+        assert!(!iter.advance());
+
+        let mut iter = db.new_iter_with_bounds(Some(b"gb"), None).unwrap();
+        let keys: Vec<Vec<u8>> = LdbIteratorIter::wrap(&mut iter).map(|(k, _)| k).collect();
+        assert_eq!(
+            vec![
+                b"gba".to_vec(),
+                b"gda".to_vec(),
+                b"haa".to_vec(),
+                b"hba".to_vec(),
+                b"iaa".to_vec(),
+                b"iba".to_vec()
+            ],
+            keys
+        );
+    }
+
+    fn db_iter_reverse_full() {
+        let mut db = build_db().0;
+        db.put(b"aac", b"val6").unwrap();
+        db.delete(b"aab").unwrap();
+        db.delete(b"iba").unwrap();
+        db.put(b"zzz", b"val7").unwrap();
+        db.delete(b"zzz").unwrap();
+
+        let mut iter = db.new_iter().unwrap();
+        let mut fwd: Vec<(Vec<u8>, Vec<u8>)> = LdbIteratorIter::wrap(&mut iter).collect();
+        assert_eq!(b"aac".to_vec(), fwd[1].0);
+        assert_eq!(b"iaa".to_vec(), fwd.last().unwrap().0);
+
+        let mut bwd = vec![];
+        iter.seek_to_last();
+        while iter.valid() {
+            bwd.push(current_key_val(&iter).unwrap());
+            iter.prev();
+        }
+        fwd.reverse();
+        assert_eq!(fwd, bwd);
+
+        let mut iter = db.new_iter_with_bounds(Some(b"aab"), Some(b"cab")).unwrap();
+        iter.seek_to_last();
+        assert_eq!(b"bba".to_vec(), current_key_val(&iter).unwrap().0);
+        assert!(iter.prev());
+        assert_eq!(b"bab".to_vec(), current_key_val(&iter).unwrap().0);
+
+        let mut iter = db.new_prefix_iter(b"aa").unwrap();
+        iter.seek_to_last();
+        assert_eq!(b"aax".to_vec(), current_key_val(&iter).unwrap().0);
+
+        // The iterator starts at the successor of the prefix, or the upper bound before it.
+        let mut iter = db.new_prefix_iter(b"a").unwrap();
+        iter.seek_to_last();
+        assert_eq!(b"aba".to_vec(), current_key_val(&iter).unwrap().0);
+        let mut iter = db.new_prefix_iter(b"c").unwrap();
+        iter.upper = Bound::Excluded(b"cb".to_vec());
+        iter.seek_to_last();
+        assert_eq!(b"cab".to_vec(), current_key_val(&iter).unwrap().0);
+        let mut iter = db.new_prefix_iter(b"gc").unwrap();
+        iter.seek_to_last();
+        assert!(!iter.valid());
+
+        assert_eq!(Some(b"ab".to_vec()), prefix_successor(b"aa"));
+        assert_eq!(Some(b"b".to_vec()), prefix_successor(b"a\xff"));
+        assert_eq!(None, prefix_successor(b"\xff\xff"));
+    }
+
+    fn db_iter_range() {
+        let mut db = build_db().0;
+
+        let keys: Vec<Vec<u8>> = db
+            .range((Bound::Included(&b"aab"[..]), Bound::Excluded(&b"bba"[..])))
+            .unwrap()
+            .map(|(k, _)| k)
+            .collect();
+        assert_eq!(
+            vec![
+                b"aab".to_vec(),
+                b"aax".to_vec(),
+                b"aba".to_vec(),
+                b"bab".to_vec()
+            ],
+            keys
+        );
+
+        let keys: Vec<Vec<u8>> = db
+            .range((Bound::Excluded(&b"aab"[..]), Bound::Included(&b"bba"[..])))
+            .unwrap()
+            .rev()
+            .map(|(k, _)| k)
+            .collect();
+        assert_eq!(
+            vec![
+                b"bba".to_vec(),
+                b"bab".to_vec(),
+                b"aba".to_vec(),
+                b"aax".to_vec()
+            ],
+            keys
+        );
+
+        assert_eq!(23, db.range(..).unwrap().count());
+        assert_eq!(23, db.range(..).unwrap().rev().count());
+
+        // Both ends meet in the middle.
+        let mut r = db.prefix(b"a").unwrap();
+        assert_eq!(b"aaa".to_vec(), r.next().unwrap().0);
+        assert_eq!(b"aba".to_vec(), r.next_back().unwrap().0);
+        assert_eq!(b"aab".to_vec(), r.next().unwrap().0);
+        assert_eq!(b"aax".to_vec(), r.next_back().unwrap().0);
+        assert_eq!(None, r.next());
+        assert_eq!(None, r.next_back());
+
+        let vals: Vec<Vec<u8>> = db.prefix(b"g").unwrap().map(|(_, v)| v).collect();
+        assert_eq!(
+            vec![
+                b"val1".to_vec(),
+                b"val2".to_vec(),
+                b"val3".to_vec(),
+                b"val5".to_vec()
+            ],
+            vals
+        );
+
+        // Both ends use one snapshot, taken when the range is created.
+        let snapshots = db.snapshots().len();
+        let mut r = db.range(..).unwrap();
+        assert_eq!(snapshots + 1, db.snapshots().len());
+        db.put(b"zzz", b"new").unwrap();
+        assert!(r.next_back().unwrap().0 != b"zzz".to_vec());
+        assert_eq!(snapshots + 1, db.snapshots().len());
+    }
+
+    fn db_iter_key_value() {
+        let mut db = build_db().0;
+        db.put(b"aac", b"val6").unwrap();
+        db.put(b"aba", b"val7").unwrap();
+
+        let mut iter = db.new_iter().unwrap();
+        assert!(iter.key().is_empty());
+        let mut n = 0;
+        while iter.advance() {
+            let (k, v) = current_key_val(&iter).unwrap();
+            assert_eq!(k.as_slice(), iter.key());
+            assert_eq!(v.as_slice(), iter.value());
+            n += 1;
+        }
+        assert_eq!(24, n);
+        assert!(iter.value().is_empty());
+
+        iter.seek_to_last();
+        while iter.valid() {
+            let (k, v) = current_key_val(&iter).unwrap();
+            assert_eq!(k.as_slice(), iter.key());
+            assert_eq!(v.as_slice(), iter.value());
+            iter.prev();
+        }
+
+        iter.seek(b"aab");
+        assert_eq!(b"aab", iter.key());
+        assert!(iter.advance());
+        assert_eq!(b"aac", iter.key());
+        assert_eq!(b"val6", iter.value());
+        assert!(iter.prev());
+        assert_eq!(b"aab", iter.key());
+        assert!(iter.advance());
+        assert_eq!(b"aac", iter.key());
+
+        iter.seek(b"ab");
+        assert_eq!(b"aba", iter.key());
+        assert_eq!(b"val7", iter.value());
+    }
 }
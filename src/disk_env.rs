@@ -4,8 +4,10 @@ use std::prelude::v1::*;
 use crate::env::{path_to_str, Env, FileLock, Logger, RandomAccess};
 use crate::env_common::micros;
 use crate::error::{err, Result, Status, StatusCode};
+use crate::key_provider::{derive_file_key, BoxedKeyProvider};
+use crate::types::{parse_file_name, FileType, KeyGeneration};
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use protected_fs;
 use std::io::{Seek, SeekFrom};
@@ -27,19 +29,316 @@ const F_UNLCK: libc::c_short = 2;
 
 type FileDescriptor = i32;
 
+/// A key in a KeyRing: Either the key of all files, or a master key that each file's key is
+/// derived from.
+#[derive(Clone)]
+enum RingKey {
+    Shared(DBPersistKey),
+    Master(Vec<u8>),
+}
+
+impl RingKey {
+    fn for_file(&self, db_id: &[u8], p: &Path) -> DBPersistKey {
+        match *self {
+            RingKey::Shared(key) => key,
+            RingKey::Master(ref master) => derive_file_key(master, db_id, &key_name(p)),
+        }
+    }
+}
+
+/// Returns the name that the key of the file p is derived from. A temporary file is renamed to
+/// CURRENT and LOG to LOG.old, so these pairs share their key.
+fn key_name(p: &Path) -> String {
+    let name = match p.file_name() {
+        Some(n) => n.to_string_lossy().into_owned(),
+        None => return String::new(),
+    };
+    match parse_file_name(&name) {
+        Ok((_, FileType::Temp)) => "CURRENT".to_string(),
+        Ok((_, FileType::InfoLog)) => "LOG".to_string(),
+        _ => name,
+    }
+}
+
+struct KeyRingState {
+    keys: BTreeMap<KeyGeneration, RingKey>,
+    current: KeyGeneration,
+    // Mixed into the keys derived from master keys, see KeyRing::from_provider().
+    db_id: Vec<u8>,
+    // The key generation of files, by file name. Files not in here are tried with all keys.
+    files: HashMap<String, KeyGeneration>,
+}
+
+/// KeyRing holds the generations of keys a database's files are encrypted with. New files are
+/// always encrypted with the newest key. Clones share their state, so that a database (via
+/// Options::key_ring) and its PosixDiskEnv can use the same ring.
+///
+/// A generation either holds a DBPersistKey used for all files, or a master key from which every
+/// file gets its own key, derived from the file's name and the ring's database identifier by
+/// key_provider::derive_file_key(). With the latter, a leaked file key exposes only that file.
+///
+/// Files are identified by their name, without the directory, so a ring should only be used for
+/// a single database.
+#[derive(Clone)]
+pub struct KeyRing {
+    state: Arc<Mutex<KeyRingState>>,
+}
+
+impl KeyRing {
+    /// Returns a new ring containing key as generation 0.
+    pub fn new(key: DBPersistKey) -> KeyRing {
+        KeyRing::with_generation(0, key)
+    }
+
+    /// Returns a new ring containing key as generation gen, e.g. for a database whose keys have
+    /// been rotated before.
+    pub fn with_generation(gen: KeyGeneration, key: DBPersistKey) -> KeyRing {
+        KeyRing::with_ring_key(gen, RingKey::Shared(key), &[])
+    }
+
+    /// Returns a new ring containing the master key of provider as generation 0, from which the
+    /// keys of individual files are derived.
+    ///
+    /// db_id identifies the database among all databases whose keys are derived from the same
+    /// master key (e.g. its name), so that their files get different keys even if they have the
+    /// same names. It must stay the same for the lifetime of the database.
+    pub fn from_provider(provider: &BoxedKeyProvider, db_id: &[u8]) -> Result<KeyRing> {
+        Ok(KeyRing::with_master_key(0, &provider.master_key()?, db_id))
+    }
+
+    /// Returns a new ring containing master as generation gen, from which the keys of individual
+    /// files are derived. See from_provider() for db_id.
+    pub fn with_master_key(gen: KeyGeneration, master: &[u8], db_id: &[u8]) -> KeyRing {
+        KeyRing::with_ring_key(gen, RingKey::Master(master.to_vec()), db_id)
+    }
+
+    fn with_ring_key(gen: KeyGeneration, key: RingKey, db_id: &[u8]) -> KeyRing {
+        let mut keys = BTreeMap::new();
+        keys.insert(gen, key);
+        KeyRing {
+            state: Arc::new(Mutex::new(KeyRingState {
+                keys,
+                current: gen,
+                db_id: db_id.to_vec(),
+                files: HashMap::new(),
+            })),
+        }
+    }
+
+    /// Adds key as a new generation, which is used for all files created from now on, and
+    /// returns the generation.
+    pub fn add_key(&self, key: DBPersistKey) -> KeyGeneration {
+        self.add_ring_key(RingKey::Shared(key))
+    }
+
+    /// Like add_key(), but with a master key from which the keys of individual files are derived.
+    /// The ring's database identifier is used for the new key, too.
+    pub fn add_master_key(&self, master: &[u8]) -> KeyGeneration {
+        self.add_ring_key(RingKey::Master(master.to_vec()))
+    }
+
+    fn add_ring_key(&self, key: RingKey) -> KeyGeneration {
+        let mut state = self.state.lock().unwrap();
+        state.current += 1;
+        let gen = state.current;
+        state.keys.insert(gen, key);
+        gen
+    }
+
+    /// Adds a key of an older generation, e.g. when opening a database whose rotation to the
+    /// current key is not complete yet. Returns an error with StatusCode::InvalidArgument if gen
+    /// is not older than the current generation.
+    pub fn add_old_key(&self, gen: KeyGeneration, key: DBPersistKey) -> Result<()> {
+        self.add_old_ring_key(gen, RingKey::Shared(key))
+    }
+
+    /// Like add_old_key(), but with a master key from which the keys of individual files are
+    /// derived.
+    pub fn add_old_master_key(&self, gen: KeyGeneration, master: &[u8]) -> Result<()> {
+        self.add_old_ring_key(gen, RingKey::Master(master.to_vec()))
+    }
+
+    fn add_old_ring_key(&self, gen: KeyGeneration, key: RingKey) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if gen >= state.current {
+            return err(
+                StatusCode::InvalidArgument,
+                &format!(
+                    "key generation {} is not older than the current generation {}",
+                    gen, state.current
+                ),
+            );
+        }
+        state.keys.insert(gen, key);
+        Ok(())
+    }
+
+    pub fn current_generation(&self) -> KeyGeneration {
+        self.state.lock().unwrap().current
+    }
+
+    /// Returns the generations of all keys in the ring, oldest first.
+    pub fn generations(&self) -> Vec<KeyGeneration> {
+        self.state.lock().unwrap().keys.keys().cloned().collect()
+    }
+
+    /// Removes all keys older than gen from the ring.
+    pub fn remove_keys_before(&self, gen: KeyGeneration) {
+        let mut state = self.state.lock().unwrap();
+        assert!(gen <= state.current);
+        let newer = state.keys.split_off(&gen);
+        state.keys = newer;
+        state.files.retain(|_, g| *g >= gen);
+    }
+
+    /// Records that the file p is encrypted with the key of generation gen.
+    pub fn set_file_generation(&self, p: &Path, gen: KeyGeneration) {
+        if let Some(name) = p.file_name() {
+            let mut state = self.state.lock().unwrap();
+            state.files.insert(name.to_string_lossy().into_owned(), gen);
+        }
+    }
+
+    /// Returns the current generation and the key it assigns to p.
+    fn current_key(&self, p: &Path) -> (KeyGeneration, DBPersistKey) {
+        let state = self.state.lock().unwrap();
+        (
+            state.current,
+            state.keys[&state.current].for_file(&state.db_id, p),
+        )
+    }
+
+    /// Returns the keys that p may be encrypted with, in the order they should be tried: The
+    /// generation recorded for p first, then all others from newest to oldest.
+    fn candidates(&self, p: &Path) -> Vec<(KeyGeneration, DBPersistKey)> {
+        let state = self.state.lock().unwrap();
+        let recorded = p
+            .file_name()
+            .and_then(|name| state.files.get(name.to_string_lossy().as_ref()))
+            .cloned()
+            .filter(|gen| state.keys.contains_key(gen));
+        let mut keys: Vec<(KeyGeneration, DBPersistKey)> = recorded
+            .map(|gen| (gen, state.keys[&gen].for_file(&state.db_id, p)))
+            .into_iter()
+            .collect();
+        for (gen, key) in state.keys.iter().rev() {
+            if recorded != Some(*gen) {
+                keys.push((*gen, key.for_file(&state.db_id, p)));
+            }
+        }
+        keys
+    }
+
+    /// Returns the key that generation gen assigns to p, if the generation is in the ring.
+    fn key_of_generation(&self, gen: KeyGeneration, p: &Path) -> Option<DBPersistKey> {
+        let state = self.state.lock().unwrap();
+        state
+            .keys
+            .get(&gen)
+            .map(|key| key.for_file(&state.db_id, p))
+    }
+
+    fn rename_file(&self, old: &Path, new: &Path) {
+        if let (Some(old), Some(new)) = (old.file_name(), new.file_name()) {
+            let mut state = self.state.lock().unwrap();
+            if let Some(gen) = state.files.remove(old.to_string_lossy().as_ref()) {
+                state.files.insert(new.to_string_lossy().into_owned(), gen);
+            }
+        }
+    }
+
+    fn remove_file(&self, p: &Path) {
+        if let Some(name) = p.file_name() {
+            let mut state = self.state.lock().unwrap();
+            state.files.remove(name.to_string_lossy().as_ref());
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct PosixDiskEnv {
     locks: Arc<Mutex<HashMap<String, protected_fs::ProtectedFile>>>,
-    key: DBPersistKey,
+    keys: KeyRing,
 }
 
 impl PosixDiskEnv {
     pub fn new_with(key: DBPersistKey) -> PosixDiskEnv {
+        PosixDiskEnv::new_with_key_ring(KeyRing::new(key))
+    }
+
+    /// Returns an env encrypting new files with the current key of keys, and reading existing
+    /// files with the key generation they were written with.
+    pub fn new_with_key_ring(keys: KeyRing) -> PosixDiskEnv {
         PosixDiskEnv {
             locks: Arc::new(Mutex::new(HashMap::new())),
-            key,
+            keys,
         }
     }
+
+    /// Returns an env encrypting every file with its own key, derived from the master key of
+    /// provider and db_id (see KeyRing::from_provider()).
+    pub fn new_with_provider(provider: &BoxedKeyProvider, db_id: &[u8]) -> Result<PosixDiskEnv> {
+        Ok(PosixDiskEnv::new_with_key_ring(KeyRing::from_provider(
+            provider, db_id,
+        )?))
+    }
+
+    /// with_key calls f with the keys p may be encrypted with until it succeeds, and records the
+    /// generation of the key that worked.
+    fn with_key<T, F: Fn(&DBPersistKey) -> io::Result<T>>(&self, p: &Path, f: F) -> io::Result<T> {
+        self.with_key_gen(p, f).map(|(_, t)| t)
+    }
+
+    /// Like with_key(), but also returns the generation of the key that worked.
+    fn with_key_gen<T, F: Fn(&DBPersistKey) -> io::Result<T>>(
+        &self,
+        p: &Path,
+        f: F,
+    ) -> io::Result<(KeyGeneration, T)> {
+        let mut last_err = None;
+        for (gen, key) in self.keys.candidates(p) {
+            match f(&key) {
+                Ok(t) => {
+                    self.keys.set_file_generation(p, gen);
+                    return Ok((gen, t));
+                }
+                Err(e) => last_err = Some(e),
+            }
+        }
+        // There is at least the current key.
+        Err(last_err.unwrap())
+    }
+
+    /// Returns the current key, and records that p is encrypted with it.
+    fn key_for_new_file(&self, p: &Path) -> DBPersistKey {
+        let (gen, key) = self.keys.current_key(p);
+        self.keys.set_file_generation(p, gen);
+        key
+    }
+
+    /// Moves old to new by re-encrypting its contents with key, which generation gen assigns to
+    /// new.
+    fn copy_file(
+        &self,
+        old: &Path,
+        new: &Path,
+        gen: KeyGeneration,
+        key: DBPersistKey,
+    ) -> Result<()> {
+        let mut contents = vec![];
+        self.open_sequential_file(old)?.read_to_end(&mut contents)?;
+        {
+            let mut f = protected_fs::OpenOptions::default()
+                .write(true)
+                .append(false)
+                .open_ex(new, &key)
+                .map_err(|e| map_err_with_name("rename (copy)", new, e))?;
+            f.write_all(&contents)?;
+            f.flush()?;
+        }
+        self.keys.set_file_generation(new, gen);
+        self.delete(old)
+    }
 }
 
 /// map_err_with_name annotates an io::Error with information about the operation and the file.
@@ -54,16 +353,21 @@ fn map_err_with_name(method: &'static str, f: &Path, e: io::Error) -> Status {
 impl Env for PosixDiskEnv {
     fn open_sequential_file(&self, p: &Path) -> Result<Box<dyn Read>> {
         Ok(Box::new(
-            protected_fs::OpenOptions::default()
-                .read(true)
-                .open_ex(p, &self.key)
-                .map_err(|e| map_err_with_name("open_sgx (seq)", p, e))?,
+            self.with_key(p, |key| {
+                protected_fs::OpenOptions::default()
+                    .read(true)
+                    .open_ex(p, key)
+            })
+            .map_err(|e| map_err_with_name("open_sgx (seq)", p, e))?,
         ))
     }
     fn open_random_access_file(&self, p: &Path) -> Result<Box<dyn RandomAccess>> {
-        Ok(protected_fs::OpenOptions::default()
-            .read(true)
-            .open_ex(p, &self.key)
+        Ok(self
+            .with_key(p, |key| {
+                protected_fs::OpenOptions::default()
+                    .read(true)
+                    .open_ex(p, key)
+            })
             .map(|f| {
                 let b: Box<dyn RandomAccess> = Box::new(f);
                 b
@@ -75,16 +379,18 @@ impl Env for PosixDiskEnv {
             protected_fs::OpenOptions::default()
                 .write(true)
                 .append(false)
-                .open_ex(p, &self.key)
+                .open_ex(p, &self.key_for_new_file(p))
                 .map_err(|e| map_err_with_name("open_sgx (write)", p, e))?,
         ))
     }
     fn open_appendable_file(&self, p: &Path) -> Result<Box<dyn Write>> {
         Ok(Box::new(
-            protected_fs::OpenOptions::default()
-                .append(true)
-                .open_ex(p, &self.key)
-                .map_err(|e| map_err_with_name("open_sgx (append_sgx)", p, e))?,
+            self.with_key(p, |key| {
+                protected_fs::OpenOptions::default()
+                    .append(true)
+                    .open_ex(p, key)
+            })
+            .map_err(|e| map_err_with_name("open_sgx (append_sgx)", p, e))?,
         ))
     }
     fn exists(&self, p: &Path) -> Result<bool> {
@@ -106,16 +412,21 @@ impl Env for PosixDiskEnv {
     }
 
     fn size_of(&self, p: &Path) -> Result<usize> {
-        let mut f = protected_fs::OpenOptions::default()
-            .read(true)
-            .open_ex(p, &self.key)
+        let mut f = self
+            .with_key(p, |key| {
+                protected_fs::OpenOptions::default()
+                    .read(true)
+                    .open_ex(p, key)
+            })
             .map_err(|e| map_err_with_name("size_of (open)", p, e))?;
         let size = f.seek(SeekFrom::End(0))?;
         Ok(size as usize)
     }
 
     fn delete(&self, p: &Path) -> Result<()> {
-        Ok(fs::remove_file(p).map_err(|e| map_err_with_name("delete", p, e))?)
+        fs::remove_file(p).map_err(|e| map_err_with_name("delete", p, e))?;
+        self.keys.remove_file(p);
+        Ok(())
     }
     fn mkdir(&self, p: &Path) -> Result<()> {
         Ok(fs::create_dir_all(p).map_err(|e| map_err_with_name("mkdir", p, e))?)
@@ -136,14 +447,25 @@ impl Env for PosixDiskEnv {
         ))?;
 
         {
-            let f = protected_fs::OpenOptions::default()
-                .append(true)
-                .open_ex(old, &self.key)
+            let (gen, f) = self
+                .with_key_gen(old, |key| {
+                    protected_fs::OpenOptions::default()
+                        .append(true)
+                        .open_ex(old, key)
+                })
                 .map_err(|e| map_err_with_name("rename_meta (open)", old, e))?;
+            let new_key = self.keys.key_of_generation(gen, new);
+            if new_key != self.keys.key_of_generation(gen, old) {
+                // The new name has a different derived key.
+                drop(f);
+                return self.copy_file(old, new, gen, new_key.unwrap());
+            }
             f.rename_meta(&old_name, &new_name)?;
         }
 
-        Ok(fs::rename(old, new).map_err(|e| map_err_with_name("rename", old, e))?)
+        fs::rename(old, new).map_err(|e| map_err_with_name("rename", old, e))?;
+        self.keys.rename_file(old, new);
+        Ok(())
     }
 
     fn lock(&self, p: &Path) -> Result<FileLock> {
@@ -155,7 +477,7 @@ impl Env for PosixDiskEnv {
             let f = protected_fs::OpenOptions::default()
                 .write(true)
                 .append(false)
-                .open_ex(p, &self.key)
+                .open_ex(p, &self.key_for_new_file(p))
                 .map_err(|e| map_err_with_name("lock_sgx: ", p, e))?;
 
             locks.insert(p.to_str().unwrap().to_string(), f);
@@ -191,13 +513,20 @@ impl Env for PosixDiskEnv {
 #[cfg(feature = "enclave_unit_test")]
 pub mod tests {
     use super::*;
+    use crate::key_provider::StaticKeyProvider;
     use std::convert::AsRef;
     use std::io::Write;
     use std::iter::FromIterator;
     use teaclave_test_utils::*;
 
     pub fn run_tests() -> bool {
-        run_tests!(test_files, test_locking, test_dirs,)
+        run_tests!(
+            test_files,
+            test_locking,
+            test_dirs,
+            test_key_ring,
+            test_key_ring_derived_keys,
+        )
     }
 
     fn test_files() {
@@ -283,4 +612,133 @@ pub mod tests {
         assert_eq!(env.children(dirname).unwrap().len(), 1);
         assert!(env.rmdir(dirname).is_ok());
     }
+
+    fn test_key_ring() {
+        let ring = KeyRing::new([1; 16]);
+        let env = PosixDiskEnv::new_with_key_ring(ring.clone());
+        let (old, new) = (Path::new("keyring_old.txt"), Path::new("keyring_new.txt"));
+
+        env.open_writable_file(old)
+            .unwrap()
+            .write_all(b"old key")
+            .unwrap();
+        assert_eq!(1, ring.add_key([2; 16]));
+        env.open_writable_file(new)
+            .unwrap()
+            .write_all(b"new key")
+            .unwrap();
+        assert_eq!(vec![0, 1], ring.generations());
+
+        // A fresh env and ring find the keys by trying them.
+        let ring2 = KeyRing::new([1; 16]);
+        ring2.add_key([2; 16]);
+        let env2 = PosixDiskEnv::new_with_key_ring(ring2.clone());
+        for &(p, content) in &[(old, "old key"), (new, "new key")] {
+            let mut s = String::new();
+            env2.open_sequential_file(p)
+                .unwrap()
+                .read_to_string(&mut s)
+                .unwrap();
+            assert_eq!(content, s);
+            assert_eq!(7, env2.size_of(p).unwrap());
+        }
+        env2.rename(old, Path::new("keyring_renamed.txt")).unwrap();
+        assert!(env2
+            .open_random_access_file(Path::new("keyring_renamed.txt"))
+            .is_ok());
+
+        ring2.remove_keys_before(1);
+        assert_eq!(vec![1], ring2.generations());
+        assert!(env2
+            .open_sequential_file(Path::new("keyring_renamed.txt"))
+            .is_err());
+        assert!(env2.open_sequential_file(new).is_ok());
+
+        // Only the current key is known.
+        let ring3 = KeyRing::with_generation(1, [2; 16]);
+        let env3 = PosixDiskEnv::new_with_key_ring(ring3.clone());
+        assert!(env3.open_sequential_file(new).is_ok());
+        assert!(env3
+            .open_sequential_file(Path::new("keyring_renamed.txt"))
+            .is_err());
+
+        // Older keys can be added later, but not as the current or a newer generation.
+        assert!(ring3.add_old_key(1, [1; 16]).is_err());
+        assert!(ring3.add_old_master_key(2, b"master key").is_err());
+        ring3.add_old_key(0, [1; 16]).unwrap();
+        assert_eq!(vec![0, 1], ring3.generations());
+        assert!(env3
+            .open_sequential_file(Path::new("keyring_renamed.txt"))
+            .is_ok());
+
+        env.delete(Path::new("keyring_renamed.txt")).unwrap();
+        env.delete(new).unwrap();
+    }
+
+    fn test_key_ring_derived_keys() {
+        let provider = StaticKeyProvider::new(b"master key");
+        let env = PosixDiskEnv::new_with_provider(&provider, b"db").unwrap();
+        let (a, b) = (Path::new("derived_a.ldb"), Path::new("derived_b.ldb"));
+        for p in &[a, b] {
+            env.open_writable_file(p)
+                .unwrap()
+                .write_all(b"contents")
+                .unwrap();
+        }
+
+        // Each file has its own key.
+        let key_a = derive_file_key(b"master key", b"db", "derived_a.ldb");
+        assert!(PosixDiskEnv::new_with(key_a)
+            .open_sequential_file(a)
+            .is_ok());
+        assert!(PosixDiskEnv::new_with(key_a)
+            .open_sequential_file(b)
+            .is_err());
+        assert!(PosixDiskEnv::new_with([0; 16])
+            .open_sequential_file(a)
+            .is_err());
+        // Another database with the same master key has different file keys.
+        assert!(PosixDiskEnv::new_with_provider(&provider, b"other db")
+            .unwrap()
+            .open_sequential_file(a)
+            .is_err());
+
+        // Temporary files share their key with CURRENT, so renaming keeps them readable.
+        let (tmp, current) = (Path::new("000007.dbtmp"), Path::new("CURRENT"));
+        env.open_writable_file(tmp)
+            .unwrap()
+            .write_all(b"MANIFEST-000006\n")
+            .unwrap();
+        env.rename(tmp, current).unwrap();
+        let env2 = PosixDiskEnv::new_with_provider(&provider, b"db").unwrap();
+        assert_eq!(16, env2.size_of(current).unwrap());
+
+        // Other renames re-encrypt the file with the key of its new name.
+        let c = Path::new("derived_c.ldb");
+        env.rename(b, c).unwrap();
+        assert!(!env.exists(b).unwrap());
+        let mut s = String::new();
+        env2.open_sequential_file(c)
+            .unwrap()
+            .read_to_string(&mut s)
+            .unwrap();
+        assert_eq!("contents", s);
+
+        // A ring with a shared key and a master key reads both kinds of files.
+        let ring = KeyRing::new([3; 16]);
+        let shared = PosixDiskEnv::new_with_key_ring(ring.clone());
+        let d = Path::new("derived_d.ldb");
+        shared
+            .open_writable_file(d)
+            .unwrap()
+            .write_all(b"shared")
+            .unwrap();
+        assert_eq!(1, ring.add_master_key(b"master key"));
+        assert!(shared.open_sequential_file(c).is_ok());
+        assert!(shared.open_sequential_file(d).is_ok());
+
+        for p in &[a, c, d, current] {
+            env.delete(p).unwrap();
+        }
+    }
 }
@@ -0,0 +1,754 @@
+//! An Env wrapping another Env, which encrypts all files at rest in software. It is meant for
+//! deployments outside of an enclave, where PosixDiskEnv's protected files are not available.
+//!
+//! Every file starts with a header containing a random salt, from which the file's data keys are
+//! derived using the master key of a KeyProvider. The contents follow as a sequence of records,
+//! each encrypted with AES-256-CTR and authenticated with HMAC-SHA256:
+//!
+//! [ magic (8 bytes) | salt (32 bytes) | header MAC (32 bytes) ]
+//! [ length (4 bytes, LE) | ciphertext (length bytes) | MAC (32 bytes) ] ...
+//!
+//! Records hold at most RECORD_SIZE bytes; a record is written whenever that much data is
+//! buffered, and when the file is flushed. The MAC of a record covers its index in the file, so
+//! that records can't be reordered; the index also determines the record's counter block.
+//!
+//! The number of records is not authenticated: a file truncated at a record boundary reads like
+//! a shorter, valid file, and a torn record at the end is ignored (which is what lets the log
+//! survive a crash). Neither is replacing a file with an older version of itself detected. Table
+//! contents are additionally covered by the hashes in the manifest (see Options::paranoid_checks
+//! and DB::state_digest()), but the log and the manifest are not.
+#[cfg(feature = "mesalock_sgx")]
+use std::prelude::v1::*;
+
+use crate::env::{path_to_str, Env, FileLock, Logger, RandomAccess};
+use crate::error::{err, Result, StatusCode};
+use crate::key_provider::BoxedKeyProvider;
+
+use std::cmp;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use aes::Aes256;
+use cipher::generic_array::GenericArray;
+use cipher::stream::{NewStreamCipher, SyncStreamCipher};
+use hmac::{Hmac, Mac, NewMac};
+use integer_encoding::FixedInt;
+use rand::RngCore;
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+type Aes256Ctr = ctr::Ctr128<Aes256>;
+
+const MAGIC: &[u8; 8] = b"LDBENC01";
+pub(crate) const SALT_LEN: usize = 32;
+pub(crate) const MAC_LEN: usize = 32;
+const HEADER_LEN: usize = 8 + SALT_LEN + MAC_LEN;
+const RECORD_LEN_LEN: usize = 4;
+/// The maximum number of plaintext bytes in a record.
+pub const RECORD_SIZE: usize = 4096;
+
+fn hmac(key: &[u8], parts: &[&[u8]]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_varkey(key).expect("HMAC accepts keys of any length");
+    for p in parts {
+        mac.update(p);
+    }
+    mac
+}
+
+fn corruption<T>(p: &Path, msg: &str) -> Result<T> {
+    err(
+        StatusCode::Corruption,
+        &format!("{}: {}", msg, path_to_str(p)),
+    )
+}
+
+/// Returns a new random salt.
+pub(crate) fn new_salt() -> [u8; SALT_LEN] {
+    let mut salt = [0; SALT_LEN];
+    rand::thread_rng().fill_bytes(&mut salt);
+    salt
+}
+
+/// The data keys of one file.
+#[derive(Clone)]
+pub(crate) struct FileKeys {
+    enc: Vec<u8>,
+    mac: Vec<u8>,
+}
+
+impl FileKeys {
+    pub(crate) fn derive(master: &[u8], salt: &[u8]) -> FileKeys {
+        let enc = hmac(master, &[b"leveldb encryption key", salt]);
+        let mac = hmac(master, &[b"leveldb authentication key", salt]);
+        FileKeys {
+            enc: enc.finalize().into_bytes().to_vec(),
+            mac: mac.finalize().into_bytes().to_vec(),
+        }
+    }
+
+    /// Returns the MAC over the given data.
+    pub(crate) fn mac(&self, parts: &[&[u8]]) -> HmacSha256 {
+        hmac(&self.mac, parts)
+    }
+
+    /// Returns the MAC over the header fields, which verifies that the master key is correct.
+    fn header_mac(&self, salt: &[u8]) -> HmacSha256 {
+        hmac(&self.mac, &[MAGIC, salt])
+    }
+
+    fn record_mac(&self, index: u64, ciphertext: &[u8]) -> HmacSha256 {
+        hmac(
+            &self.mac,
+            &[
+                &index.encode_fixed_vec(),
+                &(ciphertext.len() as u32).encode_fixed_vec(),
+                ciphertext,
+            ],
+        )
+    }
+
+    /// Encrypts or decrypts the record with the given index in place.
+    pub(crate) fn apply_keystream(&self, index: u64, data: &mut [u8]) {
+        let mut nonce = [0; 16];
+        nonce[..8].copy_from_slice(&index.to_be_bytes());
+        let mut c = Aes256Ctr::new(
+            GenericArray::from_slice(&self.enc),
+            GenericArray::from_slice(&nonce),
+        );
+        c.apply_keystream(data);
+    }
+
+    /// Returns the encrypted record with the given index, containing data.
+    fn seal(&self, index: u64, data: &[u8]) -> Vec<u8> {
+        let mut rec = Vec::with_capacity(RECORD_LEN_LEN + data.len() + MAC_LEN);
+        rec.extend_from_slice(&(data.len() as u32).encode_fixed_vec());
+        rec.extend_from_slice(data);
+        self.apply_keystream(index, &mut rec[RECORD_LEN_LEN..]);
+        let mac = self.record_mac(index, &rec[RECORD_LEN_LEN..]);
+        rec.extend_from_slice(&mac.finalize().into_bytes());
+        rec
+    }
+
+    /// Verifies and decrypts the record with the given index, consisting of the ciphertext
+    /// followed by the MAC.
+    fn open(&self, p: &Path, index: u64, rec: &mut [u8]) -> Result<()> {
+        let (ciphertext, mac) = rec.split_at_mut(rec.len() - MAC_LEN);
+        if self.record_mac(index, ciphertext).verify(mac).is_err() {
+            return corruption(p, &format!("MAC mismatch in record {}", index));
+        }
+        self.apply_keystream(index, ciphertext);
+        Ok(())
+    }
+}
+
+/// Returns a header for a new file and its keys.
+fn new_header(provider: &BoxedKeyProvider) -> Result<(Vec<u8>, FileKeys)> {
+    let salt = new_salt();
+    let keys = FileKeys::derive(&provider.master_key()?, &salt);
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    header.extend_from_slice(MAGIC);
+    header.extend_from_slice(&salt);
+    header.extend_from_slice(&keys.header_mac(&salt).finalize().into_bytes());
+    Ok((header, keys))
+}
+
+/// Verifies a file header and returns the file's keys.
+fn parse_header(provider: &BoxedKeyProvider, p: &Path, header: &[u8]) -> Result<FileKeys> {
+    if &header[..MAGIC.len()] != MAGIC {
+        return corruption(p, "not an encrypted file");
+    }
+    let salt = &header[MAGIC.len()..MAGIC.len() + SALT_LEN];
+    let keys = FileKeys::derive(&provider.master_key()?, salt);
+    if keys
+        .header_mac(salt)
+        .verify(&header[MAGIC.len() + SALT_LEN..HEADER_LEN])
+        .is_err()
+    {
+        return corruption(p, "wrong key or corrupted header");
+    }
+    Ok(keys)
+}
+
+/// The location of a record in an encrypted file.
+#[derive(Clone, Copy, Debug)]
+struct Record {
+    /// Offset of the record's plaintext in the decrypted file.
+    offset: usize,
+    /// Offset of the ciphertext in the encrypted file.
+    physical: usize,
+    len: usize,
+}
+
+/// An encrypted file opened for random access, with the location of all its records.
+struct EncryptedFile {
+    path: PathBuf,
+    inner: Box<dyn RandomAccess>,
+    keys: Option<FileKeys>,
+    records: Vec<Record>,
+    /// The physical size of the complete records, a torn record at the end is not included.
+    physical_size: usize,
+}
+
+impl EncryptedFile {
+    fn open(env: &dyn Env, provider: &BoxedKeyProvider, p: &Path) -> Result<EncryptedFile> {
+        let size = env.size_of(p)?;
+        let inner = env.open_random_access_file(p)?;
+        let mut f = EncryptedFile {
+            path: p.to_path_buf(),
+            inner,
+            keys: None,
+            records: vec![],
+            physical_size: 0,
+        };
+        let mut header = [0; HEADER_LEN];
+        if f.inner.read_at(0, &mut header)? < HEADER_LEN {
+            // The header is incomplete, so no records were written.
+            return Ok(f);
+        }
+        f.keys = Some(parse_header(provider, p, &header)?);
+
+        let (mut offset, mut physical) = (0, HEADER_LEN);
+        let mut len = [0; RECORD_LEN_LEN];
+        while physical + RECORD_LEN_LEN <= size {
+            f.inner.read_at(physical, &mut len)?;
+            let l = u32::decode_fixed(&len) as usize;
+            if l > RECORD_SIZE {
+                return corruption(p, "invalid record length");
+            }
+            if physical + RECORD_LEN_LEN + l + MAC_LEN > size {
+                break;
+            }
+            f.records.push(Record {
+                offset,
+                physical: physical + RECORD_LEN_LEN,
+                len: l,
+            });
+            offset += l;
+            physical += RECORD_LEN_LEN + l + MAC_LEN;
+        }
+        f.physical_size = physical;
+        Ok(f)
+    }
+
+    fn size(&self) -> usize {
+        self.records.last().map(|r| r.offset + r.len).unwrap_or(0)
+    }
+
+    fn read_record(&self, index: usize) -> Result<Vec<u8>> {
+        let r = self.records[index];
+        let mut rec = vec![0; r.len + MAC_LEN];
+        if self.inner.read_at(r.physical, &mut rec)? < rec.len() {
+            return corruption(&self.path, "short read");
+        }
+        // The keys are set if there are any records.
+        self.keys
+            .as_ref()
+            .unwrap()
+            .open(&self.path, index as u64, &mut rec)?;
+        rec.truncate(r.len);
+        Ok(rec)
+    }
+}
+
+impl RandomAccess for EncryptedFile {
+    fn read_at(&self, off: usize, dst: &mut [u8]) -> Result<usize> {
+        // The first record ending after off.
+        let mut ix = match self
+            .records
+            .binary_search_by(|r| (r.offset + r.len).cmp(&off))
+        {
+            Ok(i) => i + 1,
+            Err(i) => i,
+        };
+        let mut read = 0;
+        while read < dst.len() && ix < self.records.len() {
+            let r = self.records[ix];
+            let data = self.read_record(ix)?;
+            let from = off + read - r.offset;
+            let n = cmp::min(dst.len() - read, r.len - from);
+            dst[read..read + n].copy_from_slice(&data[from..from + n]);
+            read += n;
+            ix += 1;
+        }
+        Ok(read)
+    }
+}
+
+fn to_io_error(e: crate::error::Status) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.err)
+}
+
+/// Reads until dst is full or the end of the file is reached, and returns the number of bytes
+/// read.
+fn read_full(r: &mut dyn Read, dst: &mut [u8]) -> io::Result<usize> {
+    let mut n = 0;
+    while n < dst.len() {
+        match r.read(&mut dst[n..])? {
+            0 => break,
+            m => n += m,
+        }
+    }
+    Ok(n)
+}
+
+/// EncryptedReader decrypts a file sequentially, one record at a time. A torn record at the end
+/// of the file is treated like the end of the file.
+struct EncryptedReader {
+    path: PathBuf,
+    inner: Box<dyn Read>,
+    keys: Option<FileKeys>,
+    index: u64,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl EncryptedReader {
+    /// Reads the next record into buf, returning false at the end of the file.
+    fn next_record(&mut self) -> io::Result<bool> {
+        let keys = match self.keys {
+            Some(ref k) => k,
+            None => return Ok(false),
+        };
+        let mut len = [0; RECORD_LEN_LEN];
+        if read_full(self.inner.as_mut(), &mut len)? < RECORD_LEN_LEN {
+            return Ok(false);
+        }
+        let l = u32::decode_fixed(&len) as usize;
+        if l > RECORD_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid record length: {}", path_to_str(&self.path)),
+            ));
+        }
+        let mut rec = vec![0; l + MAC_LEN];
+        if read_full(self.inner.as_mut(), &mut rec)? < rec.len() {
+            return Ok(false);
+        }
+        keys.open(&self.path, self.index, &mut rec)
+            .map_err(to_io_error)?;
+        rec.truncate(l);
+        self.index += 1;
+        self.buf = rec;
+        self.pos = 0;
+        Ok(true)
+    }
+}
+
+impl Read for EncryptedReader {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.buf.len() {
+            if dst.is_empty() || !self.next_record()? {
+                return Ok(0);
+            }
+        }
+        let n = cmp::min(dst.len(), self.buf.len() - self.pos);
+        dst[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// EncryptedWriter buffers data until a record is full or the file is flushed. Data still
+/// buffered when it is dropped is written, too.
+struct EncryptedWriter {
+    inner: Box<dyn Write>,
+    keys: FileKeys,
+    index: u64,
+    buf: Vec<u8>,
+}
+
+impl EncryptedWriter {
+    fn write_record(&mut self, n: usize) -> io::Result<()> {
+        let rec = self.keys.seal(self.index, &self.buf[..n]);
+        self.inner.write_all(&rec)?;
+        self.buf.drain(..n);
+        self.index += 1;
+        Ok(())
+    }
+
+    fn write_buffered(&mut self) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            self.write_record(self.buf.len())?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for EncryptedWriter {
+    fn write(&mut self, src: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(src);
+        while self.buf.len() >= RECORD_SIZE {
+            self.write_record(RECORD_SIZE)?;
+        }
+        Ok(src.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_buffered()?;
+        self.inner.flush()
+    }
+}
+
+impl Drop for EncryptedWriter {
+    fn drop(&mut self) {
+        let _ = self.write_buffered();
+    }
+}
+
+/// EncryptedEnv encrypts the files of another Env, using keys derived from the master key of a
+/// KeyProvider. Sizes reported by size_of() are those of the decrypted files.
+pub struct EncryptedEnv {
+    inner: Box<dyn Env>,
+    provider: BoxedKeyProvider,
+}
+
+impl EncryptedEnv {
+    pub fn new(inner: Box<dyn Env>, provider: BoxedKeyProvider) -> EncryptedEnv {
+        EncryptedEnv { inner, provider }
+    }
+
+    fn open_encrypted(&self, p: &Path) -> Result<EncryptedFile> {
+        EncryptedFile::open(self.inner.as_ref(), &self.provider, p)
+    }
+
+    /// Creates or truncates p and writes a new header to it.
+    fn create(&self, p: &Path) -> Result<EncryptedWriter> {
+        let mut w = self.inner.open_writable_file(p)?;
+        let (header, keys) = new_header(&self.provider)?;
+        w.write_all(&header)?;
+        Ok(EncryptedWriter {
+            inner: w,
+            keys,
+            index: 0,
+            buf: vec![],
+        })
+    }
+
+    /// Replaces p by a new file containing contents, and returns a writer appending to it. The
+    /// new file is written under a temporary name first, so that p stays intact if this fails.
+    fn rewrite(&self, p: &Path, contents: &[u8]) -> Result<EncryptedWriter> {
+        let tmp = PathBuf::from(format!("{}.rewrite", path_to_str(p)));
+        let (keys, index) = {
+            let mut w = self.create(&tmp)?;
+            let written: Result<()> = w
+                .write_all(contents)
+                .and_then(|_| w.flush())
+                .map_err(Into::into);
+            if let Err(e) = written {
+                drop(w);
+                let _ = self.inner.delete(&tmp);
+                return Err(e);
+            }
+            (w.keys.clone(), w.index)
+        };
+        if let Err(e) = self.inner.rename(&tmp, p) {
+            let _ = self.inner.delete(&tmp);
+            return Err(e);
+        }
+        Ok(EncryptedWriter {
+            inner: self.inner.open_appendable_file(p)?,
+            keys,
+            index,
+            buf: vec![],
+        })
+    }
+}
+
+impl Env for EncryptedEnv {
+    fn open_sequential_file(&self, p: &Path) -> Result<Box<dyn Read>> {
+        let mut inner = self.inner.open_sequential_file(p)?;
+        let mut header = [0; HEADER_LEN];
+        let keys = if read_full(inner.as_mut(), &mut header)? < HEADER_LEN {
+            // The header is incomplete, so no records were written.
+            None
+        } else {
+            Some(parse_header(&self.provider, p, &header)?)
+        };
+        Ok(Box::new(EncryptedReader {
+            path: p.to_path_buf(),
+            inner,
+            keys,
+            index: 0,
+            buf: vec![],
+            pos: 0,
+        }))
+    }
+    fn open_random_access_file(&self, p: &Path) -> Result<Box<dyn RandomAccess>> {
+        Ok(Box::new(self.open_encrypted(p)?))
+    }
+    fn open_writable_file(&self, p: &Path) -> Result<Box<dyn Write>> {
+        Ok(Box::new(self.create(p)?))
+    }
+    fn open_appendable_file(&self, p: &Path) -> Result<Box<dyn Write>> {
+        if !self.inner.exists(p)? {
+            return self.open_writable_file(p);
+        }
+        let f = self.open_encrypted(p)?;
+        let keys = match f.keys {
+            Some(ref k) if f.physical_size == self.inner.size_of(p)? => k.clone(),
+            _ => {
+                // The header or the last record is incomplete. New records can't follow it, so
+                // the file is rewritten with the complete records.
+                let mut contents = Vec::with_capacity(f.size());
+                for i in 0..f.records.len() {
+                    contents.extend_from_slice(&f.read_record(i)?);
+                }
+                return Ok(Box::new(self.rewrite(p, &contents)?));
+            }
+        };
+        Ok(Box::new(EncryptedWriter {
+            inner: self.inner.open_appendable_file(p)?,
+            keys,
+            index: f.records.len() as u64,
+            buf: vec![],
+        }))
+    }
+
+    fn exists(&self, p: &Path) -> Result<bool> {
+        self.inner.exists(p)
+    }
+    fn children(&self, p: &Path) -> Result<Vec<PathBuf>> {
+        self.inner.children(p)
+    }
+    fn size_of(&self, p: &Path) -> Result<usize> {
+        Ok(self.open_encrypted(p)?.size())
+    }
+
+    fn delete(&self, p: &Path) -> Result<()> {
+        self.inner.delete(p)
+    }
+    fn mkdir(&self, p: &Path) -> Result<()> {
+        self.inner.mkdir(p)
+    }
+    fn rmdir(&self, p: &Path) -> Result<()> {
+        self.inner.rmdir(p)
+    }
+    fn rename(&self, old: &Path, new: &Path) -> Result<()> {
+        self.inner.rename(old, new)
+    }
+
+    fn lock(&self, p: &Path) -> Result<FileLock> {
+        self.inner.lock(p)
+    }
+    fn unlock(&self, l: FileLock) -> Result<()> {
+        self.inner.unlock(l)
+    }
+
+    fn new_logger(&self, p: &Path) -> Result<Logger> {
+        self.open_appendable_file(p)
+            .map(|dst| Logger::new(Box::new(dst)))
+    }
+
+    fn micros(&self) -> u64 {
+        self.inner.micros()
+    }
+    fn sleep_for(&self, micros: u32) -> Result<()> {
+        self.inner.sleep_for(micros)
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use crate::db_impl::DB;
+    use crate::fault_env::{FaultInjectionEnv, FaultOp};
+    use crate::key_provider::StaticKeyProvider;
+    use crate::mem_env::MemEnv;
+    use crate::options;
+    use std::rc::Rc;
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_encrypted_env_read_write,
+            test_encrypted_env_append,
+            test_encrypted_env_torn_record,
+            test_encrypted_env_wrong_key,
+            test_encrypted_env_tampering,
+            test_encrypted_env_db,
+        )
+    }
+
+    /// Returns an EncryptedEnv and the MemEnv it writes to. MemEnv clones share their files.
+    fn new_env(key: &[u8]) -> (EncryptedEnv, MemEnv) {
+        let mem = MemEnv::new();
+        let env = EncryptedEnv::new(Box::new(mem.clone()), StaticKeyProvider::new(key));
+        (env, mem)
+    }
+
+    fn read_all(env: &dyn Env, p: &Path) -> Result<Vec<u8>> {
+        let mut v = vec![];
+        env.open_sequential_file(p)?.read_to_end(&mut v)?;
+        Ok(v)
+    }
+
+    fn contents(n: usize) -> Vec<u8> {
+        (0..n).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn test_encrypted_env_read_write() {
+        let (env, mem) = new_env(b"key");
+        let p = Path::new("file");
+        let data = contents(3 * RECORD_SIZE + 100);
+        {
+            let mut w = env.open_writable_file(p).unwrap();
+            w.write_all(&data[..10]).unwrap();
+            w.flush().unwrap();
+            w.write_all(&data[10..]).unwrap();
+        }
+        assert_eq!(data.len(), env.size_of(p).unwrap());
+        assert!(mem.size_of(p).unwrap() > data.len() + HEADER_LEN);
+        assert_eq!(data, read_all(&env, p).unwrap());
+        // The plaintext doesn't appear in the file.
+        let raw = read_all(&mem, p).unwrap();
+        assert!(!raw.windows(16).any(|w| w == &data[100..116]));
+
+        let f = env.open_random_access_file(p).unwrap();
+        for &(off, len) in &[
+            (0, 5),
+            (5, 10),
+            (8, 2 * RECORD_SIZE),
+            (RECORD_SIZE, RECORD_SIZE),
+            (data.len() - 50, 100),
+            (data.len(), 10),
+        ] {
+            let mut buf = vec![0; len];
+            let n = f.read_at(off, &mut buf).unwrap();
+            let end = cmp::min(off + len, data.len());
+            assert_eq!(end - off, n);
+            assert_eq!(&data[off..end], &buf[..n]);
+        }
+
+        // Files with the same contents are encrypted differently.
+        env.open_writable_file(Path::new("other"))
+            .unwrap()
+            .write_all(&data)
+            .unwrap();
+        assert!(read_all(&mem, Path::new("other")).unwrap() != raw);
+    }
+
+    fn test_encrypted_env_append() {
+        let (env, _) = new_env(b"key");
+        let p = Path::new("log");
+        let data = contents(2 * RECORD_SIZE);
+        for chunk in data.chunks(1000) {
+            let mut w = env.open_appendable_file(p).unwrap();
+            w.write_all(chunk).unwrap();
+            w.flush().unwrap();
+        }
+        assert_eq!(data, read_all(&env, p).unwrap());
+        assert_eq!(data.len(), env.size_of(p).unwrap());
+        let mut buf = vec![0; 1500];
+        let f = env.open_random_access_file(p).unwrap();
+        assert_eq!(1500, f.read_at(2500, &mut buf).unwrap());
+        assert_eq!(&data[2500..4000], &buf[..]);
+    }
+
+    fn test_encrypted_env_torn_record() {
+        let (env, mem) = new_env(b"key");
+        let p = Path::new("log");
+        {
+            let mut w = env.open_writable_file(p).unwrap();
+            w.write_all(b"complete").unwrap();
+            w.flush().unwrap();
+        }
+        // Simulate a crash in the middle of writing a record.
+        let keys = env.open_encrypted(p).unwrap().keys.unwrap();
+        let torn = keys.seal(1, b"torn record");
+        mem.open_appendable_file(p)
+            .unwrap()
+            .write_all(&torn[..10])
+            .unwrap();
+
+        assert_eq!(b"complete".to_vec(), read_all(&env, p).unwrap());
+        assert_eq!(8, env.size_of(p).unwrap());
+
+        // If rewriting the file fails, it is left as it was.
+        let fault = FaultInjectionEnv::new(Box::new(mem.clone()));
+        let failing = EncryptedEnv::new(Box::new(fault.clone()), StaticKeyProvider::new(b"key"));
+        fault.fail_after(FaultOp::Rename, 0);
+        assert!(failing.open_appendable_file(p).is_err());
+        assert_eq!(b"complete".to_vec(), read_all(&env, p).unwrap());
+        assert!(!mem.exists(Path::new("log.rewrite")).unwrap());
+        {
+            let mut w = env.open_appendable_file(p).unwrap();
+            w.write_all(b" and more").unwrap();
+        }
+        assert_eq!(b"complete and more".to_vec(), read_all(&env, p).unwrap());
+    }
+
+    fn test_encrypted_env_wrong_key() {
+        let (env, mem) = new_env(b"key");
+        let p = Path::new("file");
+        env.open_writable_file(p)
+            .unwrap()
+            .write_all(b"secret")
+            .unwrap();
+
+        let other = EncryptedEnv::new(Box::new(mem.clone()), StaticKeyProvider::new(b"other"));
+        assert_eq!(
+            StatusCode::Corruption,
+            other.open_random_access_file(p).err().unwrap().code
+        );
+        assert!(other.open_sequential_file(p).is_err());
+        assert!(other.open_appendable_file(p).is_err());
+
+        // Unencrypted files are rejected as well.
+        mem.open_writable_file(Path::new("plain"))
+            .unwrap()
+            .write_all(&[0; 100])
+            .unwrap();
+        assert!(env.size_of(Path::new("plain")).is_err());
+    }
+
+    fn test_encrypted_env_tampering() {
+        let (env, mem) = new_env(b"key");
+        let p = Path::new("file");
+        let data = contents(2 * RECORD_SIZE);
+        env.open_writable_file(p).unwrap().write_all(&data).unwrap();
+
+        let mut raw = read_all(&mem, p).unwrap();
+        raw[HEADER_LEN + RECORD_LEN_LEN + RECORD_SIZE + MAC_LEN + 20] ^= 1;
+        mem.open_writable_file(p).unwrap().write_all(&raw).unwrap();
+
+        let f = env.open_random_access_file(p).unwrap();
+        let mut buf = [0; 10];
+        assert!(f.read_at(0, &mut buf).is_ok());
+        assert_eq!(
+            StatusCode::Corruption,
+            f.read_at(RECORD_SIZE, &mut buf).unwrap_err().code
+        );
+        assert!(read_all(&env, p).is_err());
+    }
+
+    fn test_encrypted_env_db() {
+        let mem = MemEnv::new();
+        let mut opt = options::for_test();
+        opt.env = Rc::new(Box::new(EncryptedEnv::new(
+            Box::new(mem.clone()),
+            StaticKeyProvider::new(b"database key"),
+        )));
+        opt.write_buffer_size = 1024;
+        {
+            let mut db = DB::open("db", opt.clone()).unwrap();
+            for i in 0..200 {
+                db.put(format!("key{:03}", i).as_bytes(), b"secret value")
+                    .unwrap();
+            }
+            db.compact_range(b"key000", b"key100").unwrap();
+            db.flush().unwrap();
+        }
+
+        let mut db = DB::open("db", opt).unwrap();
+        for i in 0..200 {
+            assert_eq!(
+                Some(b"secret value".to_vec()),
+                db.get(format!("key{:03}", i).as_bytes())
+            );
+        }
+        for name in mem.children(Path::new("db")).unwrap() {
+            let raw = read_all(&mem, &Path::new("db").join(name)).unwrap();
+            assert!(!raw.windows(12).any(|w| w == b"secret value"));
+        }
+    }
+}
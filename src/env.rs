@@ -3,9 +3,10 @@
 #[cfg(feature = "mesalock_sgx")]
 use std::prelude::v1::*;
 
+use crate::env_common;
 use crate::error::Result;
 
-use std::io::prelude::*;
+use std::io::{self, prelude::*};
 use std::path::{Path, PathBuf};
 
 use crate::error::Status;
@@ -21,6 +22,29 @@ impl RandomAccess for ProtectedFile {
     }
 }
 
+/// RandomAccessReader reads a RandomAccess file sequentially, starting at a given offset.
+pub struct RandomAccessReader {
+    file: Box<dyn RandomAccess>,
+    off: usize,
+}
+
+impl RandomAccessReader {
+    pub fn new(file: Box<dyn RandomAccess>, off: usize) -> RandomAccessReader {
+        RandomAccessReader { file, off }
+    }
+}
+
+impl Read for RandomAccessReader {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        let n = self
+            .file
+            .read_at(self.off, dst)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.err))?;
+        self.off += n;
+        Ok(n)
+    }
+}
+
 pub struct FileLock {
     pub id: String,
 }
@@ -46,6 +70,12 @@ pub trait Env {
     fn new_logger(&self, p: &Path) -> Result<Logger>;
 
     fn micros(&self) -> u64;
+    /// Blocks for (at least) the given number of microseconds. The default implementation waits
+    /// in real time outside of an enclave; inside one it fails with StatusCode::NotSupported, and
+    /// an Env that is used with a RateLimiter must provide its own.
+    fn sleep_for(&self, micros: u32) -> Result<()> {
+        env_common::sleep_for(micros)
+    }
 }
 
 pub struct Logger {
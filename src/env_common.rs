@@ -1,7 +1,15 @@
 #[cfg(feature = "mesalock_sgx")]
 use std::prelude::v1::*;
 
+use crate::error::Result;
+#[cfg(feature = "mesalock_sgx")]
+use crate::error::{err, StatusCode};
+
+#[cfg(not(feature = "mesalock_sgx"))]
+use std::thread;
 use std::time;
+#[cfg(not(feature = "mesalock_sgx"))]
+use std::time::Duration;
 #[cfg(feature = "mesalock_sgx")]
 use std::untrusted::time::SystemTimeEx;
 
@@ -15,3 +23,20 @@ pub fn micros() -> u64 {
         }
     }
 }
+
+#[cfg(not(feature = "mesalock_sgx"))]
+pub fn sleep_for(micros: u32) -> Result<()> {
+    thread::sleep(Duration::from_micros(micros as u64));
+    Ok(())
+}
+
+/// Inside an enclave, std::thread::sleep is only available with the `thread` feature of
+/// sgx_tstd, and waiting on the untrusted clock would spin with an OCALL per iteration. Sleeping
+/// is left to the embedder, which can provide it in its Env.
+#[cfg(feature = "mesalock_sgx")]
+pub fn sleep_for(_micros: u32) -> Result<()> {
+    err(
+        StatusCode::NotSupported,
+        "sleeping inside an enclave requires an Env implementing sleep_for()",
+    )
+}
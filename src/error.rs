@@ -18,6 +18,8 @@ pub enum StatusCode {
     OK,
 
     AlreadyExists,
+    /// A transaction could not be committed because a key it depends on was modified.
+    Conflict,
     Corruption,
     CompressionError,
     IOError,
@@ -27,6 +29,8 @@ pub enum StatusCode {
     NotFound,
     NotSupported,
     PermissionDenied,
+    /// The files of a database are older than its monotonic counter.
+    Rollback,
     Unknown,
     Errno(c_int),
 }
@@ -0,0 +1,478 @@
+//! An Env wrapping another Env, which can inject errors into file system operations and simulate
+//! crashes by dropping data that hasn't been flushed yet. It is meant for testing.
+#[cfg(feature = "mesalock_sgx")]
+use std::prelude::v1::*;
+
+use crate::env::{path_to_str, Env, FileLock, Logger, RandomAccess};
+use crate::error::{err, Result, StatusCode};
+use crate::types::{share, Shared};
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// FaultOp names the operations that a FaultInjectionEnv can make fail.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FaultOp {
+    OpenWritable,
+    Rename,
+    Delete,
+    ReadAt,
+}
+
+/// The length of a file written through the env, and its length when it was last flushed.
+struct FileState {
+    size: usize,
+    synced: usize,
+}
+
+struct FaultState {
+    files: HashMap<PathBuf, FileState>,
+    // For every operation with injected errors, the number of calls that still succeed.
+    countdowns: HashMap<FaultOp, usize>,
+    injected: usize,
+}
+
+impl FaultState {
+    fn maybe_fail(&mut self, op: FaultOp, p: &Path) -> Result<()> {
+        if let Some(n) = self.countdowns.get_mut(&op) {
+            if *n == 0 {
+                self.injected += 1;
+                return err(
+                    StatusCode::IOError,
+                    &format!("injected fault in {:?}: {}", op, path_to_str(p)),
+                );
+            }
+            *n -= 1;
+        }
+        Ok(())
+    }
+}
+
+/// FaultInjectionEnv wraps another Env. Clones share their state, so that a test can keep a
+/// clone in order to control an env used by a database.
+///
+/// Flushing a file opened for writing counts as syncing it: drop_unsynced_data() truncates every
+/// file to its length at the last flush, as it would be after a crash.
+#[derive(Clone)]
+pub struct FaultInjectionEnv {
+    inner: Rc<Box<dyn Env>>,
+    state: Shared<FaultState>,
+}
+
+impl FaultInjectionEnv {
+    pub fn new(inner: Box<dyn Env>) -> FaultInjectionEnv {
+        FaultInjectionEnv {
+            inner: Rc::new(inner),
+            state: share(FaultState {
+                files: HashMap::new(),
+                countdowns: HashMap::new(),
+                injected: 0,
+            }),
+        }
+    }
+
+    /// fail_after lets the next n calls of op succeed; all following calls fail with
+    /// StatusCode::IOError until clear_faults() is called.
+    pub fn fail_after(&self, op: FaultOp, n: usize) {
+        self.state.borrow_mut().countdowns.insert(op, n);
+    }
+
+    /// clear_faults stops injecting errors.
+    pub fn clear_faults(&self) {
+        self.state.borrow_mut().countdowns.clear();
+    }
+
+    /// Returns the number of errors injected so far.
+    pub fn injected_faults(&self) -> usize {
+        self.state.borrow().injected
+    }
+
+    /// Returns the number of bytes written to p since it was last flushed.
+    pub fn unsynced_bytes(&self, p: &Path) -> usize {
+        self.state
+            .borrow()
+            .files
+            .get(p)
+            .map(|f| f.size - f.synced)
+            .unwrap_or(0)
+    }
+
+    /// drop_unsynced_data simulates a crash by truncating every file written through this env to
+    /// its length when it was last flushed. Files must not be written to concurrently.
+    pub fn drop_unsynced_data(&self) -> Result<()> {
+        let inner = &self.inner;
+        let mut state = self.state.borrow_mut();
+        for (path, f) in state.files.iter_mut() {
+            if f.size == f.synced || !inner.exists(path)? {
+                continue;
+            }
+            let mut contents = vec![0; f.synced];
+            inner
+                .open_sequential_file(path)?
+                .read_exact(&mut contents)?;
+            let mut w = inner.open_writable_file(path)?;
+            w.write_all(&contents)?;
+            w.flush()?;
+            f.size = f.synced;
+        }
+        Ok(())
+    }
+
+    fn writer(&self, p: &Path, w: Box<dyn Write>, size: usize) -> Box<dyn Write> {
+        self.state
+            .borrow_mut()
+            .files
+            .insert(p.to_path_buf(), FileState { size, synced: size });
+        Box::new(FaultWriter {
+            path: p.to_path_buf(),
+            inner: w,
+            state: self.state.clone(),
+        })
+    }
+}
+
+struct FaultWriter {
+    path: PathBuf,
+    inner: Box<dyn Write>,
+    state: Shared<FaultState>,
+}
+
+impl Write for FaultWriter {
+    fn write(&mut self, src: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(src)?;
+        if let Some(f) = self.state.borrow_mut().files.get_mut(&self.path) {
+            f.size += n;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()?;
+        if let Some(f) = self.state.borrow_mut().files.get_mut(&self.path) {
+            f.synced = f.size;
+        }
+        Ok(())
+    }
+}
+
+struct FaultRandomAccess {
+    path: PathBuf,
+    inner: Box<dyn RandomAccess>,
+    state: Shared<FaultState>,
+}
+
+impl RandomAccess for FaultRandomAccess {
+    fn read_at(&self, off: usize, dst: &mut [u8]) -> Result<usize> {
+        self.state
+            .borrow_mut()
+            .maybe_fail(FaultOp::ReadAt, &self.path)?;
+        self.inner.read_at(off, dst)
+    }
+}
+
+impl Env for FaultInjectionEnv {
+    fn open_sequential_file(&self, p: &Path) -> Result<Box<dyn Read>> {
+        self.inner.open_sequential_file(p)
+    }
+    fn open_random_access_file(&self, p: &Path) -> Result<Box<dyn RandomAccess>> {
+        let f = self.inner.open_random_access_file(p)?;
+        Ok(Box::new(FaultRandomAccess {
+            path: p.to_path_buf(),
+            inner: f,
+            state: self.state.clone(),
+        }))
+    }
+    fn open_writable_file(&self, p: &Path) -> Result<Box<dyn Write>> {
+        self.state
+            .borrow_mut()
+            .maybe_fail(FaultOp::OpenWritable, p)?;
+        let w = self.inner.open_writable_file(p)?;
+        Ok(self.writer(p, w, 0))
+    }
+    fn open_appendable_file(&self, p: &Path) -> Result<Box<dyn Write>> {
+        self.state
+            .borrow_mut()
+            .maybe_fail(FaultOp::OpenWritable, p)?;
+        let inner = &self.inner;
+        let w = inner.open_appendable_file(p)?;
+        // Data that was already there is assumed to be synced.
+        let size = inner.size_of(p)?;
+        Ok(self.writer(p, w, size))
+    }
+
+    fn exists(&self, p: &Path) -> Result<bool> {
+        self.inner.exists(p)
+    }
+    fn children(&self, p: &Path) -> Result<Vec<PathBuf>> {
+        self.inner.children(p)
+    }
+    fn size_of(&self, p: &Path) -> Result<usize> {
+        self.inner.size_of(p)
+    }
+
+    fn delete(&self, p: &Path) -> Result<()> {
+        self.state.borrow_mut().maybe_fail(FaultOp::Delete, p)?;
+        self.inner.delete(p)?;
+        self.state.borrow_mut().files.remove(p);
+        Ok(())
+    }
+    fn mkdir(&self, p: &Path) -> Result<()> {
+        self.inner.mkdir(p)
+    }
+    fn rmdir(&self, p: &Path) -> Result<()> {
+        self.inner.rmdir(p)
+    }
+    fn rename(&self, old: &Path, new: &Path) -> Result<()> {
+        self.state.borrow_mut().maybe_fail(FaultOp::Rename, old)?;
+        self.inner.rename(old, new)?;
+        let mut state = self.state.borrow_mut();
+        match state.files.remove(old) {
+            Some(f) => state.files.insert(new.to_path_buf(), f),
+            None => state.files.remove(new),
+        };
+        Ok(())
+    }
+
+    fn lock(&self, p: &Path) -> Result<FileLock> {
+        self.inner.lock(p)
+    }
+    fn unlock(&self, l: FileLock) -> Result<()> {
+        self.inner.unlock(l)
+    }
+
+    fn new_logger(&self, p: &Path) -> Result<Logger> {
+        self.inner.new_logger(p)
+    }
+
+    fn micros(&self) -> u64 {
+        self.inner.micros()
+    }
+    fn sleep_for(&self, micros: u32) -> Result<()> {
+        self.inner.sleep_for(micros)
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use crate::db_impl::DB;
+    use crate::mem_env::MemEnv;
+    use crate::options::{self, Options};
+    use crate::version_set::{set_current_file, temp_file_name};
+    use crate::write_batch::WriteBatch;
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_fault_env_unsynced_data,
+            test_fault_env_errors,
+            test_fault_env_set_current_file,
+            test_fault_env_crash_during_flush,
+            test_fault_env_crash_during_compaction,
+            test_fault_env_failed_compaction,
+        )
+    }
+
+    fn read_all(env: &dyn Env, p: &Path) -> Vec<u8> {
+        let mut v = vec![];
+        env.open_sequential_file(p)
+            .unwrap()
+            .read_to_end(&mut v)
+            .unwrap();
+        v
+    }
+
+    fn test_fault_env_unsynced_data() {
+        let env = FaultInjectionEnv::new(Box::new(MemEnv::new()));
+        let (a, b, c) = (Path::new("a"), Path::new("b"), Path::new("c"));
+        {
+            let mut w = env.open_writable_file(a).unwrap();
+            w.write_all(b"hello").unwrap();
+            w.flush().unwrap();
+            w.write_all(b" world").unwrap();
+            assert_eq!(6, env.unsynced_bytes(a));
+
+            let mut w = env.open_writable_file(b).unwrap();
+            w.write_all(b"never synced").unwrap();
+        }
+        {
+            // Existing contents of appended files are synced.
+            let mut w = env.open_appendable_file(a).unwrap();
+            assert_eq!(0, env.unsynced_bytes(a));
+            w.write_all(b"!").unwrap();
+            assert_eq!(1, env.unsynced_bytes(a));
+        }
+        env.rename(a, c).unwrap();
+        assert_eq!(0, env.unsynced_bytes(a));
+        assert_eq!(1, env.unsynced_bytes(c));
+        assert_eq!(b"hello world!".to_vec(), read_all(&env, c));
+
+        env.drop_unsynced_data().unwrap();
+        assert_eq!(b"hello world".to_vec(), read_all(&env, c));
+        assert_eq!(0, env.unsynced_bytes(c));
+        assert!(read_all(&env, b).is_empty());
+        assert!(!env.exists(a).unwrap());
+    }
+
+    fn test_fault_env_errors() {
+        let env = FaultInjectionEnv::new(Box::new(MemEnv::new()));
+        let (a, b) = (Path::new("a"), Path::new("b"));
+
+        env.fail_after(FaultOp::OpenWritable, 1);
+        env.open_writable_file(a)
+            .unwrap()
+            .write_all(b"abc")
+            .unwrap();
+        assert!(env.open_writable_file(b).is_err());
+        assert!(env.open_appendable_file(a).is_err());
+        assert_eq!(2, env.injected_faults());
+
+        env.fail_after(FaultOp::Rename, 0);
+        env.fail_after(FaultOp::Delete, 0);
+        assert_eq!(StatusCode::IOError, env.rename(a, b).unwrap_err().code);
+        assert!(env.delete(a).is_err());
+        assert!(env.exists(a).unwrap());
+
+        env.fail_after(FaultOp::ReadAt, 1);
+        let f = env.open_random_access_file(a).unwrap();
+        let mut buf = [0; 3];
+        assert_eq!(3, f.read_at(0, &mut buf).unwrap());
+        assert!(f.read_at(0, &mut buf).is_err());
+        assert_eq!(5, env.injected_faults());
+
+        env.clear_faults();
+        assert!(f.read_at(0, &mut buf).is_ok());
+        env.rename(a, b).unwrap();
+        env.delete(b).unwrap();
+        assert_eq!(5, env.injected_faults());
+    }
+
+    fn test_fault_env_set_current_file() {
+        let env = FaultInjectionEnv::new(Box::new(MemEnv::new()));
+        let benv: Box<dyn Env> = Box::new(env.clone());
+        let current = Path::new("db/CURRENT");
+
+        set_current_file(&benv, "db", 1).unwrap();
+        env.fail_after(FaultOp::Rename, 0);
+        assert!(set_current_file(&benv, "db", 2).is_err());
+        env.clear_faults();
+        // CURRENT still points to the old manifest, and survives a crash.
+        env.drop_unsynced_data().unwrap();
+        assert_eq!(b"MANIFEST-000001\n".to_vec(), read_all(&env, current));
+        assert!(!env.exists(&temp_file_name("db", 2)).unwrap());
+    }
+
+    /// Returns options using env, with small tables.
+    fn fault_options(env: &FaultInjectionEnv) -> Options {
+        let mut opt = options::for_test();
+        opt.env = Rc::new(Box::new(env.clone()));
+        opt.write_buffer_size = 1024;
+        opt.max_file_size = 512;
+        opt
+    }
+
+    fn key(i: usize) -> Vec<u8> {
+        format!("key{:04}", i).into_bytes()
+    }
+
+    /// Writes n synced entries, starting at i.
+    fn write_entries(db: &mut DB, from: usize, n: usize) {
+        for i in from..from + n {
+            let mut wb = WriteBatch::new();
+            wb.put(&key(i), format!("value{}", i).as_bytes());
+            db.write(wb, true).unwrap();
+        }
+    }
+
+    /// Simulates a crash of db, reopens it, and checks that the first n entries are present.
+    fn crash_and_check(db: DB, env: &FaultInjectionEnv, n: usize, ctx: &str) -> DB {
+        drop(db);
+        env.clear_faults();
+        env.drop_unsynced_data().unwrap();
+        let mut db = DB::open("db", fault_options(env)).unwrap();
+        for i in 0..n {
+            assert_eq!(
+                Some(format!("value{}", i).into_bytes()),
+                db.get(&key(i)),
+                "key {} {}",
+                i,
+                ctx
+            );
+        }
+        // The database is still usable.
+        write_entries(&mut db, n, 1);
+        assert!(db.get(&key(n)).is_some());
+        db
+    }
+
+    /// Runs f after injecting a fault at every possible call of op, and checks that the database
+    /// recovers from a crash afterwards.
+    fn crash_at_every_step<F: Fn(&mut DB)>(op: FaultOp, f: F) {
+        for step in 0.. {
+            let env = FaultInjectionEnv::new(Box::new(MemEnv::new()));
+            let mut db = DB::open("db", fault_options(&env)).unwrap();
+            write_entries(&mut db, 0, 20);
+
+            env.fail_after(op, step);
+            f(&mut db);
+            let faults = env.injected_faults();
+            crash_and_check(db, &env, 20, &format!("{:?} {} {}", op, step, faults));
+            if faults == 0 {
+                // f ran through without reaching the fault.
+                break;
+            }
+        }
+    }
+
+    fn test_fault_env_crash_during_flush() {
+        for op in vec![FaultOp::OpenWritable, FaultOp::Rename, FaultOp::Delete] {
+            crash_at_every_step(op, |db| {
+                let _ = db.compact_range(b"", b"");
+            });
+        }
+    }
+
+    fn test_fault_env_crash_during_compaction() {
+        for op in vec![
+            FaultOp::OpenWritable,
+            FaultOp::Rename,
+            FaultOp::Delete,
+            FaultOp::ReadAt,
+        ] {
+            crash_at_every_step(op, |db| {
+                let _ = db.compact_range(b"key", b"key9999");
+                let _ = db.compact_range(b"key", b"key9999");
+            });
+        }
+    }
+
+    fn test_fault_env_failed_compaction() {
+        // A compaction failing to write its outputs must leave its inputs in place, without a
+        // crash and reopen.
+        for step in 0.. {
+            let env = FaultInjectionEnv::new(Box::new(MemEnv::new()));
+            let mut db = DB::open("db", fault_options(&env)).unwrap();
+            write_entries(&mut db, 0, 20);
+
+            env.fail_after(FaultOp::OpenWritable, step);
+            let r = db.compact_range(b"key", b"key9999");
+            let faults = env.injected_faults();
+            env.clear_faults();
+            for i in 0..20 {
+                assert_eq!(
+                    Some(format!("value{}", i).into_bytes()),
+                    db.get(&key(i)),
+                    "key {} step {}",
+                    i,
+                    step
+                );
+            }
+            if faults == 0 {
+                r.unwrap();
+                break;
+            }
+        }
+    }
+}
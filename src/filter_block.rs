@@ -34,6 +34,8 @@ pub struct FilterBlockBuilder {
     // Reset on every start_block()
     key_offsets: Vec<usize>,
     keys: Vec<u8>,
+    // Last prefix added to the current filter.
+    last_prefix: Vec<u8>,
 }
 
 impl FilterBlockBuilder {
@@ -45,6 +47,7 @@ impl FilterBlockBuilder {
             filter_offsets: Vec::with_capacity(1024),
             key_offsets: Vec::with_capacity(1024),
             keys: Vec::with_capacity(1024),
+            last_prefix: Vec::new(),
         }
     }
 
@@ -61,6 +64,17 @@ impl FilterBlockBuilder {
         self.keys.extend_from_slice(key);
     }
 
+    /// Adds a key prefix to the current filter. Consecutive identical prefixes are only added
+    /// once.
+    pub fn add_prefix(&mut self, prefix: &[u8]) {
+        if !self.last_prefix.is_empty() && self.last_prefix == prefix {
+            return;
+        }
+        self.add_key(prefix);
+        self.last_prefix.clear();
+        self.last_prefix.extend_from_slice(prefix);
+    }
+
     pub fn start_block(&mut self, offset: usize) {
         let filter_ix = get_filter_index(offset, FILTER_BASE_LOG2);
         assert!(filter_ix >= self.filter_offsets.len() as u32);
@@ -72,6 +86,7 @@ impl FilterBlockBuilder {
 
     fn generate_filter(&mut self) {
         self.filter_offsets.push(self.filters.len());
+        self.last_prefix.clear();
         if self.keys.is_empty() {
             return;
         }
@@ -164,6 +179,77 @@ impl FilterBlockReader {
     }
 }
 
+/// A full filter block is a single filter over all keys of a table, as created by the filter
+/// policy. Unlike with a Filter Block, a key can be checked without knowing the offset of the
+/// block that may contain it.
+pub struct FullFilterBlockBuilder {
+    policy: BoxedFilterPolicy,
+    key_offsets: Vec<usize>,
+    keys: Vec<u8>,
+    // Last prefix added to the filter.
+    last_prefix: Vec<u8>,
+}
+
+impl FullFilterBlockBuilder {
+    pub fn new(fp: BoxedFilterPolicy) -> FullFilterBlockBuilder {
+        FullFilterBlockBuilder {
+            policy: fp,
+            key_offsets: Vec::with_capacity(1024),
+            keys: Vec::with_capacity(1024),
+            last_prefix: Vec::new(),
+        }
+    }
+
+    /// Returns the size of the keys added so far, as the filter's size isn't known before
+    /// finish().
+    pub fn size_estimate(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn filter_name(&self) -> &'static str {
+        self.policy.name()
+    }
+
+    pub fn add_key(&mut self, key: &[u8]) {
+        self.key_offsets.push(self.keys.len());
+        self.keys.extend_from_slice(key);
+    }
+
+    /// Adds a key prefix to the filter. Consecutive identical prefixes are only added once.
+    pub fn add_prefix(&mut self, prefix: &[u8]) {
+        if !self.last_prefix.is_empty() && self.last_prefix == prefix {
+            return;
+        }
+        self.add_key(prefix);
+        self.last_prefix.clear();
+        self.last_prefix.extend_from_slice(prefix);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.policy.create_filter(&self.keys, &self.key_offsets)
+    }
+}
+
+#[derive(Clone)]
+pub struct FullFilterBlockReader {
+    policy: BoxedFilterPolicy,
+    block: Rc<BlockContents>,
+}
+
+impl FullFilterBlockReader {
+    pub fn new_owned(pol: BoxedFilterPolicy, data: Vec<u8>) -> FullFilterBlockReader {
+        FullFilterBlockReader {
+            policy: pol,
+            block: Rc::new(data),
+        }
+    }
+
+    /// Returns whether the key may be contained in the table.
+    pub fn key_may_match(&self, key: &[u8]) -> bool {
+        self.policy.key_may_match(key, &self.block)
+    }
+}
+
 #[cfg(feature = "enclave_unit_test")]
 pub mod tests {
     use super::get_filter_index;
@@ -177,6 +263,7 @@ pub mod tests {
             test_filter_index,
             test_filter_block_builder,
             test_filter_block_build_read,
+            test_full_filter_block,
         )
     }
 
@@ -256,4 +343,28 @@ pub mod tests {
             }
         }
     }
+
+    fn test_full_filter_block() {
+        let policy: BoxedFilterPolicy = Rc::new(Box::new(BloomPolicy::new(32)));
+        let mut bld = FullFilterBlockBuilder::new(policy.clone());
+        for k in get_keys().iter() {
+            bld.add_key(k);
+        }
+        bld.add_prefix(b"pre");
+        bld.add_prefix(b"pre");
+        assert_eq!(5, bld.key_offsets.len());
+
+        let reader = FullFilterBlockReader::new_owned(policy.clone(), bld.finish());
+        for key in get_keys().iter() {
+            assert!(reader.key_may_match(key));
+        }
+        assert!(reader.key_may_match(b"pre"));
+        for key in &[&b"xsb"[..], b"9sad", b"assssaaaass"] {
+            assert!(!reader.key_may_match(key));
+        }
+
+        // A filter without keys matches nothing.
+        let empty = FullFilterBlockBuilder::new(policy.clone()).finish();
+        assert!(!FullFilterBlockReader::new_owned(policy, empty).key_may_match(b"abcd"));
+    }
 }
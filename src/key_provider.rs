@@ -0,0 +1,298 @@
+//! Key providers supply the master keys that databases are encrypted with, so that callers don't
+//! have to handle raw key material. The keys of individual files are derived from a master key
+//! with derive_file_key().
+#[cfg(feature = "mesalock_sgx")]
+use std::prelude::v1::*;
+
+use crate::disk_env::DBPersistKey;
+use crate::env::path_to_str;
+use crate::error::{err, Result, Status, StatusCode};
+
+use std::cell::Cell;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+use std::untrusted::fs;
+
+use hmac::{Hmac, Mac, NewMac};
+use integer_encoding::FixedInt;
+use sha2::{Digest, Sha256};
+
+use sgx_tse::{rsgx_get_align_key, rsgx_self_report};
+use sgx_types::{
+    sgx_attributes_t, sgx_cpu_svn_t, sgx_key_id_t, sgx_key_request_t, SGX_KEYPOLICY_MRENCLAVE,
+    SGX_KEYPOLICY_MRSIGNER, SGX_KEYSELECT_SEAL, SGX_KEY_REQUEST_RESERVED2_BYTES,
+    TSEAL_DEFAULT_FLAGSMASK, TSEAL_DEFAULT_MISCMASK,
+};
+
+/// A KeyProvider supplies a master key. It may be asked for the key repeatedly, and must return
+/// the same key every time.
+pub trait KeyProvider {
+    fn master_key(&self) -> Result<Vec<u8>>;
+}
+
+/// A boxed and refcounted key provider, analogous to BoxedFilterPolicy.
+pub type BoxedKeyProvider = Rc<Box<dyn KeyProvider>>;
+
+/// derive_file_key returns the key for the file with the given name in the database identified by
+/// db_id, derived from master with HMAC-SHA256. Knowing the key of one file doesn't reveal the
+/// master key or other files' keys, and databases sharing a master key have different file keys.
+pub fn derive_file_key(master: &[u8], db_id: &[u8], name: &str) -> DBPersistKey {
+    let mut mac = Hmac::<Sha256>::new_varkey(master).expect("HMAC accepts keys of any length");
+    mac.update(b"leveldb file key");
+    // The length prefix keeps the boundary between db_id and name unambiguous.
+    mac.update(&(db_id.len() as u32).encode_fixed_vec());
+    mac.update(db_id);
+    mac.update(name.as_bytes());
+    let mut key = [0; 16];
+    key.copy_from_slice(&mac.finalize().into_bytes()[..16]);
+    key
+}
+
+/// StaticKeyProvider always returns the same master key.
+pub struct StaticKeyProvider {
+    key: Vec<u8>,
+}
+
+impl StaticKeyProvider {
+    pub fn new(key: &[u8]) -> BoxedKeyProvider {
+        Rc::new(Box::new(StaticKeyProvider { key: key.to_vec() }))
+    }
+}
+
+impl KeyProvider for StaticKeyProvider {
+    fn master_key(&self) -> Result<Vec<u8>> {
+        Ok(self.key.clone())
+    }
+}
+
+/// The file in the database directory that records the SVNs the seal key is derived with.
+const SEAL_KEY_FILE: &str = "SEALKEY";
+const SEAL_KEY_MAGIC: &[u8] = b"LDBSEAL1";
+const SEAL_KEY_FILE_LEN: usize = 8 + 16 + 2 + 2;
+
+/// SealKeyProvider derives the master key from the enclave's seal key, so that it never leaves
+/// the enclave. The label selects one of many independent keys.
+///
+/// Like sgx_tseal, the key is derived with the CPU, ISV and config SVNs recorded when the
+/// database was created (in a plaintext file next to it), not with the current ones. The key
+/// therefore stays the same after a TCB or enclave update; the hardware refuses to derive keys
+/// for SVNs newer than the current ones, so a downgrade can't recover it.
+pub struct SealKeyProvider {
+    key_id: [u8; 32],
+    policy: u16,
+    cpu_svn: sgx_cpu_svn_t,
+    isv_svn: u16,
+    config_svn: u16,
+}
+
+impl SealKeyProvider {
+    /// Returns a provider for the database in dir whose key is bound to the enclave's signer, so
+    /// that later versions of the enclave can still use it.
+    pub fn new(dir: &Path, label: &[u8]) -> Result<BoxedKeyProvider> {
+        SealKeyProvider::with_policy(dir, label, SGX_KEYPOLICY_MRSIGNER)
+    }
+
+    /// Returns a provider for the database in dir whose key is bound to the exact enclave
+    /// measurement.
+    pub fn bound_to_enclave(dir: &Path, label: &[u8]) -> Result<BoxedKeyProvider> {
+        SealKeyProvider::with_policy(dir, label, SGX_KEYPOLICY_MRENCLAVE)
+    }
+
+    fn with_policy(dir: &Path, label: &[u8], policy: u16) -> Result<BoxedKeyProvider> {
+        let mut key_id = [0; 32];
+        key_id.copy_from_slice(&Sha256::digest(label));
+        let (cpu_svn, isv_svn, config_svn) = load_or_record_svns(dir)?;
+        Ok(Rc::new(Box::new(SealKeyProvider {
+            key_id,
+            policy,
+            cpu_svn,
+            isv_svn,
+            config_svn,
+        })))
+    }
+}
+
+/// Returns the SVNs recorded in dir, recording the enclave's current ones if there are none yet.
+fn load_or_record_svns(dir: &Path) -> Result<(sgx_cpu_svn_t, u16, u16)> {
+    let p = dir.join(SEAL_KEY_FILE);
+    match fs::read(&p) {
+        Ok(buf) => {
+            if buf.len() != SEAL_KEY_FILE_LEN || &buf[..8] != SEAL_KEY_MAGIC {
+                return err(
+                    StatusCode::Corruption,
+                    &format!("invalid seal key file: {}", path_to_str(&p)),
+                );
+            }
+            let mut cpu_svn = sgx_cpu_svn_t { svn: [0; 16] };
+            cpu_svn.svn.copy_from_slice(&buf[8..24]);
+            Ok((
+                cpu_svn,
+                u16::decode_fixed(&buf[24..26]),
+                u16::decode_fixed(&buf[26..28]),
+            ))
+        }
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+            let report = rsgx_self_report();
+            let mut buf = SEAL_KEY_MAGIC.to_vec();
+            buf.extend_from_slice(&report.body.cpu_svn.svn);
+            buf.extend_from_slice(&report.body.isv_svn.encode_fixed_vec());
+            buf.extend_from_slice(&report.body.config_svn.encode_fixed_vec());
+            // Write a temporary file first so that a crash can't leave a torn file behind.
+            let tmp = dir.join(format!("{}.tmp", SEAL_KEY_FILE));
+            fs::create_dir_all(dir)?;
+            fs::write(&tmp, &buf)?;
+            fs::rename(&tmp, &p)?;
+            Ok((
+                report.body.cpu_svn,
+                report.body.isv_svn,
+                report.body.config_svn,
+            ))
+        }
+        Err(e) => Err(Status::from(e)),
+    }
+}
+
+impl KeyProvider for SealKeyProvider {
+    fn master_key(&self) -> Result<Vec<u8>> {
+        let req = sgx_key_request_t {
+            key_name: SGX_KEYSELECT_SEAL,
+            key_policy: self.policy,
+            isv_svn: self.isv_svn,
+            reserved1: 0,
+            cpu_svn: self.cpu_svn,
+            attribute_mask: sgx_attributes_t {
+                flags: TSEAL_DEFAULT_FLAGSMASK,
+                xfrm: 0,
+            },
+            key_id: sgx_key_id_t { id: self.key_id },
+            misc_mask: TSEAL_DEFAULT_MISCMASK,
+            config_svn: self.config_svn,
+            reserved2: [0; SGX_KEY_REQUEST_RESERVED2_BYTES],
+        };
+        match rsgx_get_align_key(&req) {
+            Ok(k) => Ok(k.key.to_vec()),
+            Err(e) => err(
+                StatusCode::PermissionDenied,
+                &format!("deriving the seal key failed: {}", e),
+            ),
+        }
+    }
+}
+
+/// TestKeyProvider is a stub for tests. It returns a fixed key, counts how often it was asked
+/// for it, and can be made to fail. Clones share their state.
+#[derive(Clone)]
+pub struct TestKeyProvider {
+    key: Vec<u8>,
+    requests: Rc<Cell<usize>>,
+    failing: Rc<Cell<bool>>,
+}
+
+impl TestKeyProvider {
+    pub fn new(key: &[u8]) -> TestKeyProvider {
+        TestKeyProvider {
+            key: key.to_vec(),
+            requests: Rc::new(Cell::new(0)),
+            failing: Rc::new(Cell::new(false)),
+        }
+    }
+
+    /// Returns a BoxedKeyProvider sharing this provider's state.
+    pub fn boxed(&self) -> BoxedKeyProvider {
+        Rc::new(Box::new(self.clone()))
+    }
+
+    /// Returns the number of times the key was requested.
+    pub fn requests(&self) -> usize {
+        self.requests.get()
+    }
+
+    /// Makes all following requests fail with StatusCode::PermissionDenied, or succeed again.
+    pub fn set_failing(&self, failing: bool) {
+        self.failing.set(failing);
+    }
+}
+
+impl KeyProvider for TestKeyProvider {
+    fn master_key(&self) -> Result<Vec<u8>> {
+        self.requests.set(self.requests.get() + 1);
+        if self.failing.get() {
+            return err(StatusCode::PermissionDenied, "test key provider is failing");
+        }
+        Ok(self.key.clone())
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_key_provider_derive_file_key,
+            test_key_provider_test_stub,
+            test_key_provider_seal_key,
+        )
+    }
+
+    fn test_key_provider_derive_file_key() {
+        let k = derive_file_key(b"master", b"db", "000005.ldb");
+        assert_eq!(k, derive_file_key(b"master", b"db", "000005.ldb"));
+        assert!(k != derive_file_key(b"master", b"db", "000006.ldb"));
+        assert!(k != derive_file_key(b"other master", b"db", "000005.ldb"));
+        assert!(k != derive_file_key(b"master", b"other db", "000005.ldb"));
+        assert!(k != derive_file_key(b"master", b"db0", "00005.ldb"));
+        assert!(&k[..] != &b"master"[..]);
+    }
+
+    fn test_key_provider_test_stub() {
+        let p = TestKeyProvider::new(b"key");
+        let boxed = p.boxed();
+        assert_eq!(b"key".to_vec(), boxed.master_key().unwrap());
+        p.set_failing(true);
+        assert!(boxed.master_key().is_err());
+        p.set_failing(false);
+        assert!(boxed.master_key().is_ok());
+        assert_eq!(3, p.requests());
+    }
+
+    fn test_key_provider_seal_key() {
+        let dir = Path::new("key_provider_seal_key_test");
+        let _ = fs::remove_dir_all(dir);
+        let a = SealKeyProvider::new(dir, b"a")
+            .unwrap()
+            .master_key()
+            .unwrap();
+        assert_eq!(16, a.len());
+        assert!(fs::metadata(dir.join(SEAL_KEY_FILE)).is_ok());
+        assert_eq!(
+            a,
+            SealKeyProvider::new(dir, b"a")
+                .unwrap()
+                .master_key()
+                .unwrap()
+        );
+        assert!(
+            a != SealKeyProvider::new(dir, b"b")
+                .unwrap()
+                .master_key()
+                .unwrap()
+        );
+        assert!(
+            a != SealKeyProvider::bound_to_enclave(dir, b"a")
+                .unwrap()
+                .master_key()
+                .unwrap()
+        );
+
+        // A corrupted record is reported rather than silently replaced.
+        fs::write(dir.join(SEAL_KEY_FILE), b"garbage").unwrap();
+        assert_eq!(
+            StatusCode::Corruption,
+            SealKeyProvider::new(dir, b"a").err().unwrap().code
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -31,11 +31,17 @@ extern crate sgx_tstd as std;
 extern crate protected_fs;
 extern crate sgx_libc as libc;
 extern crate sgx_trts;
+extern crate sgx_tse;
 extern crate sgx_types;
 
+extern crate aes;
+extern crate cipher;
 extern crate crc;
+extern crate ctr;
+extern crate hmac;
 extern crate integer_encoding;
 extern crate rand;
+extern crate sha2;
 extern crate snap;
 
 mod block;
@@ -43,48 +49,76 @@ mod block_builder;
 mod blockhandle;
 mod cache;
 mod cmp;
+mod counting_env;
 mod disk_env;
+mod encrypted_env;
 mod env;
 mod env_common;
 mod error;
+mod fault_env;
 mod filter;
 mod filter_block;
 #[macro_use]
 mod infolog;
+mod key_provider;
 mod key_types;
 mod log;
 mod mem_env;
 mod memtable;
 mod merging_iter;
+mod monotonic_counter;
 mod options;
+mod rate_limiter;
+mod replication;
 mod skipmap;
+mod slice_transform;
 mod snapshot;
+mod state_digest;
 mod table_block;
 mod table_builder;
 mod table_cache;
 mod table_reader;
 mod test_util;
+mod transaction;
 mod types;
+mod updates_iter;
 mod version;
 mod version_edit;
 mod version_set;
 mod write_batch;
+mod write_batch_with_index;
 
 mod db_impl;
 mod db_iter;
 
 pub use crate::cmp::{Cmp, DefaultCmp};
-pub use crate::db_iter::DBIterator;
+pub use crate::counting_env::{CountingEnv, IoCounters, IoFileType, IoStats};
+pub use crate::db_iter::{DBIterator, DBRange};
+pub use crate::encrypted_env::EncryptedEnv;
 pub use crate::env::Env;
 pub use crate::error::{Result, Status, StatusCode};
+pub use crate::fault_env::{FaultInjectionEnv, FaultOp};
 pub use crate::filter::{BloomPolicy, FilterPolicy};
+pub use crate::key_provider::{
+    derive_file_key, BoxedKeyProvider, KeyProvider, SealKeyProvider, StaticKeyProvider,
+    TestKeyProvider,
+};
 pub use crate::mem_env::MemEnv;
+pub use crate::monotonic_counter::{BoxedMonotonicCounter, MemMonotonicCounter, MonotonicCounter};
 pub use crate::options::{in_memory, CompressionType, Options};
+pub use crate::rate_limiter::RateLimiter;
+pub use crate::replication::{ChannelTransport, Follower, Leader, Transport};
 pub use crate::skipmap::SkipMap;
-pub use crate::types::LdbIterator;
-pub use crate::write_batch::WriteBatch;
+pub use crate::slice_transform::{FixedPrefixTransform, SliceTransform};
+pub use crate::snapshot::Snapshot;
+pub use crate::state_digest::{state_digest, table_hash, StateDigest};
+pub use crate::transaction::Transaction;
+pub use crate::types::{FileMetaData, KeyGeneration, LdbIterator, TableHash};
+pub use crate::updates_iter::UpdatesIter;
+pub use crate::write_batch::{Handler, WriteBatch, WriteBatchIter};
+pub use crate::write_batch_with_index::{BatchOverlayIter, WriteBatchWithIndex};
 pub use db_impl::DB;
-pub use disk_env::PosixDiskEnv;
+pub use disk_env::{DBPersistKey, KeyRing, PosixDiskEnv};
 
 #[cfg(feature = "enclave_unit_test")]
 pub mod tests {
@@ -99,27 +133,39 @@ pub mod tests {
             blockhandle::tests::run_tests(),
             cache::tests::run_tests(),
             cmp::tests::run_tests(),
+            counting_env::tests::run_tests(),
             db_impl::tests::run_tests(),
             db_iter::tests::run_tests(),
             disk_env::tests::run_tests(),
+            encrypted_env::tests::run_tests(),
+            fault_env::tests::run_tests(),
             filter::tests::run_tests(),
             filter_block::tests::run_tests(),
+            key_provider::tests::run_tests(),
             key_types::tests::run_tests(),
             log::tests::run_tests(),
             mem_env::tests::run_tests(),
             memtable::tests::run_tests(),
             merging_iter::tests::run_tests(),
+            monotonic_counter::tests::run_tests(),
+            rate_limiter::tests::run_tests(),
+            replication::tests::run_tests(),
             skipmap::tests::run_tests(),
+            slice_transform::tests::run_tests(),
             snapshot::tests::run_tests(),
+            state_digest::tests::run_tests(),
             table_builder::tests::run_tests(),
             table_cache::tests::run_tests(),
             test_util::tests::run_tests(),
             table_reader::tests::run_tests(),
+            transaction::tests::run_tests(),
             types::tests::run_tests(),
+            updates_iter::tests::run_tests(),
             version::tests::run_tests(),
             version_edit::tests::run_tests(),
             version_set::tests::run_tests(),
             write_batch::tests::run_tests(),
+            write_batch_with_index::tests::run_tests(),
         )
     }
 }
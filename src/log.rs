@@ -126,20 +126,38 @@ pub struct LogReader<R: Read> {
     blocksize: usize,
     head_scratch: [u8; 7],
     checksums: bool,
+    // The offset in the log of the next byte read from src, and of the end of the last record
+    // returned.
+    pos: usize,
+    off: usize,
 }
 
 impl<R: Read> LogReader<R> {
     pub fn new(src: R, chksum: bool) -> LogReader<R> {
+        LogReader::new_with_off(src, chksum, 0)
+    }
+
+    /// new_with_off returns a reader for a log of which src contains the part starting at off,
+    /// which must be the end of a record.
+    pub fn new_with_off(src: R, chksum: bool, off: usize) -> LogReader<R> {
         LogReader {
             src,
-            blk_off: 0,
+            blk_off: off % BLOCK_SIZE,
             blocksize: BLOCK_SIZE,
             checksums: chksum,
             head_scratch: [0; 7],
             digest: crc32::Digest::new(crc32::CASTAGNOLI),
+            pos: off,
+            off,
         }
     }
 
+    /// Returns the offset in the log of the end of the last record that was read successfully. A
+    /// reader created with new_with_off() at that offset continues with the following record.
+    pub fn offset(&self) -> usize {
+        self.off
+    }
+
     /// EOF is signalled by Ok(0)
     pub fn read(&mut self, dst: &mut Vec<u8>) -> Result<usize> {
         let mut checksum: u32;
@@ -152,7 +170,8 @@ impl<R: Read> LogReader<R> {
         loop {
             if self.blocksize - self.blk_off < HEADER_SIZE {
                 // skip to next block
-                self.src
+                self.pos += self
+                    .src
                     .read(&mut self.head_scratch[0..self.blocksize - self.blk_off])?;
                 self.blk_off = 0;
             }
@@ -165,6 +184,7 @@ impl<R: Read> LogReader<R> {
             }
 
             self.blk_off += bytes_read;
+            self.pos += bytes_read;
 
             checksum = u32::decode_fixed(&self.head_scratch[0..4]);
             length = u16::decode_fixed(&self.head_scratch[4..6]);
@@ -175,6 +195,7 @@ impl<R: Read> LogReader<R> {
                 .src
                 .read(&mut dst[dst_offset..dst_offset + length as usize])?;
             self.blk_off += bytes_read;
+            self.pos += bytes_read;
 
             if self.checksums
                 && !self.check_integrity(typ, &dst[dst_offset..dst_offset + bytes_read], checksum)
@@ -185,12 +206,14 @@ impl<R: Read> LogReader<R> {
             dst_offset += length as usize;
 
             if typ == RecordType::Full as u8 {
+                self.off = self.pos;
                 return Ok(dst_offset);
             } else if typ == RecordType::First as u8 {
                 continue;
             } else if typ == RecordType::Middle as u8 {
                 continue;
             } else if typ == RecordType::Last as u8 {
+                self.off = self.pos;
                 return Ok(dst_offset);
             }
         }
@@ -228,6 +251,7 @@ pub mod tests {
             test_writer,
             test_writer_append,
             test_reader,
+            test_reader_offset,
         )
     }
 
@@ -332,4 +356,38 @@ pub mod tests {
         }
         assert_eq!(i, data.len());
     }
+
+    fn test_reader_offset() {
+        let mut lw = LogWriter::new(Vec::new());
+        let data = vec![vec![1; 100], vec![2; BLOCK_SIZE], vec![3; 10]];
+        for d in data.iter() {
+            lw.add_record(d).unwrap();
+        }
+        let log = lw.dst;
+        let mut dst = vec![];
+
+        let mut lr = LogReader::new(log.as_slice(), true);
+        assert_eq!(0, lr.offset());
+        lr.read(&mut dst).unwrap();
+        let first = lr.offset();
+        assert_eq!(100 + HEADER_SIZE, first);
+
+        // A reader starting after the first record returns the others.
+        let mut lr = LogReader::new_with_off(&log[first..], true, first);
+        for d in &data[1..] {
+            lr.read(&mut dst).unwrap();
+            assert_eq!(d, &dst);
+        }
+        assert_eq!(0, lr.read(&mut dst).unwrap());
+        assert_eq!(log.len(), lr.offset());
+
+        // A torn record doesn't move the offset.
+        let mut lr = LogReader::new(&log[..log.len() - 3], true);
+        lr.read(&mut dst).unwrap();
+        lr.read(&mut dst).unwrap();
+        let second = lr.offset();
+        assert!(lr.read(&mut dst).is_err());
+        assert_eq!(second, lr.offset());
+        assert_eq!(log.len() - 10 - HEADER_SIZE, second);
+    }
 }
@@ -2,6 +2,8 @@
 #[cfg(feature = "mesalock_sgx")]
 use std::prelude::v1::*;
 
+use crate::disk_env::DBPersistKey;
+use crate::encrypted_env::{new_salt, FileKeys, MAC_LEN, SALT_LEN};
 use crate::env::{path_to_str, path_to_string, Env, FileLock, Logger, RandomAccess};
 use crate::env_common::micros;
 use crate::error::{err, Result, StatusCode};
@@ -14,6 +16,22 @@ use std::path::{Path, PathBuf};
 
 use std::sync::{Arc, SgxMutex as Mutex};
 
+use hmac::Mac;
+use integer_encoding::{VarIntReader, VarIntWriter};
+
+// An exported MemEnv (see MemEnv::export()) is laid out as
+//
+// [ magic (8 bytes) | flags (1 byte) | salt (32 bytes, if sealed) | body | MAC (32 bytes, if
+// sealed) ]
+//
+// with the body consisting of the number of files followed by each file's path and contents,
+// all as varint-length-prefixed strings. A sealed body is encrypted with AES-256-CTR, and the
+// MAC (HMAC-SHA256) covers everything before it. Both keys are derived from the DBPersistKey and
+// the salt like those of an EncryptedEnv file.
+const BLOB_MAGIC: &[u8; 8] = b"LDBMEM01";
+const BLOB_PLAIN: u8 = 0;
+const BLOB_SEALED: u8 = 1;
+
 /// BufferBackedFile is a simple type implementing RandomAccess on a Vec<u8>.
 pub type BufferBackedFile = Vec<u8>;
 
@@ -127,6 +145,7 @@ struct MemFSEntry {
 
 /// MemFS implements a completely in-memory file system, both for testing and temporary in-memory
 /// databases. It supports full concurrency.
+#[derive(Clone)]
 pub struct MemFS {
     store: Arc<Mutex<HashMap<String, MemFSEntry>>>,
 }
@@ -250,6 +269,16 @@ impl MemFS {
             }
         }
     }
+    /// Returns the paths and contents of all files, sorted by path.
+    fn files(&self) -> Result<Vec<(String, Vec<u8>)>> {
+        let fs = self.store.lock()?;
+        let mut files: Vec<(String, Vec<u8>)> = fs
+            .iter()
+            .map(|(p, e)| (p.clone(), e.f.0.lock().unwrap().clone()))
+            .collect();
+        files.sort();
+        Ok(files)
+    }
     fn unlock_(&self, l: FileLock) -> Result<()> {
         let mut fs = self.store.lock()?;
         let id = l.id.clone();
@@ -274,13 +303,142 @@ impl MemFS {
 }
 
 /// MemEnv is an in-memory environment that can be used for testing or ephemeral databases. The
-/// performance will be better than what a disk environment delivers.
+/// performance will be better than what a disk environment delivers. Clones share their files.
+#[derive(Clone)]
 pub struct MemEnv(MemFS);
 
 impl MemEnv {
     pub fn new() -> MemEnv {
         MemEnv(MemFS::new())
     }
+
+    /// export serializes all files into a single blob, which import() turns back into a MemEnv.
+    /// If key is given, the blob is sealed: encrypted and authenticated with the key. Files
+    /// should not be written while exporting, e.g. by an open database.
+    pub fn export(&self, key: Option<&DBPersistKey>) -> Result<Vec<u8>> {
+        let files = self.0.files()?;
+        let mut body = vec![];
+        body.write_varint(files.len())?;
+        for (path, contents) in files.iter() {
+            body.write_varint(path.len())?;
+            body.write_all(path.as_bytes())?;
+            body.write_varint(contents.len())?;
+            body.write_all(contents)?;
+        }
+
+        let mut blob = BLOB_MAGIC.to_vec();
+        match key {
+            None => {
+                blob.push(BLOB_PLAIN);
+                blob.extend_from_slice(&body);
+            }
+            Some(key) => {
+                let salt = new_salt();
+                let keys = FileKeys::derive(key, &salt);
+                blob.push(BLOB_SEALED);
+                blob.extend_from_slice(&salt);
+                keys.apply_keystream(0, &mut body);
+                blob.extend_from_slice(&body);
+                let m = keys.mac(&[&blob]);
+                blob.extend_from_slice(&m.finalize().into_bytes());
+            }
+        }
+        Ok(blob)
+    }
+
+    /// import returns a new MemEnv containing the files of a blob returned by export(). The key
+    /// must be given if and only if the blob was sealed.
+    pub fn import(blob: &[u8], key: Option<&DBPersistKey>) -> Result<MemEnv> {
+        let header_len = BLOB_MAGIC.len() + 1;
+        if blob.len() < header_len || &blob[..BLOB_MAGIC.len()] != BLOB_MAGIC {
+            return err(StatusCode::InvalidData, "not an exported MemEnv");
+        }
+        let sealed = match blob[BLOB_MAGIC.len()] {
+            BLOB_PLAIN => false,
+            BLOB_SEALED => true,
+            _ => return err(StatusCode::InvalidData, "unknown format of exported MemEnv"),
+        };
+        let body = match (sealed, key) {
+            (false, None) => blob[header_len..].to_vec(),
+            (true, Some(key)) => {
+                if blob.len() < header_len + SALT_LEN + MAC_LEN {
+                    return err(StatusCode::Corruption, "exported MemEnv is truncated");
+                }
+                let salt = &blob[header_len..header_len + SALT_LEN];
+                let keys = FileKeys::derive(key, salt);
+                let (sealed_part, m) = blob.split_at(blob.len() - MAC_LEN);
+                if keys.mac(&[sealed_part]).verify(m).is_err() {
+                    return err(
+                        StatusCode::Corruption,
+                        "MAC mismatch in exported MemEnv (wrong key?)",
+                    );
+                }
+                let mut body = sealed_part[header_len + SALT_LEN..].to_vec();
+                keys.apply_keystream(0, &mut body);
+                body
+            }
+            (true, None) => {
+                return err(
+                    StatusCode::InvalidArgument,
+                    "exported MemEnv is sealed, but no key was given",
+                )
+            }
+            // Accepting it would let a plain blob replace a sealed one.
+            (false, Some(_)) => {
+                return err(
+                    StatusCode::PermissionDenied,
+                    "exported MemEnv is not sealed",
+                )
+            }
+        };
+
+        let env = MemEnv::new();
+        let mut reader = &body[..];
+        let n: usize = match reader.read_varint() {
+            Ok(n) => n,
+            Err(_) => {
+                return err(
+                    StatusCode::Corruption,
+                    "exported MemEnv: couldn't read number of files",
+                )
+            }
+        };
+        for _ in 0..n {
+            let path = match String::from_utf8(read_blob_string(&mut reader)?) {
+                Ok(path) => path,
+                Err(_) => return err(StatusCode::Corruption, "exported MemEnv: bad path encoding"),
+            };
+            let contents = read_blob_string(&mut reader)?;
+            env.open_writable_file(Path::new(&path))?
+                .write_all(&contents)?;
+        }
+        if !reader.is_empty() {
+            return err(
+                StatusCode::Corruption,
+                "exported MemEnv: trailing data after the last file",
+            );
+        }
+        Ok(env)
+    }
+}
+
+/// Reads a varint-length-prefixed string of an exported MemEnv.
+fn read_blob_string(reader: &mut &[u8]) -> Result<Vec<u8>> {
+    let len: usize = match reader.read_varint() {
+        Ok(len) => len,
+        Err(_) => {
+            return err(
+                StatusCode::Corruption,
+                "exported MemEnv: couldn't read length",
+            )
+        }
+    };
+    if len > reader.len() {
+        return err(StatusCode::Corruption, "exported MemEnv is truncated");
+    }
+    let (s, rest) = reader.split_at(len);
+    *reader = rest;
+    Ok(s.to_vec())
 }
 
 impl Env for MemEnv {
@@ -351,7 +509,10 @@ impl Env for MemEnv {
 #[cfg(feature = "enclave_unit_test")]
 pub mod tests {
     use super::*;
+    use crate::db_impl::DB;
     use crate::env;
+    use crate::options;
+    use std::rc::Rc;
     use teaclave_test_utils::*;
 
     pub fn run_tests() -> bool {
@@ -365,6 +526,9 @@ pub mod tests {
             test_mem_fs_children,
             test_mem_fs_lock,
             test_memenv_all,
+            test_memenv_export_import,
+            test_memenv_export_sealed,
+            test_memenv_export_db,
         )
     }
 
@@ -606,4 +770,107 @@ pub mod tests {
         me.new_logger(p1).unwrap();
         assert!(me.micros() > 0);
     }
+
+    fn read_file(env: &MemEnv, p: &str) -> Vec<u8> {
+        let mut v = vec![];
+        env.open_sequential_file(Path::new(p))
+            .unwrap()
+            .read_to_end(&mut v)
+            .unwrap();
+        v
+    }
+
+    fn test_memenv_export_import() {
+        let me = MemEnv::new();
+        me.open_writable_file(Path::new("/a/b"))
+            .unwrap()
+            .write_all(b"hello")
+            .unwrap();
+        me.open_writable_file(Path::new("/a/empty")).unwrap();
+        me.open_writable_file(Path::new("/c"))
+            .unwrap()
+            .write_all(&[7; 10000])
+            .unwrap();
+
+        let blob = me.export(None).unwrap();
+        assert_eq!(blob, me.export(None).unwrap());
+        let imported = MemEnv::import(&blob, None).unwrap();
+        assert_eq!(b"hello".to_vec(), read_file(&imported, "/a/b"));
+        assert_eq!(0, imported.size_of(Path::new("/a/empty")).unwrap());
+        assert_eq!(vec![7; 10000], read_file(&imported, "/c"));
+        assert_eq!(2, imported.children(Path::new("/a")).unwrap().len());
+        // The copy is independent.
+        imported.delete(Path::new("/c")).unwrap();
+        assert!(me.exists(Path::new("/c")).unwrap());
+
+        assert!(MemEnv::import(&MemEnv::new().export(None).unwrap(), None).is_ok());
+        assert_eq!(
+            StatusCode::InvalidData,
+            MemEnv::import(b"garbage", None).err().unwrap().code
+        );
+        assert_eq!(
+            StatusCode::Corruption,
+            MemEnv::import(&blob[..blob.len() - 1], None)
+                .err()
+                .unwrap()
+                .code
+        );
+    }
+
+    fn test_memenv_export_sealed() {
+        let me = MemEnv::new();
+        me.open_writable_file(Path::new("/secret"))
+            .unwrap()
+            .write_all(b"plaintext contents")
+            .unwrap();
+        let key = [3; 16];
+        let blob = me.export(Some(&key)).unwrap();
+        assert!(!blob
+            .windows(b"plaintext".len())
+            .any(|w| w == &b"plaintext"[..]));
+        // Every export uses a new salt.
+        assert!(blob != me.export(Some(&key)).unwrap());
+
+        let imported = MemEnv::import(&blob, Some(&key)).unwrap();
+        assert_eq!(
+            b"plaintext contents".to_vec(),
+            read_file(&imported, "/secret")
+        );
+
+        let code =
+            |blob: &[u8], key: Option<&DBPersistKey>| MemEnv::import(blob, key).err().unwrap().code;
+        assert_eq!(StatusCode::Corruption, code(&blob, Some(&[4; 16])));
+        assert_eq!(StatusCode::InvalidArgument, code(&blob, None));
+        assert_eq!(
+            StatusCode::PermissionDenied,
+            code(&me.export(None).unwrap(), Some(&key))
+        );
+        let mut tampered = blob.clone();
+        tampered[20] ^= 1;
+        assert_eq!(StatusCode::Corruption, code(&tampered, Some(&key)));
+        assert_eq!(StatusCode::Corruption, code(&blob[..30], Some(&key)));
+    }
+
+    fn test_memenv_export_db() {
+        let key = [9; 16];
+        let env = MemEnv::new();
+        let mut opt = options::for_test();
+        opt.env = Rc::new(Box::new(env.clone()));
+        {
+            let mut db = DB::open("db", opt.clone()).unwrap();
+            for i in 0..100 {
+                db.put(format!("key{:03}", i).as_bytes(), b"value").unwrap();
+            }
+            db.compact_range(b"key000", b"key050").unwrap();
+            db.put(b"unflushed", b"value").unwrap();
+            db.flush().unwrap();
+        }
+        let blob = env.export(Some(&key)).unwrap();
+
+        let imported = MemEnv::import(&blob, Some(&key)).unwrap();
+        opt.env = Rc::new(Box::new(imported));
+        let mut db = DB::open("db", opt).unwrap();
+        assert_eq!(Some(b"value".to_vec()), db.get(b"key042"));
+        assert_eq!(Some(b"value".to_vec()), db.get(b"unflushed"));
+    }
 }
@@ -5,7 +5,7 @@ use crate::cmp::{Cmp, MemtableKeyCmp};
 use crate::key_types::{build_memtable_key, parse_internal_key, parse_memtable_key, ValueType};
 use crate::key_types::{LookupKey, UserKey};
 use crate::skipmap::{SkipMap, SkipMapIter};
-use crate::types::{current_key_val, LdbIterator, SequenceNumber};
+use crate::types::{LdbIterator, SequenceNumber};
 
 use std::rc::Rc;
 
@@ -51,8 +51,9 @@ impl MemTable {
         let mut iter = self.map.iter();
         iter.seek(key.memtable_key());
 
-        if let Some((foundkey, _)) = current_key_val(&iter) {
-            let (fkeylen, fkeyoff, tag, vallen, valoff) = parse_memtable_key(&foundkey);
+        if iter.valid() {
+            let foundkey = iter.key();
+            let (fkeylen, fkeyoff, tag, vallen, valoff) = parse_memtable_key(foundkey);
 
             // Compare user key -- if equal, proceed
             // We only care about user key equality here
@@ -67,6 +68,22 @@ impl MemTable {
         (None, false)
     }
 
+    /// get_sequence returns the sequence number of the newest entry for the user key of `key`
+    /// (which may be a deletion) that is visible at the lookup key's sequence number.
+    pub fn get_sequence(&self, key: &LookupKey) -> Option<SequenceNumber> {
+        let mut iter = self.map.iter();
+        iter.seek(key.memtable_key());
+
+        if iter.valid() {
+            let foundkey = iter.key();
+            let (fkeylen, fkeyoff, tag, _, _) = parse_memtable_key(foundkey);
+            if key.user_key() == &foundkey[fkeyoff..fkeyoff + fkeylen] {
+                return Some(tag >> 8);
+            }
+        }
+        None
+    }
+
     pub fn iter(&self) -> MemtableIterator {
         MemtableIterator {
             skipmapiter: self.map.iter(),
@@ -94,24 +111,10 @@ impl LdbIterator for MemtableIterator {
         self.skipmapiter.reset();
     }
     fn prev(&mut self) -> bool {
-        // Make sure this is actually needed (skipping deleted values?).
-        let (mut key, mut val) = (vec![], vec![]);
-        loop {
-            if !self.skipmapiter.prev() {
-                return false;
-            }
-            if self.skipmapiter.current(&mut key, &mut val) {
-                let (_, _, tag, _, _) = parse_memtable_key(&key);
-
-                if tag & 0xff == ValueType::TypeValue as u64 {
-                    return true;
-                } else {
-                    continue;
-                }
-            } else {
-                return false;
-            }
-        }
+        self.skipmapiter.prev()
+    }
+    fn seek_to_last(&mut self) {
+        self.skipmapiter.seek_to_last();
     }
     fn valid(&self) -> bool {
         self.skipmapiter.valid()
@@ -121,19 +124,30 @@ impl LdbIterator for MemtableIterator {
         if !self.valid() {
             return false;
         }
-
-        if self.skipmapiter.current(key, val) {
-            let (keylen, keyoff, _, vallen, valoff) = parse_memtable_key(&key);
-            val.clear();
-            val.extend_from_slice(&key[valoff..valoff + vallen]);
-            // zero-allocation truncation.
-            shift_left(key, keyoff);
-            // Truncate key to key+tag.
-            key.truncate(keylen + u64::required_space());
-            return true;
-        } else {
-            panic!("should not happen");
+        key.clear();
+        val.clear();
+        key.extend_from_slice(self.key());
+        val.extend_from_slice(self.value());
+        true
+    }
+    /// key returns the current key in InternalKey format, borrowed from the MemtableKey stored in
+    /// the skipmap.
+    fn key(&self) -> &[u8] {
+        if !self.valid() {
+            return &[];
         }
+        let mkey = self.skipmapiter.key();
+        let (keylen, keyoff, _, _, _) = parse_memtable_key(mkey);
+        // Key+tag.
+        &mkey[keyoff..keyoff + keylen + u64::required_space()]
+    }
+    fn value(&self) -> &[u8] {
+        if !self.valid() {
+            return &[];
+        }
+        let mkey = self.skipmapiter.key();
+        let (_, _, _, vallen, valoff) = parse_memtable_key(mkey);
+        &mkey[valoff..valoff + vallen]
     }
     /// seek takes an InternalKey.
     fn seek(&mut self, to: &[u8]) {
@@ -144,15 +158,6 @@ impl LdbIterator for MemtableIterator {
     }
 }
 
-/// shift_left moves s[mid..] to s[0..s.len()-mid]. The new size is s.len()-mid.
-fn shift_left(s: &mut Vec<u8>, mid: usize) {
-    for i in mid..s.len() {
-        s.swap(i, i - mid);
-    }
-    let newlen = s.len() - mid;
-    s.truncate(newlen);
-}
-
 #[cfg(feature = "enclave_unit_test")]
 #[allow(unused_variables)]
 pub mod tests {
@@ -160,14 +165,15 @@ pub mod tests {
     use crate::key_types::*;
     use crate::options;
     use crate::test_util::{test_iterator_properties, LdbIteratorIter};
+    use crate::types::{current_key_val, MAX_SEQUENCE_NUMBER};
     use teaclave_test_utils::*;
 
     pub fn run_tests() -> bool {
         run_tests!(
-            test_shift_left,
             test_memtable_parse_tag,
             test_memtable_add,
             test_memtable_add_get,
+            test_memtable_get_sequence,
             test_memtable_iterator_init,
             test_memtable_iterator_seek,
             test_memtable_iterator_fwd,
@@ -177,16 +183,6 @@ pub mod tests {
         )
     }
 
-    fn test_shift_left() {
-        let mut v = vec![1, 2, 3, 4, 5];
-        shift_left(&mut v, 1);
-        assert_eq!(v, vec![2, 3, 4, 5]);
-
-        let mut v = vec![1, 2, 3, 4, 5];
-        shift_left(&mut v, 4);
-        assert_eq!(v, vec![5]);
-    }
-
     fn get_memtable() -> MemTable {
         let mut mt = MemTable::new(options::for_test().cmp);
         let entries = vec![
@@ -269,6 +265,26 @@ pub mod tests {
         }
     }
 
+    fn test_memtable_get_sequence() {
+        let mt = get_memtable();
+
+        assert_eq!(
+            Some(120),
+            mt.get_sequence(&LookupKey::new(b"abc", MAX_SEQUENCE_NUMBER))
+        );
+        assert_eq!(Some(115), mt.get_sequence(&LookupKey::new(b"abc", 119)));
+        assert_eq!(None, mt.get_sequence(&LookupKey::new(b"abc", 110)));
+        // Deletions count as entries.
+        assert_eq!(
+            Some(122),
+            mt.get_sequence(&LookupKey::new(b"abe", MAX_SEQUENCE_NUMBER))
+        );
+        assert_eq!(
+            None,
+            mt.get_sequence(&LookupKey::new(b"abz", MAX_SEQUENCE_NUMBER))
+        );
+    }
+
     fn test_memtable_iterator_init() {
         let mt = get_memtable();
         let mut iter = mt.iter();
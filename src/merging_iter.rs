@@ -57,9 +57,6 @@ impl MergingIter {
             return;
         }
 
-        let mut keybuf = vec![];
-        let mut valbuf = vec![];
-
         if let Some((key, _)) = current_key_val(self) {
             if let Some(current) = self.current {
                 match d {
@@ -71,10 +68,10 @@ impl MergingIter {
                                 // This doesn't work if two iterators are returning the exact same
                                 // keys. However, in reality, two entries will always have differing
                                 // sequence numbers.
-                                if self.iters[i].current(&mut keybuf, &mut valbuf) {
-                                    if self.cmp.cmp(&keybuf, &key) == Ordering::Equal {
-                                        self.iters[i].advance();
-                                    }
+                                if self.iters[i].valid()
+                                    && self.cmp.cmp(self.iters[i].key(), &key) == Ordering::Equal
+                                {
+                                    self.iters[i].advance();
                                 }
                             }
                         }
@@ -87,8 +84,7 @@ impl MergingIter {
                                 if self.iters[i].valid() {
                                     self.iters[i].prev();
                                 } else {
-                                    // seek to last.
-                                    while self.iters[i].advance() {}
+                                    self.iters[i].seek_to_last();
                                 }
                             }
                         }
@@ -121,12 +117,11 @@ impl MergingIter {
         }
 
         let mut next_ix = 0;
-        let (mut current, mut smallest, mut valscratch) = (vec![], vec![], vec![]);
 
         for i in 1..self.iters.len() {
-            if self.iters[i].current(&mut current, &mut valscratch) {
-                if self.iters[next_ix].current(&mut smallest, &mut valscratch) {
-                    if self.cmp.cmp(&current, &smallest) == ord {
+            if self.iters[i].valid() {
+                if self.iters[next_ix].valid() {
+                    if self.cmp.cmp(self.iters[i].key(), self.iters[next_ix].key()) == ord {
                         next_ix = i;
                     }
                 } else {
@@ -166,13 +161,22 @@ impl LdbIterator for MergingIter {
         for i in 0..self.iters.len() {
             self.iters[i].seek(key);
         }
+        self.direction = Direction::Forward;
         self.find_smallest();
     }
+    fn seek_to_last(&mut self) {
+        for i in 0..self.iters.len() {
+            self.iters[i].seek_to_last();
+        }
+        self.direction = Direction::Reverse;
+        self.find_largest();
+    }
     fn reset(&mut self) {
         for i in 0..self.iters.len() {
             self.iters[i].reset();
         }
         self.current = None;
+        self.direction = Direction::Forward;
     }
     fn current(&self, key: &mut Vec<u8>, val: &mut Vec<u8>) -> bool {
         if let Some(ix) = self.current {
@@ -181,6 +185,18 @@ impl LdbIterator for MergingIter {
             false
         }
     }
+    fn key(&self) -> &[u8] {
+        match self.current {
+            Some(ix) => self.iters[ix].key(),
+            None => &[],
+        }
+    }
+    fn value(&self) -> &[u8] {
+        match self.current {
+            Some(ix) => self.iters[ix].value(),
+            None => &[],
+        }
+    }
     fn prev(&mut self) -> bool {
         if let Some(current) = self.current {
             if self.iters[current].valid() {
@@ -0,0 +1,71 @@
+//! Monotonic counters protect a database against rollback: protected files guarantee that each
+//! file is authentic, but not that it is the newest version. Every manifest write is bound to the
+//! next value of a counter that the untrusted host can't decrement, and DB::open refuses to
+//! recover a manifest older than the counter.
+#[cfg(feature = "mesalock_sgx")]
+use std::prelude::v1::*;
+
+use crate::error::Result;
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+/// A MonotonicCounter is a counter that can only be incremented, and whose value survives
+/// restarts. A counter must be used for a single database; it starts at 0.
+pub trait MonotonicCounter {
+    /// Returns the current value.
+    fn read(&self) -> Result<u64>;
+    /// Increments the counter and returns the new value.
+    fn increment(&self) -> Result<u64>;
+}
+
+/// A boxed and refcounted counter, analogous to BoxedFilterPolicy.
+pub type BoxedMonotonicCounter = Rc<Box<dyn MonotonicCounter>>;
+
+/// MemMonotonicCounter keeps its value in memory, which is only useful for tests. Clones share
+/// the value.
+#[derive(Clone, Default)]
+pub struct MemMonotonicCounter {
+    value: Rc<Cell<u64>>,
+}
+
+impl MemMonotonicCounter {
+    pub fn new() -> MemMonotonicCounter {
+        MemMonotonicCounter::default()
+    }
+
+    /// Returns a BoxedMonotonicCounter sharing this counter's value.
+    pub fn boxed(&self) -> BoxedMonotonicCounter {
+        Rc::new(Box::new(self.clone()))
+    }
+}
+
+impl MonotonicCounter for MemMonotonicCounter {
+    fn read(&self) -> Result<u64> {
+        Ok(self.value.get())
+    }
+
+    fn increment(&self) -> Result<u64> {
+        self.value.set(self.value.get() + 1);
+        Ok(self.value.get())
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(test_monotonic_counter_mem,)
+    }
+
+    fn test_monotonic_counter_mem() {
+        let c = MemMonotonicCounter::new();
+        let boxed = c.boxed();
+        assert_eq!(0, boxed.read().unwrap());
+        assert_eq!(1, boxed.increment().unwrap());
+        assert_eq!(2, c.increment().unwrap());
+        assert_eq!(2, boxed.read().unwrap());
+    }
+}
@@ -4,17 +4,23 @@ use std::prelude::v1::*;
 use crate::block::Block;
 use crate::cache::Cache;
 use crate::cmp::{Cmp, DefaultCmp};
+use crate::counting_env::IoStats;
 use crate::disk_env;
 
 use crate::env::Env;
+use crate::error::Result;
 use crate::filter;
 use crate::infolog::{self, Logger};
+use crate::key_provider::BoxedKeyProvider;
 use crate::mem_env::MemEnv;
+use crate::monotonic_counter::BoxedMonotonicCounter;
+use crate::rate_limiter::RateLimiter;
+use crate::slice_transform::BoxedSliceTransform;
 use crate::types::{share, Shared};
 
 use std::rc::Rc;
 
-use disk_env::DBPersistKey;
+use disk_env::{DBPersistKey, KeyRing};
 
 const KB: usize = 1 << 10;
 const MB: usize = KB * KB;
@@ -59,14 +65,52 @@ pub struct Options {
     pub compression_type: CompressionType,
     pub reuse_logs: bool,
     pub reuse_manifest: bool,
+    /// The number of log files no longer needed for recovery that are kept (the newest ones), so
+    /// that DB::get_updates_since() can still read the updates they contain.
+    pub keep_log_files: usize,
     pub filter_policy: filter::BoxedFilterPolicy,
+    /// If set, the prefixes of all keys are added to table filters as well, allowing prefix
+    /// iterators to skip tables and blocks.
+    pub prefix_extractor: Option<BoxedSliceTransform>,
+    /// If set, new tables get a single filter over all their keys instead of one filter per 2 KiB
+    /// of data blocks, so that lookups check it before reading the index. Tables with either
+    /// kind of filter can be read.
+    pub full_filters: bool,
+    /// If set, table files written by memtable flushes and compactions are written no faster
+    /// than the limiter allows. Log writes are not limited. The env must implement
+    /// Env::sleep_for(), which the default implementation doesn't inside an enclave.
+    pub rate_limiter: Option<RateLimiter>,
+    /// The counters of a CountingEnv used as env, reported by DB::get_property(). See
+    /// CountingEnv::install().
+    pub io_stats: Option<IoStats>,
+    /// The keys used by env, if it encrypts files with a KeyRing. Required by DB::rotate_key().
+    pub key_ring: Option<KeyRing>,
+    /// If set, every manifest write is bound to the next value of the counter, and opening a
+    /// database whose manifest is older than the counter fails with StatusCode::Rollback.
+    pub monotonic_counter: Option<BoxedMonotonicCounter>,
 }
 
 impl Options {
     pub fn new_disk_db_with(key: DBPersistKey) -> Options {
+        Options::new_disk_db_with_key_ring(KeyRing::new(key))
+    }
+
+    /// Returns options for a database on disk whose files are encrypted with keys derived from
+    /// the master key of provider, one per file. db_id identifies the database among those using
+    /// the same master key; see KeyRing::from_provider().
+    pub fn new_disk_db_with_provider(provider: &BoxedKeyProvider, db_id: &[u8]) -> Result<Options> {
+        Ok(Options::new_disk_db_with_key_ring(KeyRing::from_provider(
+            provider, db_id,
+        )?))
+    }
+
+    /// Returns options for a database on disk whose files are encrypted with the keys in ring.
+    pub fn new_disk_db_with_key_ring(ring: KeyRing) -> Options {
         Options {
             cmp: Rc::new(Box::new(DefaultCmp)),
-            env: Rc::new(Box::new(disk_env::PosixDiskEnv::new_with(key))),
+            env: Rc::new(Box::new(disk_env::PosixDiskEnv::new_with_key_ring(
+                ring.clone(),
+            ))),
             log: None,
             create_if_missing: true,
             error_if_exists: false,
@@ -80,8 +124,15 @@ impl Options {
             block_restart_interval: 16,
             reuse_logs: true,
             reuse_manifest: true,
+            keep_log_files: 0,
             compression_type: CompressionType::CompressionNone,
             filter_policy: Rc::new(Box::new(filter::BloomPolicy::new(DEFAULT_BITS_PER_KEY))),
+            prefix_extractor: None,
+            full_filters: false,
+            rate_limiter: None,
+            io_stats: None,
+            key_ring: Some(ring),
+            monotonic_counter: None,
         }
     }
 
@@ -102,8 +153,15 @@ impl Options {
             block_restart_interval: 16,
             reuse_logs: true,
             reuse_manifest: true,
+            keep_log_files: 0,
             compression_type: CompressionType::CompressionNone,
             filter_policy: Rc::new(Box::new(filter::BloomPolicy::new(DEFAULT_BITS_PER_KEY))),
+            prefix_extractor: None,
+            full_filters: false,
+            rate_limiter: None,
+            io_stats: None,
+            key_ring: None,
+            monotonic_counter: None,
         }
     }
 }
@@ -0,0 +1,317 @@
+//! A token bucket limiting the rate at which table files are written by flushes and compactions,
+//! so that they don't starve foreground reads of I/O bandwidth.
+#[cfg(feature = "mesalock_sgx")]
+use std::prelude::v1::*;
+
+use crate::env::Env;
+use crate::error::Result;
+use crate::types::{share, Shared};
+
+use std::cmp;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+const MICROS_PER_SEC: u64 = 1_000_000;
+
+struct Bucket {
+    bytes_per_sec: u64,
+    available: u64,
+    // The time up to which tokens have been added to the bucket; None before the first request.
+    last_refill: Option<u64>,
+    total_bytes: u64,
+}
+
+impl Bucket {
+    /// The bucket holds at most one second worth of writes.
+    fn capacity(&self) -> u64 {
+        self.bytes_per_sec
+    }
+
+    fn refill(&mut self, now: u64) {
+        let last = match self.last_refill {
+            None => {
+                self.last_refill = Some(now);
+                return;
+            }
+            Some(last) if now <= last => return,
+            Some(last) => last,
+        };
+        // Anything beyond one second fills the bucket anyway; clamping keeps the product from
+        // overflowing after long idle periods.
+        let elapsed = cmp::min(now - last, MICROS_PER_SEC);
+        let add = elapsed * self.bytes_per_sec / MICROS_PER_SEC;
+        if self.available + add >= self.capacity() {
+            self.available = self.capacity();
+            self.last_refill = Some(now);
+        } else if add > 0 {
+            self.available += add;
+            // Only account for the time spent on whole bytes, to not lose fractions.
+            self.last_refill = Some(last + add * MICROS_PER_SEC / self.bytes_per_sec);
+        }
+    }
+}
+
+/// RateLimiter is a token bucket refilled at a configurable number of bytes per second. Clones
+/// share their state, so that the rate of a limiter set in Options can be changed while the
+/// database is open.
+#[derive(Clone)]
+pub struct RateLimiter {
+    bucket: Shared<Bucket>,
+}
+
+impl RateLimiter {
+    /// Returns a new RateLimiter allowing bytes_per_sec bytes to be written per second. The
+    /// bucket starts out empty.
+    pub fn new(bytes_per_sec: usize) -> RateLimiter {
+        assert!(bytes_per_sec > 0);
+        RateLimiter {
+            bucket: share(Bucket {
+                bytes_per_sec: bytes_per_sec as u64,
+                available: 0,
+                last_refill: None,
+                total_bytes: 0,
+            }),
+        }
+    }
+
+    pub fn bytes_per_second(&self) -> usize {
+        self.bucket.borrow().bytes_per_sec as usize
+    }
+
+    /// Changes the rate; it applies to all following requests.
+    pub fn set_bytes_per_second(&self, bytes_per_sec: usize) {
+        assert!(bytes_per_sec > 0);
+        let mut b = self.bucket.borrow_mut();
+        b.bytes_per_sec = bytes_per_sec as u64;
+        b.available = cmp::min(b.available, b.capacity());
+    }
+
+    /// Returns the number of bytes that have passed through this limiter.
+    pub fn total_bytes(&self) -> usize {
+        self.bucket.borrow().total_bytes as usize
+    }
+
+    /// request blocks until n bytes may be written, using env to tell the time and to sleep. It
+    /// fails if env can't sleep.
+    pub fn request(&self, env: &dyn Env, n: usize) -> Result<()> {
+        let mut b = self.bucket.borrow_mut();
+        let mut n = n as u64;
+        b.total_bytes += n;
+        while n > 0 {
+            b.refill(env.micros());
+            if b.available == 0 {
+                let want = cmp::min(n, b.capacity());
+                // Round up, so that enough tokens are there after sleeping.
+                let wait = (want * MICROS_PER_SEC + b.bytes_per_sec - 1) / b.bytes_per_sec;
+                env.sleep_for(cmp::min(wait, u32::max_value() as u64) as u32)?;
+                continue;
+            }
+            let take = cmp::min(n, b.available);
+            b.available -= take;
+            n -= take;
+        }
+        Ok(())
+    }
+
+    /// Returns a writer passing all writes to w after they have been admitted by this limiter.
+    pub fn wrap(&self, env: Rc<Box<dyn Env>>, w: Box<dyn Write>) -> Box<dyn Write> {
+        Box::new(RateLimitedWriter {
+            limiter: self.clone(),
+            env,
+            inner: w,
+        })
+    }
+}
+
+struct RateLimitedWriter {
+    limiter: RateLimiter,
+    env: Rc<Box<dyn Env>>,
+    inner: Box<dyn Write>,
+}
+
+impl Write for RateLimitedWriter {
+    fn write(&mut self, src: &[u8]) -> io::Result<usize> {
+        self.limiter
+            .request(self.env.as_ref().as_ref(), src.len())
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.err))?;
+        self.inner.write(src)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use crate::db_impl::DB;
+    use crate::env::{FileLock, Logger, RandomAccess};
+    use crate::error::StatusCode;
+    use crate::mem_env::MemEnv;
+    use crate::options;
+    use std::cell::Cell;
+    use std::io::Read;
+    use std::path::{Path, PathBuf};
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_rate_limiter_request,
+            test_rate_limiter_set_rate,
+            test_rate_limiter_long_idle,
+            test_rate_limiter_db_writes,
+            test_rate_limiter_needs_sleep,
+        )
+    }
+
+    /// FakeClockEnv is a MemEnv whose clock only advances when sleeping.
+    #[derive(Clone)]
+    struct FakeClockEnv {
+        inner: Rc<MemEnv>,
+        now: Rc<Cell<u64>>,
+    }
+
+    impl FakeClockEnv {
+        fn new() -> FakeClockEnv {
+            FakeClockEnv {
+                inner: Rc::new(MemEnv::new()),
+                now: Rc::new(Cell::new(1000)),
+            }
+        }
+    }
+
+    impl Env for FakeClockEnv {
+        fn open_sequential_file(&self, p: &Path) -> Result<Box<dyn Read>> {
+            self.inner.open_sequential_file(p)
+        }
+        fn open_random_access_file(&self, p: &Path) -> Result<Box<dyn RandomAccess>> {
+            self.inner.open_random_access_file(p)
+        }
+        fn open_writable_file(&self, p: &Path) -> Result<Box<dyn Write>> {
+            self.inner.open_writable_file(p)
+        }
+        fn open_appendable_file(&self, p: &Path) -> Result<Box<dyn Write>> {
+            self.inner.open_appendable_file(p)
+        }
+        fn exists(&self, p: &Path) -> Result<bool> {
+            self.inner.exists(p)
+        }
+        fn children(&self, p: &Path) -> Result<Vec<PathBuf>> {
+            self.inner.children(p)
+        }
+        fn size_of(&self, p: &Path) -> Result<usize> {
+            self.inner.size_of(p)
+        }
+        fn delete(&self, p: &Path) -> Result<()> {
+            self.inner.delete(p)
+        }
+        fn mkdir(&self, p: &Path) -> Result<()> {
+            self.inner.mkdir(p)
+        }
+        fn rmdir(&self, p: &Path) -> Result<()> {
+            self.inner.rmdir(p)
+        }
+        fn rename(&self, old: &Path, new: &Path) -> Result<()> {
+            self.inner.rename(old, new)
+        }
+        fn lock(&self, p: &Path) -> Result<FileLock> {
+            self.inner.lock(p)
+        }
+        fn unlock(&self, l: FileLock) -> Result<()> {
+            self.inner.unlock(l)
+        }
+        fn new_logger(&self, p: &Path) -> Result<Logger> {
+            self.inner.new_logger(p)
+        }
+        fn micros(&self) -> u64 {
+            self.now.get()
+        }
+        fn sleep_for(&self, micros: u32) -> Result<()> {
+            self.now.set(self.now.get() + micros as u64);
+            Ok(())
+        }
+    }
+
+    fn test_rate_limiter_request() {
+        let env = FakeClockEnv::new();
+        let rl = RateLimiter::new(1000);
+
+        rl.request(&env, 500).unwrap();
+        assert_eq!(1000 + 500_000, env.micros());
+        // Requests larger than the bucket are split up.
+        rl.request(&env, 2500).unwrap();
+        assert_eq!(1000 + 3_000_000, env.micros());
+        assert_eq!(3000, rl.total_bytes());
+
+        // Idle time fills the bucket, up to one second worth of bytes.
+        env.sleep_for(5_000_000).unwrap();
+        rl.request(&env, 1000).unwrap();
+        assert_eq!(1000 + 8_000_000, env.micros());
+        rl.request(&env, 1).unwrap();
+        assert_eq!(1000 + 8_001_000, env.micros());
+    }
+
+    fn test_rate_limiter_set_rate() {
+        let env = FakeClockEnv::new();
+        let rl = RateLimiter::new(1000);
+        rl.request(&env, 1000).unwrap();
+        assert_eq!(1000 + 1_000_000, env.micros());
+
+        // Clones share the rate.
+        rl.clone().set_bytes_per_second(4000);
+        assert_eq!(4000, rl.bytes_per_second());
+        rl.request(&env, 1000).unwrap();
+        assert_eq!(1000 + 1_250_000, env.micros());
+    }
+
+    fn test_rate_limiter_long_idle() {
+        let env = FakeClockEnv::new();
+        let gb = 1 << 30;
+        let rl = RateLimiter::new(gb);
+        rl.request(&env, 1).unwrap();
+
+        // Three days without writes fill the bucket, but not more.
+        env.now.set(env.now.get() + 3 * 24 * 3600 * MICROS_PER_SEC);
+        let start = env.micros();
+        rl.request(&env, gb).unwrap();
+        assert_eq!(start, env.micros());
+        rl.request(&env, gb).unwrap();
+        assert_eq!(start + MICROS_PER_SEC, env.micros());
+    }
+
+    fn test_rate_limiter_db_writes() {
+        let env = FakeClockEnv::new();
+        let rl = RateLimiter::new(10 * 1024);
+        let mut opt = options::for_test();
+        opt.env = Rc::new(Box::new(env.clone()));
+        opt.rate_limiter = Some(rl.clone());
+
+        let mut db = DB::open("db", opt).unwrap();
+        for i in 0..100 {
+            db.put(format!("key{:03}", i).as_bytes(), &[b'x'; 100])
+                .unwrap();
+        }
+        db.flush().unwrap();
+        // Log writes are not limited.
+        assert_eq!(0, rl.total_bytes());
+        let start = env.micros();
+
+        db.compact_range(b"key", b"key999").unwrap();
+        let written = rl.total_bytes();
+        assert!(written > 100 * 100);
+        // Writing took at least as long as the rate permits.
+        assert!(env.micros() - start >= written as u64 * 1_000_000 / (10 * 1024));
+        assert_eq!(Some(vec![b'x'; 100]), db.get(b"key042"));
+    }
+
+    fn test_rate_limiter_needs_sleep() {
+        // MemEnv has the default sleep_for(), which can't sleep inside an enclave.
+        let mut opt = options::for_test();
+        opt.rate_limiter = Some(RateLimiter::new(1024));
+        assert_eq!(
+            StatusCode::NotSupported,
+            DB::open("db", opt).err().unwrap().code
+        );
+    }
+}
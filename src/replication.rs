@@ -0,0 +1,374 @@
+//! Replication of the write stream of a database (the leader) to other databases (followers).
+//!
+//! The leader reads the batches written to it from its log files (see DB::get_updates_since()),
+//! and sends every batch, together with its sequence number, as one message over a Transport.
+//! Messages are framed like log records, so a follower can detect corrupted messages. Followers
+//! apply the batches with their original sequence numbers, so that the applied position of a
+//! follower is simply its latest sequence number, which is recovered from its own log files when
+//! it is reopened.
+#[cfg(feature = "mesalock_sgx")]
+use std::prelude::v1::*;
+
+use crate::db_impl::DB;
+use crate::error::{err, Result, StatusCode};
+use crate::log::{LogReader, LogWriter};
+use crate::types::{share, SequenceNumber, Shared};
+use crate::write_batch::WriteBatch;
+
+use std::collections::VecDeque;
+
+/// A Transport carries messages from a leader to a follower. Messages must be delivered in
+/// order; delivering a message more than once is fine.
+pub trait Transport {
+    fn send(&mut self, msg: &[u8]) -> Result<()>;
+    /// Returns the next message, or None if there is no message available at the moment.
+    fn recv(&mut self) -> Result<Option<Vec<u8>>>;
+}
+
+/// ChannelTransport is an in-process Transport. Clones share the same queue of messages, so that
+/// one clone can be given to the leader and another one to the follower.
+#[derive(Clone)]
+pub struct ChannelTransport {
+    queue: Shared<VecDeque<Vec<u8>>>,
+}
+
+impl ChannelTransport {
+    pub fn new() -> ChannelTransport {
+        ChannelTransport {
+            queue: share(VecDeque::new()),
+        }
+    }
+
+    /// Returns the number of messages not yet received.
+    pub fn pending(&self) -> usize {
+        self.queue.borrow().len()
+    }
+}
+
+impl Transport for ChannelTransport {
+    fn send(&mut self, msg: &[u8]) -> Result<()> {
+        self.queue.borrow_mut().push_back(msg.to_vec());
+        Ok(())
+    }
+
+    fn recv(&mut self) -> Result<Option<Vec<u8>>> {
+        Ok(self.queue.borrow_mut().pop_front())
+    }
+}
+
+/// A Leader ships the batches written to a database to a follower.
+pub struct Leader<T: Transport> {
+    transport: T,
+    // The sequence number of the next operation to ship.
+    next_seq: SequenceNumber,
+}
+
+impl<T: Transport> Leader<T> {
+    /// Returns a leader shipping batches starting at sequence number next_seq; typically, this is
+    /// Follower::next_sequence() of the follower.
+    pub fn new(transport: T, next_seq: SequenceNumber) -> Leader<T> {
+        Leader {
+            transport,
+            next_seq: next_seq.max(1),
+        }
+    }
+
+    /// Returns the sequence number of the next operation to be shipped.
+    pub fn next_sequence(&self) -> SequenceNumber {
+        self.next_seq
+    }
+
+    /// ship sends all batches written to db since the last call, and returns how many batches
+    /// were sent. If the log files of db don't contain the next batch anymore, an error with
+    /// StatusCode::NotFound is returned (see Options::keep_log_files).
+    pub fn ship(&mut self, db: &mut DB) -> Result<usize> {
+        let mut shipped = 0;
+        for update in db.get_updates_since(self.next_seq)? {
+            let (seq, batch) = update?;
+            let count = batch.count() as u64;
+            self.transport.send(&encode_message(batch, seq)?)?;
+            self.next_seq = seq + count;
+            shipped += 1;
+        }
+        Ok(shipped)
+    }
+}
+
+/// A Follower applies the batches shipped by a leader to a database. The database should not be
+/// written to by other means, as this breaks the continuity of sequence numbers.
+pub struct Follower<T: Transport> {
+    transport: T,
+    // A received message whose batch could not be written yet; it is retried before receiving
+    // more messages.
+    pending: Option<Vec<u8>>,
+}
+
+impl<T: Transport> Follower<T> {
+    pub fn new(transport: T) -> Follower<T> {
+        Follower {
+            transport,
+            pending: None,
+        }
+    }
+
+    /// Returns the sequence number of the next operation db expects from the leader.
+    pub fn next_sequence(&self, db: &DB) -> SequenceNumber {
+        db.latest_sequence_number() + 1
+    }
+
+    /// apply applies all messages available from the transport to db, and returns how many
+    /// batches were applied. Batches that have already been applied are skipped. A batch that
+    /// doesn't directly follow the latest applied one results in an error with
+    /// StatusCode::InvalidArgument; a malformed message in an error with StatusCode::Corruption.
+    /// Such messages are dropped, as they can never be applied; the leader has to ship again
+    /// starting at next_sequence(). If writing a batch fails, the batch is kept and retried by
+    /// the next call. `sync` is passed to DB::write_replicated() for every batch.
+    pub fn apply(&mut self, db: &mut DB, sync: bool) -> Result<usize> {
+        let mut applied = 0;
+        loop {
+            let msg = match self.pending.take() {
+                Some(msg) => msg,
+                None => match self.transport.recv()? {
+                    Some(msg) => msg,
+                    None => break,
+                },
+            };
+            let batch = decode_message(&msg)?;
+            let next = self.next_sequence(db);
+            if batch.sequence() + batch.count() as u64 <= next {
+                continue;
+            }
+            if batch.sequence() != next {
+                return err(
+                    StatusCode::InvalidArgument,
+                    &format!(
+                        "replicated batch starts at sequence number {}, expected {}",
+                        batch.sequence(),
+                        next
+                    ),
+                );
+            }
+            if let Err(e) = db.write_replicated(batch, sync) {
+                self.pending = Some(msg);
+                return Err(e);
+            }
+            applied += 1;
+        }
+        Ok(applied)
+    }
+}
+
+/// encode_message frames a batch starting at seq as a log record.
+fn encode_message(batch: WriteBatch, seq: SequenceNumber) -> Result<Vec<u8>> {
+    let mut msg = vec![];
+    LogWriter::new(&mut msg).add_record(&batch.encode(seq))?;
+    Ok(msg)
+}
+
+fn decode_message(msg: &[u8]) -> Result<WriteBatch> {
+    let mut rec = vec![];
+    let mut reader = LogReader::new(msg, true);
+    if reader.read(&mut rec)? == 0 || reader.read(&mut vec![])? != 0 {
+        return err(
+            StatusCode::Corruption,
+            "replication message is not a single record",
+        );
+    }
+    let batch = WriteBatch::from_bytes(&rec)?;
+    if batch.sequence() == 0 || batch.count() == 0 {
+        return err(
+            StatusCode::Corruption,
+            "replication message contains no operations",
+        );
+    }
+    Ok(batch)
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use crate::options;
+    use crate::types::LdbIterator;
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_replication,
+            test_replication_continuity,
+            test_replication_corrupt_message,
+            test_replication_reopen_follower,
+            test_replication_retry,
+        )
+    }
+
+    fn contents(db: &mut DB) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut iter = db.new_iter().unwrap();
+        let mut result = vec![];
+        while let Some(kv) = iter.next() {
+            result.push(kv);
+        }
+        result
+    }
+
+    fn test_replication() {
+        let mut leader_db = DB::open("leaderdb", options::for_test()).unwrap();
+        let mut follower_db = DB::open("followerdb", options::for_test()).unwrap();
+        let transport = ChannelTransport::new();
+        let mut follower = Follower::new(transport.clone());
+        let mut leader = Leader::new(transport.clone(), follower.next_sequence(&follower_db));
+
+        leader_db.put(b"a", b"1").unwrap();
+        let mut wb = WriteBatch::new();
+        wb.put(b"b", b"2");
+        wb.put(b"c", b"3");
+        wb.delete(b"a");
+        leader_db.write(wb, false).unwrap();
+
+        assert_eq!(2, leader.ship(&mut leader_db).unwrap());
+        assert_eq!(2, transport.pending());
+        assert_eq!(5, leader.next_sequence());
+        assert_eq!(2, follower.apply(&mut follower_db, false).unwrap());
+        assert_eq!(0, transport.pending());
+        assert_eq!(4, follower_db.latest_sequence_number());
+        assert_eq!(contents(&mut leader_db), contents(&mut follower_db));
+
+        // Nothing new to ship.
+        assert_eq!(0, leader.ship(&mut leader_db).unwrap());
+        assert_eq!(0, follower.apply(&mut follower_db, false).unwrap());
+
+        leader_db.put(b"d", b"4").unwrap();
+        leader_db.delete(b"b").unwrap();
+        assert_eq!(2, leader.ship(&mut leader_db).unwrap());
+        assert_eq!(2, follower.apply(&mut follower_db, true).unwrap());
+        assert_eq!(6, follower_db.latest_sequence_number());
+        assert_eq!(contents(&mut leader_db), contents(&mut follower_db));
+        assert_eq!(
+            vec![
+                (b"c".to_vec(), b"3".to_vec()),
+                (b"d".to_vec(), b"4".to_vec())
+            ],
+            contents(&mut follower_db)
+        );
+    }
+
+    fn test_replication_continuity() {
+        let mut leader_db = DB::open("leaderdb", options::for_test()).unwrap();
+        let mut follower_db = DB::open("followerdb", options::for_test()).unwrap();
+        let transport = ChannelTransport::new();
+        let mut follower = Follower::new(transport.clone());
+
+        leader_db.put(b"a", b"1").unwrap();
+        leader_db.put(b"b", b"2").unwrap();
+        leader_db.put(b"c", b"3").unwrap();
+
+        // Batches that were already applied are skipped.
+        let mut leader = Leader::new(transport.clone(), 1);
+        leader.ship(&mut leader_db).unwrap();
+        let mut leader = Leader::new(transport.clone(), 2);
+        leader.ship(&mut leader_db).unwrap();
+        assert_eq!(5, transport.pending());
+        assert_eq!(3, follower.apply(&mut follower_db, false).unwrap());
+        assert_eq!(3, follower_db.latest_sequence_number());
+
+        // A gap is detected and stops replication.
+        leader_db.put(b"d", b"4").unwrap();
+        leader_db.put(b"e", b"5").unwrap();
+        let mut leader = Leader::new(transport.clone(), 5);
+        leader.ship(&mut leader_db).unwrap();
+        let e = follower.apply(&mut follower_db, false).unwrap_err();
+        assert_eq!(StatusCode::InvalidArgument, e.code);
+        assert_eq!(3, follower_db.latest_sequence_number());
+        assert_eq!(None, follower_db.get(b"e"));
+
+        let mut wb = WriteBatch::new();
+        wb.put(b"x", b"y");
+        let msg = encode_message(wb, 4).unwrap();
+        let mut wb = WriteBatch::new();
+        wb.put(b"x", b"z");
+        assert!(follower_db.write_replicated(wb, false).is_err());
+        follower_db
+            .write_replicated(decode_message(&msg).unwrap(), false)
+            .unwrap();
+        assert_eq!(Some(b"y".to_vec()), follower_db.get(b"x"));
+        assert_eq!(4, follower_db.latest_sequence_number());
+    }
+
+    fn test_replication_corrupt_message() {
+        let mut follower_db = DB::open("followerdb", options::for_test()).unwrap();
+        let mut transport = ChannelTransport::new();
+        let mut follower = Follower::new(transport.clone());
+
+        let mut wb = WriteBatch::new();
+        wb.put(b"a", b"1");
+        let mut msg = encode_message(wb, 1).unwrap();
+        let last = msg.len() - 1;
+        msg[last] ^= 1;
+        transport.send(&msg).unwrap();
+        assert_eq!(
+            StatusCode::Corruption,
+            follower.apply(&mut follower_db, false).unwrap_err().code
+        );
+
+        // Empty batches are not shipped.
+        transport
+            .send(&encode_message(WriteBatch::new(), 1).unwrap())
+            .unwrap();
+        assert_eq!(
+            StatusCode::Corruption,
+            follower.apply(&mut follower_db, false).unwrap_err().code
+        );
+        assert_eq!(0, follower_db.latest_sequence_number());
+    }
+
+    fn test_replication_reopen_follower() {
+        let mut leader_db = DB::open("leaderdb", options::for_test()).unwrap();
+        let opt = options::for_test();
+        let mut follower_db = DB::open("followerdb", opt.clone()).unwrap();
+        let transport = ChannelTransport::new();
+        let mut leader = Leader::new(transport.clone(), 1);
+        let mut follower = Follower::new(transport.clone());
+
+        leader_db.put(b"a", b"1").unwrap();
+        leader_db.put(b"b", b"2").unwrap();
+        leader.ship(&mut leader_db).unwrap();
+        follower.apply(&mut follower_db, true).unwrap();
+        follower_db.compact_range(b"a", b"b").unwrap();
+        leader_db.put(b"c", b"3").unwrap();
+        leader.ship(&mut leader_db).unwrap();
+        follower.apply(&mut follower_db, true).unwrap();
+
+        // The applied position survives reopening the follower.
+        drop(follower_db);
+        let mut follower_db = DB::open("followerdb", opt).unwrap();
+        assert_eq!(4, follower.next_sequence(&follower_db));
+
+        leader_db.put(b"d", b"4").unwrap();
+        let mut leader = Leader::new(transport.clone(), follower.next_sequence(&follower_db));
+        assert_eq!(1, leader.ship(&mut leader_db).unwrap());
+        assert_eq!(1, follower.apply(&mut follower_db, true).unwrap());
+        assert_eq!(contents(&mut leader_db), contents(&mut follower_db));
+    }
+
+    fn test_replication_retry() {
+        let mut leader_db = DB::open("leaderdb", options::for_test()).unwrap();
+        let opt = options::for_test();
+        drop(DB::open("followerdb", opt.clone()).unwrap());
+        // Writes to a read-only database fail.
+        let mut follower_db = DB::open_read_only("followerdb", opt.clone()).unwrap();
+        let transport = ChannelTransport::new();
+        let mut leader = Leader::new(transport.clone(), 1);
+        let mut follower = Follower::new(transport.clone());
+
+        leader_db.put(b"a", b"1").unwrap();
+        leader_db.put(b"b", b"2").unwrap();
+        leader.ship(&mut leader_db).unwrap();
+        assert!(follower.apply(&mut follower_db, false).is_err());
+        assert_eq!(1, transport.pending());
+
+        // The batch that failed is not lost.
+        drop(follower_db);
+        let mut follower_db = DB::open("followerdb", opt).unwrap();
+        assert_eq!(2, follower.apply(&mut follower_db, false).unwrap());
+        assert_eq!(contents(&mut leader_db), contents(&mut follower_db));
+    }
+}
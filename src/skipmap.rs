@@ -15,10 +15,12 @@ const MAX_HEIGHT: usize = 12;
 const BRANCHING_FACTOR: u32 = 4;
 
 /// A node in a skipmap contains links to the next node and others that are further away (skips);
-/// `skips[0]` is the immediate element after, that is, the element contained in `next`.
+/// `skips[0]` is the immediate element after, that is, the element contained in `next`. `prev`
+/// links back to the immediate element before (the head node for the first element).
 struct Node {
     skips: Vec<Option<*mut Node>>,
     next: Option<Box<Node>>,
+    prev: Option<*mut Node>,
     key: Vec<u8>,
     value: Vec<u8>,
 }
@@ -56,6 +58,7 @@ impl SkipMap {
                 head: Box::new(Node {
                     skips: s,
                     next: None,
+                    prev: None,
                     key: Vec::new(),
                     value: Vec::new(),
                 }),
@@ -153,6 +156,31 @@ impl InnerSkipMap {
         }
     }
 
+    /// Returns the last node, or None if the map is empty.
+    fn get_last<'a>(&'a self) -> Option<&'a Node> {
+        let mut current = self.head.as_ref() as *const Node;
+        let mut level = self.head.skips.len() - 1;
+
+        loop {
+            unsafe {
+                if let Some(next) = (*current).skips[level] {
+                    current = next;
+                    continue;
+                }
+            }
+            if level == 0 {
+                break;
+            }
+            level -= 1;
+        }
+
+        if current == self.head.as_ref() {
+            None
+        } else {
+            unsafe { Some(&(*current)) }
+        }
+    }
+
     /// Finds the node immediately before the node with key.
     /// Returns None if no smaller key was found.
     fn get_next_smaller<'a>(&'a self, key: &[u8]) -> Option<&'a Node> {
@@ -241,6 +269,7 @@ impl InnerSkipMap {
         let mut new = Box::new(Node {
             skips: new_skips,
             next: None,
+            prev: Some(current),
             key,
             value: val,
         });
@@ -265,6 +294,9 @@ impl InnerSkipMap {
         // Insert new node by first replacing the previous element's next field with None and
         // assigning its value to new.next...
         new.next = unsafe { replace(&mut (*current).next, None) };
+        if let Some(ref mut next) = new.next {
+            next.prev = Some(newp);
+        }
         // ...and then setting the previous element's next field to the new node
         unsafe { replace(&mut (*current).next, Some(new)) };
     }
@@ -340,15 +372,10 @@ impl LdbIterator for SkipMapIter {
         }
     }
     fn prev(&mut self) -> bool {
-        // Going after the original implementation here; we just seek to the node before current().
         if self.valid() {
-            if let Some(prev) = self
-                .map
-                .borrow()
-                .get_next_smaller(unsafe { &(*self.current).key })
-            {
+            if let Some(prev) = unsafe { (*self.current).prev } {
                 self.current = prev as *const Node;
-                if !prev.key.is_empty() {
+                if self.valid() {
                     return true;
                 }
             }
@@ -356,6 +383,21 @@ impl LdbIterator for SkipMapIter {
         self.reset();
         false
     }
+    fn seek_to_last(&mut self) {
+        if let Some(node) = self.map.borrow().get_last() {
+            self.current = node as *const Node;
+            return;
+        }
+        self.reset();
+    }
+    // Nodes are never removed while the map is alive, and the iterator holds a reference to it.
+    // The head node has an empty key and value, so no validity check is needed.
+    fn key(&self) -> &[u8] {
+        unsafe { &(*self.current).key }
+    }
+    fn value(&self) -> &[u8] {
+        unsafe { &(*self.current).value }
+    }
 }
 
 #[cfg(feature = "enclave_unit_test")]
@@ -381,6 +423,7 @@ pub mod tests {
             test_skipmap_iterator_seek_valid,
             test_skipmap_behavior,
             test_skipmap_iterator_prev,
+            test_skipmap_iterator_reverse,
             test_skipmap_iterator_concurrent_insert,
         )
     }
@@ -594,6 +637,32 @@ pub mod tests {
         );
     }
 
+    fn test_skipmap_iterator_reverse() {
+        let mut skm = make_skipmap();
+        // Back links must be maintained for insertions in the middle and at both ends.
+        skm.insert("abcc".as_bytes().to_vec(), "def".as_bytes().to_vec());
+        skm.insert("aa".as_bytes().to_vec(), "def".as_bytes().to_vec());
+        skm.insert("ac".as_bytes().to_vec(), "def".as_bytes().to_vec());
+
+        let mut iter = skm.iter();
+        let mut fwd: Vec<Vec<u8>> = LdbIteratorIter::wrap(&mut iter).map(|(k, _)| k).collect();
+        fwd.reverse();
+
+        let mut bwd = vec![];
+        iter.seek_to_last();
+        while iter.valid() {
+            bwd.push(current_key_val(&iter).unwrap().0);
+            iter.prev();
+        }
+        assert_eq!(29, bwd.len());
+        assert_eq!(fwd, bwd);
+
+        let skm = SkipMap::new(options::for_test().cmp);
+        let mut iter = skm.iter();
+        iter.seek_to_last();
+        assert!(!iter.valid());
+    }
+
     fn test_skipmap_iterator_concurrent_insert() {
         // Asserts that the map can be mutated while an iterator exists; this is intentional.
         let mut skm = make_skipmap();
@@ -0,0 +1,78 @@
+#[cfg(feature = "mesalock_sgx")]
+use std::prelude::v1::*;
+
+use std::rc::Rc;
+
+/// A SliceTransform extracts a prefix from user keys. If one is set in Options, the prefixes of
+/// all keys are added to table filters, so that iterators restricted to a prefix (see
+/// DB::new_prefix_iter()) can skip tables and blocks not containing any key with that prefix.
+pub trait SliceTransform {
+    /// Returns a string identifying this transform. It is recorded in every table built with the
+    /// transform, and prefix filters are only used if it matches the configured transform.
+    fn name(&self) -> String;
+    /// Returns the prefix of key. Only called for keys for which in_domain() returns true.
+    fn transform<'a>(&self, key: &'a [u8]) -> &'a [u8];
+    /// Returns whether a prefix can be extracted from key.
+    fn in_domain(&self, key: &[u8]) -> bool;
+}
+
+/// A boxed and refcounted slice transform, analogous to BoxedFilterPolicy.
+pub type BoxedSliceTransform = Rc<Box<dyn SliceTransform>>;
+
+impl SliceTransform for BoxedSliceTransform {
+    fn name(&self) -> String {
+        (**self).name()
+    }
+    fn transform<'a>(&self, key: &'a [u8]) -> &'a [u8] {
+        (**self).transform(key)
+    }
+    fn in_domain(&self, key: &[u8]) -> bool {
+        (**self).in_domain(key)
+    }
+}
+
+/// FixedPrefixTransform uses the first `len` bytes of a key as its prefix. Keys shorter than
+/// that have no prefix.
+#[derive(Clone)]
+pub struct FixedPrefixTransform {
+    len: usize,
+}
+
+impl FixedPrefixTransform {
+    /// Returns a new FixedPrefixTransform wrapped in a BoxedSliceTransform.
+    pub fn new(len: usize) -> BoxedSliceTransform {
+        Rc::new(Box::new(FixedPrefixTransform { len }))
+    }
+}
+
+impl SliceTransform for FixedPrefixTransform {
+    fn name(&self) -> String {
+        format!("leveldb.FixedPrefix.{}", self.len)
+    }
+    fn transform<'a>(&self, key: &'a [u8]) -> &'a [u8] {
+        &key[..self.len]
+    }
+    fn in_domain(&self, key: &[u8]) -> bool {
+        key.len() >= self.len
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(test_fixed_prefix_transform,)
+    }
+
+    fn test_fixed_prefix_transform() {
+        let t = FixedPrefixTransform::new(3);
+        assert_eq!("leveldb.FixedPrefix.3", t.name());
+        assert!(t.in_domain(b"abc"));
+        assert!(t.in_domain(b"abcdef"));
+        assert!(!t.in_domain(b"ab"));
+        assert_eq!(b"abc", t.transform(b"abcdef"));
+        assert_eq!(b"abc", t.transform(b"abc"));
+    }
+}
@@ -1,4 +1,4 @@
-use crate::types::{share, SequenceNumber, Shared, MAX_SEQUENCE_NUMBER};
+use crate::types::{share, SequenceNumber, Shared};
 use std::collections::HashMap;
 
 use std::rc::Rc;
@@ -21,12 +21,15 @@ impl Drop for InnerSnapshot {
     }
 }
 
+/// A Snapshot is a consistent, read-only view of the database, obtained from DB::get_snapshot().
+/// Entries visible at a snapshot are not removed by compactions while the snapshot is alive.
 #[derive(Clone)]
 pub struct Snapshot {
     inner: Rc<InnerSnapshot>,
 }
 
 impl Snapshot {
+    /// Returns the sequence number of the most recent write visible at this snapshot.
     pub fn sequence(&self) -> SequenceNumber {
         (*self.inner).seq
     }
@@ -35,8 +38,8 @@ impl Snapshot {
 /// A list of all snapshots is kept in the DB.
 struct InnerSnapshotList {
     map: HashMap<SnapshotHandle, SequenceNumber>,
+    // The handle of the most recently created snapshot.
     newest: SnapshotHandle,
-    oldest: SnapshotHandle,
 }
 
 pub struct SnapshotList {
@@ -49,7 +52,6 @@ impl SnapshotList {
             inner: share(InnerSnapshotList {
                 map: HashMap::new(),
                 newest: 0,
-                oldest: 0,
             }),
         }
     }
@@ -62,13 +64,9 @@ impl SnapshotList {
         let newest = sl.newest;
         sl.map.insert(newest, seq);
 
-        if sl.oldest == 0 {
-            sl.oldest = sl.newest;
-        }
-
         Snapshot {
             inner: Rc::new(InnerSnapshot {
-                id: sl.newest,
+                id: newest,
                 seq,
                 sl: inner,
             }),
@@ -78,34 +76,25 @@ impl SnapshotList {
     /// oldest returns the lowest sequence number of all snapshots. It returns 0 if no snapshots
     /// are present.
     pub fn oldest(&self) -> SequenceNumber {
-        let oldest = self
-            .inner
-            .borrow()
-            .map
-            .iter()
-            .fold(
-                MAX_SEQUENCE_NUMBER,
-                |s, (seq, _)| if *seq < s { *seq } else { s },
-            );
-        if oldest == MAX_SEQUENCE_NUMBER {
-            0
-        } else {
-            oldest
-        }
+        self.inner.borrow().map.values().min().cloned().unwrap_or(0)
     }
 
     /// newest returns the newest sequence number of all snapshots. If no snapshots are present, it
     /// returns 0.
     pub fn newest(&self) -> SequenceNumber {
-        self.inner
-            .borrow()
-            .map
-            .iter()
-            .fold(0, |s, (seq, _)| if *seq > s { *seq } else { s })
+        self.inner.borrow().map.values().max().cloned().unwrap_or(0)
+    }
+
+    /// sequences returns the sequence numbers of all snapshots in ascending order. Several
+    /// snapshots may have the same sequence number.
+    pub fn sequences(&self) -> Vec<SequenceNumber> {
+        let mut seqs: Vec<SequenceNumber> = self.inner.borrow().map.values().cloned().collect();
+        seqs.sort();
+        seqs
     }
 
     pub fn empty(&self) -> bool {
-        self.inner.borrow().oldest == 0
+        self.inner.borrow().map.is_empty()
     }
 }
 
@@ -122,7 +111,7 @@ pub mod tests {
     use teaclave_test_utils::*;
 
     pub fn run_tests() -> bool {
-        run_tests!(test_snapshot_list,)
+        run_tests!(test_snapshot_list, test_snapshot_list_sequences,)
     }
 
     #[allow(unused_variables)]
@@ -152,4 +141,34 @@ pub mod tests {
         }
         assert_eq!(l.oldest(), 0);
     }
+
+    #[allow(unused_variables)]
+    fn test_snapshot_list_sequences() {
+        let mut l = SnapshotList::new();
+        let a = l.new_snapshot(20);
+        let b = l.new_snapshot(10);
+        let c = l.new_snapshot(30);
+        let d = l.new_snapshot(10);
+        assert_eq!(10, l.oldest());
+        assert_eq!(30, l.newest());
+        assert_eq!(vec![10, 10, 20, 30], l.sequences());
+
+        drop(b);
+        assert_eq!(10, l.oldest());
+        drop(d);
+        drop(c);
+        assert_eq!(20, l.oldest());
+        assert_eq!(20, l.newest());
+        assert_eq!(vec![20], l.sequences());
+
+        // Clones keep the snapshot alive.
+        let a2 = a.clone();
+        drop(a);
+        assert!(!l.empty());
+        assert_eq!(20, a2.sequence());
+        drop(a2);
+        assert!(l.empty());
+        assert_eq!(0, l.oldest());
+        assert_eq!(0, l.newest());
+    }
 }
@@ -0,0 +1,113 @@
+//! The state digest authenticates the live state of a database: its table files, by number, size
+//! and content hash, and the last sequence number. It is a Merkle tree over the tables, so that
+//! it can go into an attestation report and be checked against a list of tables.
+//!
+//! leaf(table) = SHA-256(0x00 | number (8 bytes, LE) | size (8 bytes, LE) | content hash)
+//! node(l, r)  = SHA-256(0x01 | l | r)
+//! digest      = SHA-256(0x02 | root | last sequence (8 bytes, LE))
+//!
+//! Leaves are ordered by file number. An unpaired node is carried up to the next level unchanged,
+//! and the root of an empty tree is all zeros. Tables without a known content hash use all zeros.
+#[cfg(feature = "mesalock_sgx")]
+use std::prelude::v1::*;
+
+use crate::types::{FileMetaData, SequenceNumber, TableHash};
+
+use integer_encoding::FixedInt;
+use sha2::{Digest, Sha256};
+
+/// A digest over the live state of a database, see DB::state_digest().
+pub type StateDigest = [u8; 32];
+
+const LEAF: u8 = 0;
+const NODE: u8 = 1;
+const ROOT: u8 = 2;
+
+fn hash(parts: &[&[u8]]) -> [u8; 32] {
+    let mut h = Sha256::new();
+    for p in parts {
+        h.update(p);
+    }
+    let mut out = [0; 32];
+    out.copy_from_slice(&h.finalize());
+    out
+}
+
+/// Returns the SHA-256 hash of a table's contents.
+pub fn table_hash(contents: &[u8]) -> TableHash {
+    hash(&[contents])
+}
+
+fn leaf(f: &FileMetaData) -> [u8; 32] {
+    let no_hash = [0; 32];
+    hash(&[
+        &[LEAF],
+        &f.num.encode_fixed_vec(),
+        &(f.size as u64).encode_fixed_vec(),
+        f.content_hash.as_ref().unwrap_or(&no_hash),
+    ])
+}
+
+/// Returns the digest over the given tables, in any order, and the last sequence number.
+pub fn state_digest<'a, I: IntoIterator<Item = &'a FileMetaData>>(
+    tables: I,
+    last_seq: SequenceNumber,
+) -> StateDigest {
+    let mut tables: Vec<&FileMetaData> = tables.into_iter().collect();
+    tables.sort_by_key(|f| f.num);
+    let mut level: Vec<[u8; 32]> = tables.into_iter().map(leaf).collect();
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [l, r] => hash(&[&[NODE], l, r]),
+                [single] => *single,
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+    let root = level.pop().unwrap_or([0; 32]);
+    hash(&[&[ROOT], &root, &last_seq.encode_fixed_vec()])
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(test_state_digest,)
+    }
+
+    fn table(num: u64, size: usize, contents: &[u8]) -> FileMetaData {
+        FileMetaData {
+            num,
+            size,
+            content_hash: Some(table_hash(contents)),
+            ..Default::default()
+        }
+    }
+
+    fn test_state_digest() {
+        let (a, b, c) = (table(5, 10, b"a"), table(7, 20, b"b"), table(9, 30, b"c"));
+        let d = state_digest(vec![&a, &b, &c], 100);
+
+        // The order of tables doesn't matter, their levels and key ranges aren't covered.
+        let mut b2 = b.clone();
+        b2.smallest = b"x".to_vec();
+        assert_eq!(d, state_digest(vec![&c, &b2, &a], 100));
+
+        // Everything else is.
+        assert!(d != state_digest(vec![&a, &b, &c], 101));
+        assert!(d != state_digest(vec![&a, &b], 100));
+        assert!(d != state_digest(vec![&a, &b, &table(9, 30, b"d")], 100));
+        assert!(d != state_digest(vec![&a, &b, &table(9, 31, b"c")], 100));
+        assert!(d != state_digest(vec![&a, &b, &table(10, 30, b"c")], 100));
+        let mut unhashed = c.clone();
+        unhashed.content_hash = None;
+        assert!(d != state_digest(vec![&a, &b, &unhashed], 100));
+
+        assert!(state_digest(vec![], 0) != state_digest(vec![], 1));
+        assert!(state_digest(vec![], 0) != state_digest(vec![&a], 0));
+    }
+}
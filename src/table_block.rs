@@ -6,7 +6,7 @@ use crate::blockhandle::BlockHandle;
 use crate::env::RandomAccess;
 use crate::error::{err, Result, StatusCode};
 use crate::filter;
-use crate::filter_block::FilterBlockReader;
+use crate::filter_block::{FilterBlockReader, FullFilterBlockReader};
 use crate::log::unmask_crc;
 use crate::options::{self, CompressionType, Options};
 use crate::table_builder;
@@ -37,6 +37,22 @@ pub fn read_filter_block(
     Ok(FilterBlockReader::new_owned(policy, buf))
 }
 
+/// Reads a serialized full filter block from a file and returns a FullFilterBlockReader.
+pub fn read_full_filter_block(
+    src: &dyn RandomAccess,
+    location: &BlockHandle,
+    policy: filter::BoxedFilterPolicy,
+) -> Result<FullFilterBlockReader> {
+    if location.size() == 0 {
+        return err(
+            StatusCode::InvalidArgument,
+            "no filter block in empty location",
+        );
+    }
+    let buf = read_bytes(src, location)?;
+    Ok(FullFilterBlockReader::new_owned(policy, buf))
+}
+
 /// Reads a table block from a random-access source.
 /// A table block consists of [bytes..., compress (1B), checksum (4B)]; the handle only refers to
 /// the location and length of [bytes...].
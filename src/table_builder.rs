@@ -7,10 +7,12 @@ use crate::blockhandle::BlockHandle;
 use crate::cmp::InternalKeyCmp;
 use crate::error::Result;
 use crate::filter::{InternalFilterPolicy, NoFilterPolicy};
-use crate::filter_block::FilterBlockBuilder;
-use crate::key_types::InternalKey;
+use crate::filter_block::{FilterBlockBuilder, FullFilterBlockBuilder};
+use crate::key_types::{parse_internal_key, InternalKey, LookupKey};
 use crate::log::mask_crc;
 use crate::options::{CompressionType, Options};
+use crate::slice_transform::{BoxedSliceTransform, SliceTransform};
+use crate::types::{TableHash, MAX_SEQUENCE_NUMBER};
 
 use std::cmp::Ordering;
 use std::io::Write;
@@ -18,7 +20,8 @@ use std::rc::Rc;
 
 use crc::crc32;
 use crc::Hasher32;
-use integer_encoding::FixedIntWriter;
+use integer_encoding::FixedInt;
+use sha2::{Digest, Sha256};
 use snap::Encoder;
 
 pub const FOOTER_LENGTH: usize = 40;
@@ -89,12 +92,18 @@ pub struct TableBuilder<Dst: Write> {
     dst: Dst,
 
     offset: usize,
+    // The hash of everything written to dst.
+    hash: Sha256,
     num_entries: usize,
     prev_block_last_key: Vec<u8>,
 
     data_block: Option<BlockBuilder>,
     index_block: Option<BlockBuilder>,
     filter_block: Option<FilterBlockBuilder>,
+    // Replaces filter_block if opt.full_filters is set.
+    full_filter: Option<FullFilterBlockBuilder>,
+    // Only set for tables with internal keys.
+    prefix_extractor: Option<BoxedSliceTransform>,
 }
 
 impl<Dst: Write> TableBuilder<Dst> {
@@ -109,24 +118,42 @@ impl<Dst: Write> TableBuilder<Dst> {
 impl<Dst: Write> TableBuilder<Dst> {
     /// Create a new table builder.
     /// The comparator in opt will be wrapped in a InternalKeyCmp, and the filter policy
-    /// in an InternalFilterPolicy.
+    /// in an InternalFilterPolicy. If opt contains a prefix extractor, the prefixes of all keys
+    /// are added to the filters, too.
     pub fn new(mut opt: Options, dst: Dst) -> TableBuilder<Dst> {
         opt.cmp = Rc::new(Box::new(InternalKeyCmp(opt.cmp.clone())));
         opt.filter_policy = Rc::new(Box::new(InternalFilterPolicy::new(opt.filter_policy)));
-        TableBuilder::new_raw(opt, dst)
+        let prefix_extractor = opt.prefix_extractor.clone();
+        let mut b = TableBuilder::new_raw(opt, dst);
+        b.prefix_extractor = prefix_extractor;
+        b
     }
 
     /// Like new(), but doesn't wrap the comparator in an InternalKeyCmp (for testing)
     pub fn new_raw(opt: Options, dst: Dst) -> TableBuilder<Dst> {
+        let (filter_block, full_filter) = if opt.full_filters {
+            (
+                None,
+                Some(FullFilterBlockBuilder::new(opt.filter_policy.clone())),
+            )
+        } else {
+            (
+                Some(FilterBlockBuilder::new(opt.filter_policy.clone())),
+                None,
+            )
+        };
         TableBuilder {
             opt: opt.clone(),
             dst,
             offset: 0,
+            hash: Sha256::new(),
             prev_block_last_key: vec![],
             num_entries: 0,
             data_block: Some(BlockBuilder::new(opt.clone())),
-            filter_block: Some(FilterBlockBuilder::new(opt.filter_policy.clone())),
+            filter_block,
+            full_filter,
             index_block: Some(BlockBuilder::new(opt)),
+            prefix_extractor: None,
         }
     }
 
@@ -145,6 +172,9 @@ impl<Dst: Write> TableBuilder<Dst> {
         if let Some(ref b) = self.filter_block {
             size += b.size_estimate();
         }
+        if let Some(ref b) = self.full_filter {
+            size += b.size_estimate();
+        }
         size + self.offset + FULL_FOOTER_LENGTH
     }
 
@@ -162,8 +192,29 @@ impl<Dst: Write> TableBuilder<Dst> {
 
         let dblock = &mut self.data_block.as_mut().unwrap();
 
+        // Prefixes are stored as internal keys, as the filter policy expects them.
+        let prefix = match self.prefix_extractor {
+            Some(ref pe) => {
+                let (_, _, ukey) = parse_internal_key(key);
+                if pe.in_domain(ukey) {
+                    Some(LookupKey::new(pe.transform(ukey), MAX_SEQUENCE_NUMBER))
+                } else {
+                    None
+                }
+            }
+            None => None,
+        };
         if let Some(ref mut fblock) = self.filter_block {
             fblock.add_key(key);
+            if let Some(ref prefix) = prefix {
+                fblock.add_prefix(prefix.internal_key());
+            }
+        }
+        if let Some(ref mut fblock) = self.full_filter {
+            fblock.add_key(key);
+            if let Some(ref prefix) = prefix {
+                fblock.add_prefix(prefix.internal_key());
+            }
         }
 
         self.num_entries += 1;
@@ -214,9 +265,9 @@ impl<Dst: Write> TableBuilder<Dst> {
         digest.write(&data);
         digest.write(&[ctype as u8; TABLE_BLOCK_COMPRESS_LEN]);
 
-        self.dst.write(&data)?;
-        self.dst.write(&[ctype as u8; TABLE_BLOCK_COMPRESS_LEN])?;
-        self.dst.write_fixedint(mask_crc(digest.sum32()))?;
+        self.write_raw(&data)?;
+        self.write_raw(&[ctype as u8; TABLE_BLOCK_COMPRESS_LEN])?;
+        self.write_raw(&mask_crc(digest.sum32()).encode_fixed_vec())?;
 
         let handle = BlockHandle::new(self.offset, data.len());
         self.offset += data.len() + TABLE_BLOCK_COMPRESS_LEN + TABLE_BLOCK_CKSUM_LEN;
@@ -224,7 +275,20 @@ impl<Dst: Write> TableBuilder<Dst> {
         Ok(handle)
     }
 
-    pub fn finish(mut self) -> Result<usize> {
+    /// Writes data to dst, and adds it to the content hash.
+    fn write_raw(&mut self, data: &[u8]) -> Result<()> {
+        self.hash.update(data);
+        self.dst.write_all(data)?;
+        Ok(())
+    }
+
+    /// Writes the remaining blocks and the footer, and returns the size of the table.
+    pub fn finish(self) -> Result<usize> {
+        self.finish_with_hash().map(|(size, _)| size)
+    }
+
+    /// Like finish(), but also returns the SHA-256 hash of the table's contents.
+    pub fn finish_with_hash(mut self) -> Result<(usize, TableHash)> {
         assert!(self.data_block.is_some());
         let ctype = self.opt.compression_type;
 
@@ -253,6 +317,25 @@ impl<Dst: Write> TableBuilder<Dst> {
 
             meta_ix_block.add(filter_key.as_bytes(), &handle_enc[0..enc_len]);
         }
+        if self.full_filter.is_some() {
+            // A full filter is stored under a different key, so that readers not knowing it
+            // ignore it. "fullfilter." sorts after "filter.".
+            let fblock = self.full_filter.take().unwrap();
+            let filter_key = format!("fullfilter.{}", fblock.filter_name());
+            let fblock_data = fblock.finish();
+            let fblock_handle = self.write_block(fblock_data, CompressionType::CompressionNone)?;
+
+            let mut handle_enc = [0 as u8; 16];
+            let enc_len = fblock_handle.encode_to(&mut handle_enc);
+
+            meta_ix_block.add(filter_key.as_bytes(), &handle_enc[0..enc_len]);
+        }
+
+        // Mark the filters as containing key prefixes. "prefix." sorts after both filter keys.
+        if let Some(ref pe) = self.prefix_extractor {
+            let prefix_key = format!("prefix.{}", pe.name());
+            meta_ix_block.add(prefix_key.as_bytes(), &[]);
+        }
 
         // write metaindex block
         let meta_ix = meta_ix_block.finish();
@@ -267,9 +350,13 @@ impl<Dst: Write> TableBuilder<Dst> {
         let mut buf = [0; FULL_FOOTER_LENGTH];
         footer.encode(&mut buf);
 
-        self.offset += self.dst.write(&buf[..])?;
+        self.write_raw(&buf[..])?;
+        self.offset += buf.len();
         self.dst.flush()?;
-        Ok(self.offset)
+
+        let mut hash = TableHash::default();
+        hash.copy_from_slice(&self.hash.finalize());
+        Ok((self.offset, hash))
     }
 }
 
@@ -278,6 +365,7 @@ pub mod tests {
     use super::*;
     use crate::blockhandle::BlockHandle;
     use crate::options;
+    use crate::state_digest::table_hash;
     use teaclave_test_utils::*;
 
     pub fn run_tests() -> bool {
@@ -328,8 +416,10 @@ pub mod tests {
         assert_eq!(143, estimate);
         assert!(b.filter_block.is_some());
 
-        let actual = b.finish().unwrap();
+        let (actual, hash) = b.finish_with_hash().unwrap();
         assert_eq!(223, actual);
+        assert_eq!(223, d.len());
+        assert_eq!(table_hash(&d), hash);
     }
 
     fn test_bad_input() {
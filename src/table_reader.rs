@@ -8,12 +8,12 @@ use crate::cmp::InternalKeyCmp;
 use crate::env::RandomAccess;
 use crate::error::Result;
 use crate::filter;
-use crate::filter_block::FilterBlockReader;
-use crate::key_types::InternalKey;
+use crate::filter_block::{FilterBlockReader, FullFilterBlockReader};
+use crate::key_types::{parse_internal_key, InternalKey, LookupKey};
 use crate::options::Options;
 use crate::table_block;
 use crate::table_builder::{self, Footer};
-use crate::types::{current_key_val, LdbIterator};
+use crate::types::{current_key_val, LdbIterator, MAX_SEQUENCE_NUMBER};
 
 use std::cmp::Ordering;
 use std::rc::Rc;
@@ -38,6 +38,10 @@ pub struct Table {
     footer: Footer,
     indexblock: Block,
     filters: Option<FilterBlockReader>,
+    // A single filter over all keys, for tables built with opt.full_filters.
+    full_filter: Option<FullFilterBlockReader>,
+    // Whether the filters contain the key prefixes produced by opt.prefix_extractor.
+    prefix_filters: bool,
 }
 
 impl Table {
@@ -51,6 +55,10 @@ impl Table {
 
         let filter_block_reader =
             Table::read_filter_block(&metaindexblock, file.as_ref().as_ref(), &opt)?;
+        let full_filter =
+            Table::read_full_filter_block(&metaindexblock, file.as_ref().as_ref(), &opt)?;
+        let prefix_filters = (filter_block_reader.is_some() || full_filter.is_some())
+            && Table::has_prefix_filters(&metaindexblock, &opt);
         let cache_id = opt.block_cache.borrow_mut().new_cache_id();
 
         Ok(Table {
@@ -60,34 +68,79 @@ impl Table {
             opt,
             footer,
             filters: filter_block_reader,
+            full_filter,
+            prefix_filters,
             indexblock,
         })
     }
 
+    /// Returns the location of the non-empty meta block stored under `name` in the metaindex
+    /// block.
+    fn find_meta_block(metaix: &Block, name: &[u8]) -> Option<BlockHandle> {
+        let mut metaindexiter = metaix.iter();
+        metaindexiter.seek(name);
+
+        if let Some((key, val)) = current_key_val(&metaindexiter) {
+            if key != name {
+                return None;
+            }
+            let location = BlockHandle::decode(&val).0;
+            if location.size() > 0 {
+                return Some(location);
+            }
+        }
+        None
+    }
+
     fn read_filter_block(
         metaix: &Block,
         file: &dyn RandomAccess,
         options: &Options,
     ) -> Result<Option<FilterBlockReader>> {
         // Open filter block for reading
-        let filter_name = format!("filter.{}", options.filter_policy.name())
-            .as_bytes()
-            .to_vec();
+        let filter_name = format!("filter.{}", options.filter_policy.name());
+        match Table::find_meta_block(metaix, filter_name.as_bytes()) {
+            Some(location) => Ok(Some(table_block::read_filter_block(
+                file,
+                &location,
+                options.filter_policy.clone(),
+            )?)),
+            None => Ok(None),
+        }
+    }
+
+    fn read_full_filter_block(
+        metaix: &Block,
+        file: &dyn RandomAccess,
+        options: &Options,
+    ) -> Result<Option<FullFilterBlockReader>> {
+        let filter_name = format!("fullfilter.{}", options.filter_policy.name());
+        match Table::find_meta_block(metaix, filter_name.as_bytes()) {
+            Some(location) => Ok(Some(table_block::read_full_filter_block(
+                file,
+                &location,
+                options.filter_policy.clone(),
+            )?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns whether the metaindex block marks the filters as containing the prefixes of the
+    /// configured prefix extractor.
+    fn has_prefix_filters(metaix: &Block, options: &Options) -> bool {
+        let pe = match options.prefix_extractor {
+            Some(ref pe) => pe,
+            None => return false,
+        };
+        let prefix_name = format!("prefix.{}", pe.name()).as_bytes().to_vec();
 
         let mut metaindexiter = metaix.iter();
-        metaindexiter.seek(&filter_name);
+        metaindexiter.seek(&prefix_name);
 
-        if let Some((_key, val)) = current_key_val(&metaindexiter) {
-            let filter_block_location = BlockHandle::decode(&val).0;
-            if filter_block_location.size() > 0 {
-                return Ok(Some(table_block::read_filter_block(
-                    file,
-                    &filter_block_location,
-                    options.filter_policy.clone(),
-                )?));
-            }
+        match current_key_val(&metaindexiter) {
+            Some((key, _)) => key == prefix_name,
+            None => false,
         }
-        Ok(None)
     }
 
     /// Creates a new table reader operating on internal keys (i.e., InternalKey). This means that
@@ -156,10 +209,59 @@ impl Table {
             current_block_off: 0,
             index_block: self.indexblock.iter(),
             table: self.clone(),
+            prefix: None,
         };
         iter
     }
 
+    /// Like iter(), but the returned iterator skips blocks that the filters show not to contain
+    /// any key starting with `prefix` (a user key prefix). Only applies to tables with internal
+    /// keys whose filters contain key prefixes; otherwise this is the same as iter().
+    pub fn prefix_iter(&self, prefix: &[u8]) -> TableIterator {
+        let mut iter = self.iter();
+        if self.prefix_filters {
+            iter.prefix = Some(
+                LookupKey::new(prefix, MAX_SEQUENCE_NUMBER)
+                    .internal_key()
+                    .to_vec(),
+            );
+        }
+        iter
+    }
+
+    /// Returns false if this table certainly doesn't contain any key starting with `prefix` (a
+    /// user key prefix). This requires internal keys, filters containing key prefixes, and a
+    /// comparator ordering keys with a common prefix contiguously (like the default one).
+    pub fn prefix_may_match(&self, prefix: &[u8]) -> bool {
+        if !self.prefix_filters {
+            return true;
+        }
+        let lookup = LookupKey::new(prefix, MAX_SEQUENCE_NUMBER);
+        if let Some(ref full) = self.full_filter {
+            return full.key_may_match(lookup.internal_key());
+        }
+        let filters = match self.filters {
+            Some(ref f) => f,
+            None => return true,
+        };
+        let mut index_iter = self.indexblock.iter();
+        index_iter.seek(lookup.internal_key());
+
+        while let Some((sep, h)) = current_key_val(&index_iter) {
+            let handle = BlockHandle::decode(&h).0;
+            if filters.key_may_match(handle.offset(), lookup.internal_key()) {
+                return true;
+            }
+            // Blocks after this one only contain keys greater than the separator.
+            let (_, _, ukey) = parse_internal_key(&sep);
+            if !ukey.starts_with(prefix) {
+                break;
+            }
+            index_iter.advance();
+        }
+        false
+    }
+
     /// Retrieve next-biggest entry for key from table. This function uses the attached filters, so
     /// is better suited if you frequently look for non-existing values (as it will detect the
     /// non-existence of an entry in a block without having to load the block).
@@ -172,12 +274,19 @@ impl Table {
     /// exact match; it depends on other comparators than the one that the table reader knows
     /// whether a match is acceptable.
     pub fn get<'a>(&self, key: InternalKey<'a>) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        // A full filter is checked without reading the index.
+        if let Some(ref full) = self.full_filter {
+            if !full.key_may_match(key) {
+                return Ok(None);
+            }
+        }
+
         let mut index_iter = self.indexblock.iter();
         index_iter.seek(key);
 
         let handle;
         if let Some((last_in_block, h)) = current_key_val(&index_iter) {
-            if self.opt.cmp.cmp(key, &last_in_block) == Ordering::Less {
+            if self.opt.cmp.cmp(key, &last_in_block) != Ordering::Greater {
                 handle = BlockHandle::decode(&h).0;
             } else {
                 return Ok(None);
@@ -208,6 +317,58 @@ impl Table {
         }
         Ok(None)
     }
+
+    /// multi_get is like get(), but looks up a batch of keys, which must be sorted in ascending
+    /// order. The returned entries correspond to the keys at the same position. The index block
+    /// is only traversed once, and data blocks containing several of the keys are only read once.
+    pub fn multi_get<'a>(
+        &self,
+        keys: &[InternalKey<'a>],
+    ) -> Result<Vec<Option<(Vec<u8>, Vec<u8>)>>> {
+        let mut index_iter = self.indexblock.iter();
+        // The most recently read data block and its offset.
+        let mut block: Option<(usize, BlockIter)> = None;
+        let mut result = Vec::with_capacity(keys.len());
+
+        for key in keys {
+            if let Some(ref full) = self.full_filter {
+                if !full.key_may_match(key) {
+                    result.push(None);
+                    continue;
+                }
+            }
+            index_iter.seek(key);
+            if !index_iter.valid() {
+                result.push(None);
+                continue;
+            }
+            let handle = BlockHandle::decode(index_iter.value()).0;
+
+            if let Some(ref filters) = self.filters {
+                if !filters.key_may_match(handle.offset(), key) {
+                    result.push(None);
+                    continue;
+                }
+            }
+
+            let loaded = match block {
+                Some((offset, _)) => offset == handle.offset(),
+                None => false,
+            };
+            if !loaded {
+                block = Some((handle.offset(), self.read_block(&handle)?.iter()));
+            }
+
+            let iter = &mut block.as_mut().unwrap().1;
+            iter.seek(key);
+            if iter.valid() {
+                result.push(Some((iter.key().to_vec(), iter.value().to_vec())));
+            } else {
+                result.push(None);
+            }
+        }
+        Ok(result)
+    }
 }
 
 /// This iterator is a "TwoLevelIterator"; it uses an index block in order to get an offset hint
@@ -223,6 +384,8 @@ pub struct TableIterator {
     current_block: Option<BlockIter>,
     current_block_off: usize,
     index_block: BlockIter,
+    // If set, blocks whose filter doesn't match this (internal) prefix key are skipped.
+    prefix: Option<Vec<u8>>,
 }
 
 impl TableIterator {
@@ -231,20 +394,37 @@ impl TableIterator {
     // Err means corruption or I/O error; Ok(true) means a new block was loaded; Ok(false) means
     // tht there's no more entries.
     fn skip_to_next_entry(&mut self) -> Result<bool> {
-        if let Some((_key, val)) = self.index_block.next() {
-            self.load_block(&val).map(|_| true)
-        } else {
-            Ok(false)
+        while self.index_block.advance() {
+            let (handle, _) = BlockHandle::decode(self.index_block.value());
+            if self.block_may_match(&handle) {
+                return self.load_block(&handle).map(|_| true);
+            }
+        }
+        Ok(false)
+    }
+
+    // Returns false if the block at `handle` can be skipped because it doesn't contain any key
+    // with the iterator's prefix.
+    fn block_may_match(&self, handle: &BlockHandle) -> bool {
+        let prefix = match self.prefix {
+            Some(ref prefix) => prefix,
+            None => return true,
+        };
+        if let Some(ref full) = self.table.full_filter {
+            return full.key_may_match(prefix);
+        }
+        match self.table.filters {
+            Some(ref filters) => filters.key_may_match(handle.offset(), prefix),
+            None => true,
         }
     }
 
     // Load the block at `handle` into `self.current_block`
-    fn load_block(&mut self, handle: &[u8]) -> Result<()> {
-        let (new_block_handle, _) = BlockHandle::decode(handle);
-        let block = self.table.read_block(&new_block_handle)?;
+    fn load_block(&mut self, handle: &BlockHandle) -> Result<()> {
+        let block = self.table.read_block(handle)?;
 
         self.current_block = Some(block.iter());
-        self.current_block_off = new_block_handle.offset();
+        self.current_block_off = handle.offset();
 
         Ok(())
     }
@@ -293,8 +473,20 @@ impl LdbIterator for TableIterator {
         self.index_block.seek(to);
 
         // It's possible that this is a seek past-last; reset in that case.
-        if let Some((past_block, handle)) = current_key_val(&self.index_block) {
-            if self.table.opt.cmp.cmp(to, &past_block) <= Ordering::Equal {
+        if self.index_block.valid() {
+            let past_block = self.index_block.key();
+            let (handle, _) = BlockHandle::decode(self.index_block.value());
+            if self.table.opt.cmp.cmp(to, past_block) <= Ordering::Equal {
+                if !self.block_may_match(&handle) {
+                    // The block doesn't contain the prefix; continue at the next one that may.
+                    self.current_block = None;
+                    if let Ok(true) = self.skip_to_next_entry() {
+                        self.current_block.as_mut().unwrap().advance();
+                        return;
+                    }
+                    self.reset();
+                    return;
+                }
                 // ok, found right block: continue
                 if let Ok(()) = self.load_block(&handle) {
                     // current_block is always set if load_block() returned Ok.
@@ -316,21 +508,41 @@ impl LdbIterator for TableIterator {
         }
 
         // Go back one block and look for the last entry in the previous block
-        if self.index_block.prev() {
-            if let Some((_, handle)) = current_key_val(&self.index_block) {
-                if self.load_block(&handle).is_ok() {
-                    self.current_block.as_mut().unwrap().seek_to_last();
-                    self.current_block.as_ref().unwrap().valid()
-                } else {
-                    self.reset();
-                    false
-                }
+        while self.index_block.prev() {
+            if !self.index_block.valid() {
+                return false;
+            }
+            let (handle, _) = BlockHandle::decode(self.index_block.value());
+            if !self.block_may_match(&handle) {
+                continue;
+            }
+            if self.load_block(&handle).is_ok() {
+                self.current_block.as_mut().unwrap().seek_to_last();
+                return self.current_block.as_ref().unwrap().valid();
             } else {
-                false
+                self.reset();
+                return false;
+            }
+        }
+        false
+    }
+
+    fn seek_to_last(&mut self) {
+        self.index_block.seek_to_last();
+        self.current_block = None;
+        if self.index_block.valid() {
+            let (handle, _) = BlockHandle::decode(self.index_block.value());
+            if !self.block_may_match(&handle) {
+                // prev() continues with the previous block that may match.
+                self.prev();
+                return;
+            }
+            if self.load_block(&handle).is_ok() {
+                self.current_block.as_mut().unwrap().seek_to_last();
+                return;
             }
-        } else {
-            false
         }
+        self.reset();
     }
 
     fn reset(&mut self) {
@@ -351,6 +563,18 @@ impl LdbIterator for TableIterator {
             false
         }
     }
+    fn key(&self) -> &[u8] {
+        match self.current_block {
+            Some(ref cb) => cb.key(),
+            None => &[],
+        }
+    }
+    fn value(&self) -> &[u8] {
+        match self.current_block {
+            Some(ref cb) => cb.value(),
+            None => &[],
+        }
+    }
 }
 
 #[cfg(feature = "enclave_unit_test")]
@@ -358,6 +582,7 @@ pub mod tests {
     use crate::filter::BloomPolicy;
     use crate::key_types::LookupKey;
     use crate::options::{self, CompressionType};
+    use crate::slice_transform::FixedPrefixTransform;
     use crate::table_builder::TableBuilder;
     use crate::test_util::{test_iterator_properties, LdbIteratorIter};
     use crate::types::{current_key_val, LdbIterator};
@@ -376,8 +601,13 @@ pub mod tests {
             test_table_iterator_values,
             test_table_iterator_seek,
             test_table_get,
+            test_table_get_separator,
+            test_table_multi_get,
             test_table_internal_keys,
             test_table_reader_checksum,
+            test_table_prefix_filters,
+            test_table_prefix_iterator_skips_blocks,
+            test_table_full_filter,
         )
     }
 
@@ -422,11 +652,15 @@ pub mod tests {
 
     // Build a table containing keys in InternalKey format.
     fn build_internal_table() -> (Vec<u8>, usize) {
-        let mut d = Vec::with_capacity(512);
         let mut opt = options::for_test();
         opt.block_restart_interval = 1;
         opt.block_size = 32;
         opt.filter_policy = Rc::new(Box::new(BloomPolicy::new(4)));
+        build_internal_table_with(opt)
+    }
+
+    fn build_internal_table_with(opt: Options) -> (Vec<u8>, usize) {
+        let mut d = Vec::with_capacity(512);
 
         let mut i = 1 as u64;
         let data: Vec<(Vec<u8>, &'static str)> = build_data()
@@ -686,6 +920,70 @@ pub mod tests {
         assert!(table.get("zz{".as_bytes()).unwrap().is_none());
     }
 
+    fn test_table_get_separator() {
+        let mut opt = options::for_test();
+        opt.block_size = 1;
+        opt.block_restart_interval = 1;
+
+        // Two versions of a user key in different blocks: the separator between the blocks is
+        // the first key itself.
+        let keys: Vec<Vec<u8>> = vec![
+            LookupKey::new(b"abc", 5).internal_key().to_vec(),
+            LookupKey::new(b"abc", 4).internal_key().to_vec(),
+            LookupKey::new(b"abd", 3).internal_key().to_vec(),
+        ];
+        let mut d = vec![];
+        {
+            let mut b = TableBuilder::new(opt.clone(), &mut d);
+            for k in keys.iter() {
+                b.add(k, b"val").unwrap();
+            }
+            b.finish().unwrap();
+        }
+        let size = d.len();
+        let table = Table::new(opt, wrap_buffer(d), size).unwrap();
+
+        for k in keys.iter() {
+            assert_eq!(Some(k.clone()), table.get(k).unwrap().map(|(k, _)| k));
+        }
+        let found = table.get(LookupKey::new(b"abc", 6).internal_key()).unwrap();
+        assert_eq!(Some(keys[0].clone()), found.map(|(k, _)| k));
+    }
+
+    fn test_table_multi_get() {
+        let (src, size) = build_table(build_data());
+        let table = Table::new_raw(options::for_test(), wrap_buffer(src), size).unwrap();
+
+        let mut _iter = table.iter();
+        let entries: Vec<(Vec<u8>, Vec<u8>)> = LdbIteratorIter::wrap(&mut _iter).collect();
+        let keys: Vec<&[u8]> = entries.iter().map(|e| e.0.as_slice()).collect();
+        let found = table.multi_get(&keys).unwrap();
+        assert_eq!(entries.len(), found.len());
+        for (e, f) in entries.iter().zip(found.iter()) {
+            assert_eq!(Some(e), f.as_ref());
+        }
+
+        let keys: &[&[u8]] = &[b"aa", b"abc", b"abcd", b"bcd", b"zz1", b"zzz", b"zz{"];
+        let found = table.multi_get(keys).unwrap();
+        let got: Vec<Option<&[u8]>> = found
+            .iter()
+            .map(|f| f.as_ref().map(|kv| kv.0.as_slice()))
+            .collect();
+        assert_eq!(
+            vec![
+                None,
+                Some(&b"abc"[..]),
+                None,
+                Some(&b"bcd"[..]),
+                None,
+                Some(&b"zzz"[..]),
+                None
+            ],
+            got
+        );
+        assert!(table.multi_get(&[]).unwrap().is_empty());
+    }
+
     // This test verifies that the table and filters work with internal keys. This means:
     // The table contains keys in InternalKey format and it uses a filter wrapped by
     // InternalFilterPolicy.
@@ -754,4 +1052,132 @@ pub mod tests {
             panic!("Should have hit 5th record in table!");
         }
     }
+
+    fn test_table_prefix_filters() {
+        let mut opt = options::for_test();
+        opt.block_restart_interval = 1;
+        opt.block_size = 32;
+        opt.prefix_extractor = Some(FixedPrefixTransform::new(2));
+        let (src, size) = build_internal_table_with(opt.clone());
+
+        let table = Table::new(opt, wrap_buffer(src.clone()), size).unwrap();
+        assert!(table.prefix_filters);
+
+        for p in &[b"ab", b"bc", b"bs", b"xy", b"xz", b"zz"] {
+            assert!(table.prefix_may_match(*p));
+        }
+        for p in &[b"aa", b"ac", b"xx", b"zy"] {
+            assert!(!table.prefix_may_match(*p));
+        }
+
+        let mut iter = table.prefix_iter(b"xz");
+        iter.seek(LookupKey::new(b"xz", MAX_SEQUENCE_NUMBER).internal_key());
+        assert!(iter.valid());
+        assert_eq!(
+            b"xzz",
+            parse_internal_key(&current_key_val(&iter).unwrap().0).2
+        );
+
+        // Without a prefix extractor, the prefixes are ignored.
+        let table = Table::new(options::for_test(), wrap_buffer(src), size).unwrap();
+        assert!(!table.prefix_filters);
+        assert!(table.prefix_may_match(b"ac"));
+        let mut iter = table.prefix_iter(b"xz");
+        assert_eq!(LdbIteratorIter::wrap(&mut iter).count(), 7);
+    }
+
+    fn test_table_prefix_iterator_skips_blocks() {
+        let mut opt = options::for_test();
+        opt.block_size = 2048;
+        opt.prefix_extractor = Some(FixedPrefixTransform::new(2));
+
+        let mut d = Vec::with_capacity(1 << 16);
+        let mut keys = vec![];
+        for p in &["aa", "ab", "ac", "ad"] {
+            for i in 0..100 {
+                keys.push(format!("{}{:03}", p, i));
+            }
+        }
+        {
+            let mut b = TableBuilder::new(opt.clone(), &mut d);
+            for (i, k) in keys.iter().enumerate() {
+                let ikey = LookupKey::new(k.as_bytes(), i as u64 + 1);
+                b.add(ikey.internal_key(), &[b'x'; 20]).unwrap();
+            }
+            b.finish().unwrap();
+        }
+
+        let size = d.len();
+        let table = Table::new(opt, wrap_buffer(d), size).unwrap();
+        assert!(table.filters.as_ref().unwrap().num() > 4);
+
+        let mut iter = table.prefix_iter(b"ac");
+        let found: Vec<Vec<u8>> = LdbIteratorIter::wrap(&mut iter)
+            .map(|(k, _)| parse_internal_key(&k).2.to_vec())
+            .collect();
+        assert!(found.len() < keys.len());
+        assert!(!found.contains(&b"aa000".to_vec()));
+        assert_eq!(100, found.iter().filter(|k| k.starts_with(b"ac")).count());
+
+        // Seeking to the prefix skips the blocks before the first one with the prefix.
+        iter.seek(LookupKey::new(b"ac", MAX_SEQUENCE_NUMBER).internal_key());
+        assert!(iter.valid());
+        assert_eq!(
+            b"ac000",
+            parse_internal_key(&current_key_val(&iter).unwrap().0).2
+        );
+        // Going backwards, blocks without the prefix are skipped as well.
+        let mut n = 0;
+        while iter.prev() {
+            n += 1;
+        }
+        assert!(n < 200);
+    }
+
+    fn test_table_full_filter() {
+        let mut opt = options::for_test();
+        opt.block_restart_interval = 1;
+        opt.block_size = 32;
+        opt.full_filters = true;
+        opt.prefix_extractor = Some(FixedPrefixTransform::new(2));
+        let (src, size) = build_internal_table_with(opt.clone());
+
+        // The full filter replaces the per-block filters, and doesn't depend on the option.
+        let table = Table::new(options::for_test(), wrap_buffer(src.clone()), size).unwrap();
+        assert!(table.filters.is_none());
+        assert!(table.full_filter.is_some());
+
+        let mut _iter = table.iter();
+        let entries: Vec<(Vec<u8>, Vec<u8>)> = LdbIteratorIter::wrap(&mut _iter).collect();
+        assert_eq!(build_data().len(), entries.len());
+        for (k, v) in entries.iter() {
+            assert_eq!(Some((k.clone(), v.clone())), table.get(k).unwrap());
+        }
+        let keys: Vec<&[u8]> = entries.iter().map(|e| e.0.as_slice()).collect();
+        assert!(table.multi_get(&keys).unwrap().iter().all(|e| e.is_some()));
+
+        // Keys not in the table are rejected before the index is consulted, even if they fall
+        // into the range of a block.
+        let full = table.full_filter.clone().unwrap();
+        for k in &[&b"abb"[..], b"abcd", b"xyy", b"zz1"] {
+            let lkey = LookupKey::new(k, 100);
+            assert!(!full.key_may_match(lkey.internal_key()));
+            assert_eq!(None, table.get(lkey.internal_key()).unwrap());
+        }
+
+        // Prefixes work as with per-block filters.
+        let table = Table::new(opt, wrap_buffer(src), size).unwrap();
+        assert!(table.prefix_filters);
+        for p in &[b"ab", b"bc", b"xz", b"zz"] {
+            assert!(table.prefix_may_match(*p));
+        }
+        for p in &[b"aa", b"ac", b"zy"] {
+            assert!(!table.prefix_may_match(*p));
+        }
+        let mut iter = table.prefix_iter(b"ac");
+        assert_eq!(0, LdbIteratorIter::wrap(&mut iter).count());
+        let mut iter = table.prefix_iter(b"xz");
+        iter.seek(LookupKey::new(b"xz", MAX_SEQUENCE_NUMBER).internal_key());
+        assert!(iter.valid());
+    }
 }
@@ -70,6 +70,28 @@ impl<'a> LdbIterator for TestLdbIter<'a> {
             true
         }
     }
+    fn seek_to_last(&mut self) {
+        self.init = !self.v.is_empty();
+        self.ix = if self.v.is_empty() {
+            0
+        } else {
+            self.v.len() - 1
+        };
+    }
+    fn key(&self) -> &[u8] {
+        if self.valid() {
+            self.v[self.ix].0
+        } else {
+            &[]
+        }
+    }
+    fn value(&self) -> &[u8] {
+        if self.valid() {
+            self.v[self.ix].1
+        } else {
+            &[]
+        }
+    }
 }
 
 /// LdbIteratorIter implements std::iter::Iterator for an LdbIterator.
@@ -97,6 +119,9 @@ pub fn test_iterator_properties<It: LdbIterator>(mut it: It) {
     assert!(it.advance());
     assert!(it.valid());
     let first = current_key_val(&it);
+    // key() and value() borrow the same entry that current() copies out.
+    assert_eq!(first.as_ref().unwrap().0.as_slice(), it.key());
+    assert_eq!(first.as_ref().unwrap().1.as_slice(), it.value());
     assert!(it.advance());
     let second = current_key_val(&it);
     assert!(it.advance());
@@ -105,9 +130,12 @@ pub fn test_iterator_properties<It: LdbIterator>(mut it: It) {
     assert!(it.advance());
     assert!(it.valid());
     let fourth = current_key_val(&it);
+    assert_eq!(fourth.as_ref().unwrap().0.as_slice(), it.key());
+    assert_eq!(fourth.as_ref().unwrap().1.as_slice(), it.value());
     // past end is invalid
     assert!(!it.advance());
     assert!(!it.valid());
+    assert!(it.key().is_empty());
 
     it.reset();
     it.seek(&fourth.as_ref().unwrap().0);
@@ -131,6 +159,17 @@ pub fn test_iterator_properties<It: LdbIterator>(mut it: It) {
     assert_eq!(first, current_key_val(&it));
     assert!(!it.prev());
     assert!(!it.valid());
+
+    it.seek_to_last();
+    assert!(it.valid());
+    assert_eq!(fourth, current_key_val(&it));
+    assert!(it.prev());
+    assert_eq!(third, current_key_val(&it));
+    assert_eq!(third.as_ref().unwrap().0.as_slice(), it.key());
+    assert!(it.advance());
+    assert_eq!(fourth, current_key_val(&it));
+    assert!(!it.advance());
+    assert!(!it.valid());
 }
 
 #[cfg(feature = "enclave_unit_test")]
@@ -139,7 +178,11 @@ pub mod tests {
     use teaclave_test_utils::*;
 
     pub fn run_tests() -> bool {
-        run_tests!(test_test_util_basic, test_test_util_ldbiter_properties,)
+        run_tests!(
+            test_test_util_basic,
+            test_test_util_ldbiter_properties,
+            test_test_util_default_seek_to_last,
+        )
     }
 
     fn test_test_util_basic() {
@@ -166,4 +209,48 @@ pub mod tests {
         }
         test_iterator_properties(TestLdbIter::new(v));
     }
+
+    /// Wraps a TestLdbIter, using the default implementation of seek_to_last().
+    struct DefaultSeekToLast<'a>(TestLdbIter<'a>);
+
+    impl<'a> LdbIterator for DefaultSeekToLast<'a> {
+        fn advance(&mut self) -> bool {
+            self.0.advance()
+        }
+        fn reset(&mut self) {
+            self.0.reset()
+        }
+        fn current(&self, key: &mut Vec<u8>, val: &mut Vec<u8>) -> bool {
+            self.0.current(key, val)
+        }
+        fn valid(&self) -> bool {
+            self.0.valid()
+        }
+        fn seek(&mut self, k: &[u8]) {
+            self.0.seek(k)
+        }
+        fn prev(&mut self) -> bool {
+            self.0.prev()
+        }
+        fn key(&self) -> &[u8] {
+            self.0.key()
+        }
+        fn value(&self) -> &[u8] {
+            self.0.value()
+        }
+    }
+
+    fn test_test_util_default_seek_to_last() {
+        let v = vec![
+            ("abc".as_bytes(), "def".as_bytes()),
+            ("abd".as_bytes(), "deg".as_bytes()),
+            ("abe".as_bytes(), "deh".as_bytes()),
+        ];
+        let mut iter = DefaultSeekToLast(TestLdbIter::new(v));
+        iter.seek_to_last();
+        assert!(iter.valid());
+        assert_eq!((&b"abe"[..], &b"deh"[..]), (iter.key(), iter.value()));
+        assert!(iter.prev());
+        assert_eq!(b"abd", iter.key());
+    }
 }
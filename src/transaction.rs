@@ -0,0 +1,220 @@
+#[cfg(feature = "mesalock_sgx")]
+use std::prelude::v1::*;
+
+use crate::cmp::Cmp;
+use crate::db_impl::DB;
+use crate::error::{err, Result, StatusCode};
+use crate::snapshot::Snapshot;
+use crate::write_batch_with_index::WriteBatchWithIndex;
+
+use std::collections::HashSet;
+use std::rc::Rc;
+
+/// A Transaction is an optimistic transaction, started with DB::begin(). Reads are served from
+/// the snapshot taken at the start of the transaction, and writes are buffered in a
+/// WriteBatchWithIndex.
+///
+/// Keys read with get_for_update() or written by the transaction are tracked; commit() fails with
+/// StatusCode::Conflict if any of them has been modified in the database after the transaction
+/// began. Otherwise, all writes are applied atomically.
+pub struct Transaction {
+    snapshot: Snapshot,
+    batch: WriteBatchWithIndex,
+    tracked: HashSet<Vec<u8>>,
+}
+
+impl Transaction {
+    /// Returns a new transaction reading from snapshot, for a database using the comparator cmp.
+    pub fn new(cmp: Rc<Box<dyn Cmp>>, snapshot: Snapshot) -> Transaction {
+        Transaction {
+            snapshot,
+            batch: WriteBatchWithIndex::new(cmp),
+            tracked: HashSet::new(),
+        }
+    }
+
+    /// Returns the snapshot this transaction reads from.
+    pub fn snapshot(&self) -> &Snapshot {
+        &self.snapshot
+    }
+
+    /// Adds a put operation to the transaction.
+    pub fn put(&mut self, key: &[u8], val: &[u8]) {
+        self.batch.put(key, val);
+        self.track(key);
+    }
+
+    /// Adds a delete operation to the transaction.
+    pub fn delete(&mut self, key: &[u8]) {
+        self.batch.delete(key);
+        self.track(key);
+    }
+
+    /// get returns the value for key, as written by this transaction or as of the transaction's
+    /// snapshot. The key is not tracked for conflicts.
+    pub fn get(&self, db: &mut DB, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        match self.batch.get_from_batch(key) {
+            Some(v) => Ok(v),
+            None => db.get_at(&self.snapshot, key),
+        }
+    }
+
+    /// get_for_update is like get(), but also tracks key: committing the transaction fails if key
+    /// is modified by someone else before.
+    pub fn get_for_update(&mut self, db: &mut DB, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.track(key);
+        self.get(db, key)
+    }
+
+    /// commit checks that none of the tracked keys has been written since the transaction began,
+    /// and then writes the transaction's changes to db in one batch. If a conflict is found,
+    /// nothing is written and an error with StatusCode::Conflict is returned.
+    pub fn commit(self, db: &mut DB, sync: bool) -> Result<()> {
+        for key in self.tracked.iter() {
+            if let Some(seq) = db.latest_sequence_for_key(key)? {
+                if seq > self.snapshot.sequence() {
+                    return err(
+                        StatusCode::Conflict,
+                        &format!("key {:?} was modified by another writer", key),
+                    );
+                }
+            }
+        }
+        if self.batch.count() == 0 {
+            return Ok(());
+        }
+        db.write(self.batch.into_write_batch(), sync)
+    }
+
+    fn track(&mut self, key: &[u8]) {
+        self.tracked.insert(key.to_vec());
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use crate::options;
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_transaction_read_own_writes,
+            test_transaction_commit,
+            test_transaction_conflict,
+            test_transaction_conflict_from_table,
+        )
+    }
+
+    fn open_db() -> DB {
+        DB::open("transactiondb", options::for_test()).unwrap()
+    }
+
+    fn test_transaction_read_own_writes() {
+        let mut db = open_db();
+        db.put(b"a", b"1").unwrap();
+        db.put(b"b", b"2").unwrap();
+
+        let mut txn = db.begin();
+        db.put(b"c", b"3").unwrap();
+        txn.put(b"a", b"10");
+        txn.delete(b"b");
+        txn.put(b"d", b"4");
+        txn.put(b"d", b"40");
+
+        assert_eq!(Some(b"10".to_vec()), txn.get(&mut db, b"a").unwrap());
+        assert_eq!(None, txn.get(&mut db, b"b").unwrap());
+        // Written after the transaction began.
+        assert_eq!(None, txn.get(&mut db, b"c").unwrap());
+        assert_eq!(Some(b"40".to_vec()), txn.get(&mut db, b"d").unwrap());
+        // Nothing is visible outside the transaction before commit.
+        assert_eq!(Some(b"1".to_vec()), db.get(b"a"));
+        assert_eq!(None, db.get(b"d"));
+    }
+
+    fn test_transaction_commit() {
+        let mut db = open_db();
+        db.put(b"a", b"1").unwrap();
+
+        let mut txn = db.begin();
+        assert_eq!(
+            Some(b"1".to_vec()),
+            txn.get_for_update(&mut db, b"a").unwrap()
+        );
+        txn.put(b"a", b"2");
+        txn.put(b"b", b"3");
+        // Writes to untracked keys don't conflict.
+        db.put(b"c", b"4").unwrap();
+        txn.commit(&mut db, false).unwrap();
+
+        assert_eq!(Some(b"2".to_vec()), db.get(b"a"));
+        assert_eq!(Some(b"3".to_vec()), db.get(b"b"));
+
+        // An empty transaction commits trivially.
+        let txn = db.begin();
+        txn.commit(&mut db, false).unwrap();
+    }
+
+    fn test_transaction_conflict() {
+        let mut db = open_db();
+        db.put(b"a", b"1").unwrap();
+
+        let mut txn = db.begin();
+        assert_eq!(
+            Some(b"1".to_vec()),
+            txn.get_for_update(&mut db, b"a").unwrap()
+        );
+        txn.put(b"b", b"2");
+        db.put(b"a", b"5").unwrap();
+
+        let e = txn.commit(&mut db, false).unwrap_err();
+        assert_eq!(StatusCode::Conflict, e.code);
+        assert_eq!(None, db.get(b"b"));
+        assert_eq!(Some(b"5".to_vec()), db.get(b"a"));
+
+        // Deleting a tracked key, or creating a key that didn't exist, is a conflict as well.
+        let mut txn = db.begin();
+        txn.get_for_update(&mut db, b"a").unwrap();
+        assert_eq!(None, txn.get_for_update(&mut db, b"x").unwrap());
+        db.delete(b"a").unwrap();
+        assert!(txn.commit(&mut db, false).is_err());
+
+        let mut txn = db.begin();
+        txn.get_for_update(&mut db, b"x").unwrap();
+        db.put(b"x", b"1").unwrap();
+        assert!(txn.commit(&mut db, false).is_err());
+
+        // Two transactions writing the same key: the second one to commit fails.
+        let mut t1 = db.begin();
+        let mut t2 = db.begin();
+        t1.put(b"y", b"1");
+        t2.put(b"y", b"2");
+        t1.commit(&mut db, false).unwrap();
+        assert_eq!(
+            StatusCode::Conflict,
+            t2.commit(&mut db, false).unwrap_err().code
+        );
+        assert_eq!(Some(b"1".to_vec()), db.get(b"y"));
+    }
+
+    fn test_transaction_conflict_from_table() {
+        let mut db = open_db();
+        db.put(b"a", b"1").unwrap();
+        db.put(b"b", b"1").unwrap();
+
+        let mut txn = db.begin();
+        txn.get_for_update(&mut db, b"a").unwrap();
+        txn.get_for_update(&mut db, b"b").unwrap();
+        db.put(b"b", b"2").unwrap();
+        // Move all entries into a table.
+        db.compact_range(b"a", b"c").unwrap();
+
+        assert!(db.latest_sequence_for_key(b"b").unwrap().unwrap() > txn.snapshot().sequence());
+        assert!(db.latest_sequence_for_key(b"a").unwrap().unwrap() <= txn.snapshot().sequence());
+        assert_eq!(None, db.latest_sequence_for_key(b"z").unwrap());
+        assert_eq!(
+            StatusCode::Conflict,
+            txn.commit(&mut db, false).unwrap_err().code
+        );
+    }
+}
@@ -56,9 +56,17 @@ pub trait LdbIterator {
     /// i.e. if `current()` would succeed.
     fn valid(&self) -> bool;
     /// Go to the previous item; if the iterator is moved beyond the first element, `prev()`
-    /// returns false and it will be `!valid()`. This is inefficient for most iterator
-    /// implementations.
+    /// returns false and it will be `!valid()`.
     fn prev(&mut self) -> bool;
+    /// Returns the key of the current entry without copying it. The slice borrows from the
+    /// iterator's own storage (e.g. the pinned block) and is empty if the iterator is not valid.
+    ///
+    /// key() and value() have no default implementation, as current() copies into buffers owned
+    /// by the caller, which can't be borrowed from. Iterators implemented outside of this crate
+    /// must add them; keeping the current entry in the iterator is sufficient.
+    fn key(&self) -> &[u8];
+    /// Returns the value of the current entry without copying it; see key().
+    fn value(&self) -> &[u8];
 
     // default implementations.
 
@@ -82,6 +90,23 @@ pub trait LdbIterator {
         self.reset();
         self.advance();
     }
+
+    /// Seek the iterator to the last element. If there are no elements, the iterator is `!valid()`
+    /// afterwards.
+    ///
+    /// The default implementation walks over all elements, and then seeks to the last key seen.
+    /// Iterators that can be positioned at their end directly should override it.
+    fn seek_to_last(&mut self) {
+        self.reset();
+        let (mut last, mut val) = (vec![], vec![]);
+        let mut found = false;
+        while self.advance() {
+            found = self.current(&mut last, &mut val);
+        }
+        if found {
+            self.seek(&last);
+        }
+    }
 }
 
 /// current_key_val is a helper allocating two vectors and filling them with the current key/value
@@ -114,11 +139,26 @@ impl LdbIterator for Box<dyn LdbIterator> {
     fn prev(&mut self) -> bool {
         self.as_mut().prev()
     }
+    fn seek_to_last(&mut self) {
+        self.as_mut().seek_to_last()
+    }
+    fn key(&self) -> &[u8] {
+        self.as_ref().key()
+    }
+    fn value(&self) -> &[u8] {
+        self.as_ref().value()
+    }
 }
 
 /// The unique (sequential) number of a file.
 pub type FileNum = u64;
 
+/// The generation of a key in a KeyRing. Databases not using a KeyRing only use generation 0.
+pub type KeyGeneration = u32;
+
+/// The SHA-256 hash of the contents of a table file.
+pub type TableHash = [u8; 32];
+
 /// Describes a file on disk.
 #[derive(Clone, Debug, Default, PartialEq)]
 pub struct FileMetaData {
@@ -129,6 +169,10 @@ pub struct FileMetaData {
     // these are in InternalKey format:
     pub smallest: Vec<u8>,
     pub largest: Vec<u8>,
+    // The generation of the key the file is encrypted with.
+    pub key_gen: KeyGeneration,
+    // The hash of the table's contents; None for tables written by other implementations.
+    pub content_hash: Option<TableHash>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -0,0 +1,304 @@
+#[cfg(feature = "mesalock_sgx")]
+use std::prelude::v1::*;
+
+use crate::db_impl::log_file_name;
+use crate::env::Env;
+use crate::error::{err, Result, StatusCode};
+use crate::log::LogReader;
+use crate::types::{FileNum, SequenceNumber};
+use crate::write_batch::WriteBatch;
+
+use std::collections::VecDeque;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// UpdatesIter iterates over the write batches recorded in the log files of a database, starting
+/// with the batch containing a given sequence number. It is returned by DB::get_updates_since().
+///
+/// Every item is the sequence number of the first operation in a batch, and the batch itself.
+/// Log files are read lazily; reading stops at the end of the newest log file. Consecutive items
+/// are checked to have consecutive sequence numbers; a gap (e.g. a missing log file) is reported
+/// as an error with StatusCode::Corruption, after which the iterator is exhausted.
+pub struct UpdatesIter {
+    env: Rc<Box<dyn Env>>,
+    path: PathBuf,
+    // Log files not opened yet, in ascending order.
+    logs: VecDeque<FileNum>,
+    current: Option<LogReader<Box<dyn Read>>>,
+    scratch: Vec<u8>,
+    // The first sequence number to return.
+    seq: SequenceNumber,
+    // The first item, read ahead by new() in order to check that seq is available.
+    first: Option<Result<(SequenceNumber, WriteBatch)>>,
+    // The sequence number the next batch has to start with, once a batch has been returned.
+    expected: Option<SequenceNumber>,
+    done: bool,
+}
+
+impl UpdatesIter {
+    /// Returns an iterator over the log files `logs` in directory `path`, starting at sequence
+    /// number `seq`. If seq is not later than `last_seq` (the most recent sequence number of the
+    /// database), the log files must still contain it; otherwise, an error with
+    /// StatusCode::NotFound is returned.
+    pub fn new(
+        env: Rc<Box<dyn Env>>,
+        path: &Path,
+        mut logs: Vec<FileNum>,
+        seq: SequenceNumber,
+        last_seq: SequenceNumber,
+    ) -> Result<UpdatesIter> {
+        logs.sort();
+        // Sequence numbers start at 1.
+        let seq = seq.max(1);
+        let mut iter = UpdatesIter {
+            env,
+            path: path.to_path_buf(),
+            logs: logs.into_iter().collect(),
+            current: None,
+            scratch: vec![],
+            seq,
+            first: None,
+            expected: None,
+            done: false,
+        };
+        if seq <= last_seq {
+            match iter.next() {
+                Some(Ok((first, _))) if first > seq => {
+                    return err(
+                        StatusCode::NotFound,
+                        &format!(
+                            "sequence number {} is not in the log files anymore (oldest: {})",
+                            seq, first
+                        ),
+                    )
+                }
+                Some(Err(e)) => return Err(e),
+                None => {
+                    return err(
+                        StatusCode::NotFound,
+                        &format!("sequence number {} is not in the log files anymore", seq),
+                    )
+                }
+                first => iter.first = first,
+            }
+        }
+        Ok(iter)
+    }
+
+    /// next_batch returns the next non-empty batch from the log files.
+    fn next_batch(&mut self) -> Result<Option<WriteBatch>> {
+        loop {
+            if self.current.is_none() {
+                match self.logs.pop_front() {
+                    Some(num) => {
+                        let file = self
+                            .env
+                            .open_sequential_file(&log_file_name(&self.path, num))?;
+                        self.current = Some(LogReader::new(file, true));
+                    }
+                    None => return Ok(None),
+                }
+            }
+            let len = self.current.as_mut().unwrap().read(&mut self.scratch)?;
+            if len == 0 {
+                self.current = None;
+                continue;
+            }
+            let batch = WriteBatch::from_bytes(&self.scratch)?;
+            if batch.count() > 0 {
+                return Ok(Some(batch));
+            }
+        }
+    }
+}
+
+impl Iterator for UpdatesIter {
+    type Item = Result<(SequenceNumber, WriteBatch)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(first) = self.first.take() {
+            return Some(first);
+        }
+        if self.done {
+            return None;
+        }
+        loop {
+            match self.next_batch() {
+                Ok(Some(batch)) => {
+                    // Skip batches ending before seq.
+                    let (seq, end) = (batch.sequence(), batch.sequence() + batch.count() as u64);
+                    if end <= self.seq {
+                        continue;
+                    }
+                    if let Some(expected) = self.expected {
+                        if seq != expected {
+                            self.done = true;
+                            return Some(err(
+                                StatusCode::Corruption,
+                                &format!(
+                                    "sequence numbers in log files are not contiguous: expected \
+                                     {}, found {}",
+                                    expected, seq
+                                ),
+                            ));
+                        }
+                    }
+                    self.expected = Some(end);
+                    return Some(Ok((seq, batch)));
+                }
+                Ok(None) => {
+                    self.done = true;
+                    return None;
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use crate::db_impl::DB;
+    use crate::log::LogWriter;
+    use crate::mem_env::MemEnv;
+    use crate::options;
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_updates_iter,
+            test_updates_iter_compacted,
+            test_updates_iter_retention,
+            test_updates_iter_gap,
+        )
+    }
+
+    // Returns the keys written by the batches returned by iter.
+    fn keys(iter: UpdatesIter) -> Vec<(SequenceNumber, Vec<Vec<u8>>)> {
+        iter.map(|r| {
+            let (seq, batch) = r.unwrap();
+            (seq, batch.iter().map(|(k, _)| k.to_vec()).collect())
+        })
+        .collect()
+    }
+
+    fn test_updates_iter() {
+        let mut db = DB::open("updatesdb", options::for_test()).unwrap();
+        db.put(b"a", b"1").unwrap();
+        let mut wb = WriteBatch::new();
+        wb.put(b"b", b"2");
+        wb.delete(b"a");
+        wb.put(b"c", b"3");
+        db.write(wb, false).unwrap();
+        db.delete(b"b").unwrap();
+
+        let all = vec![
+            (1, vec![b"a".to_vec()]),
+            (2, vec![b"b".to_vec(), b"a".to_vec(), b"c".to_vec()]),
+            (5, vec![b"b".to_vec()]),
+        ];
+        assert_eq!(all, keys(db.get_updates_since(0).unwrap()));
+        assert_eq!(all, keys(db.get_updates_since(1).unwrap()));
+        // A sequence number in the middle of a batch returns the whole batch.
+        assert_eq!(all[1..].to_vec(), keys(db.get_updates_since(3).unwrap()));
+        assert_eq!(all[2..].to_vec(), keys(db.get_updates_since(5).unwrap()));
+        assert!(keys(db.get_updates_since(6).unwrap()).is_empty());
+
+        let (seq, batch) = db.get_updates_since(2).unwrap().next().unwrap().unwrap();
+        assert_eq!(2, seq);
+        assert_eq!(
+            vec![
+                (&b"b"[..], Some(&b"2"[..])),
+                (b"a", None),
+                (b"c", Some(&b"3"[..]))
+            ],
+            batch.iter().collect::<Vec<_>>()
+        );
+
+        // Later writes are appended.
+        db.put(b"d", b"4").unwrap();
+        let updates = keys(db.get_updates_since(1).unwrap());
+        assert_eq!(all, updates[..3].to_vec());
+        assert_eq!((6, vec![b"d".to_vec()]), updates[3]);
+    }
+
+    fn test_updates_iter_compacted() {
+        let mut db = DB::open("updatesdb", options::for_test()).unwrap();
+        db.put(b"a", b"1").unwrap();
+        db.put(b"b", b"2").unwrap();
+        db.compact_range(b"a", b"b").unwrap();
+        db.put(b"c", b"3").unwrap();
+
+        let e = db.get_updates_since(1).err().unwrap();
+        assert_eq!(StatusCode::NotFound, e.code);
+        assert_eq!(
+            vec![(3, vec![b"c".to_vec()])],
+            keys(db.get_updates_since(3).unwrap())
+        );
+        assert!(keys(db.get_updates_since(4).unwrap()).is_empty());
+    }
+
+    fn test_updates_iter_retention() {
+        let mut opt = options::for_test();
+        opt.keep_log_files = 1;
+        let mut db = DB::open("updatesdb", opt.clone()).unwrap();
+        db.put(b"a", b"1").unwrap();
+        db.compact_range(b"a", b"b").unwrap();
+        db.put(b"b", b"2").unwrap();
+        db.compact_range(b"a", b"b").unwrap();
+        db.put(b"c", b"3").unwrap();
+
+        // Only the newest obsolete log file is kept.
+        assert!(db.get_updates_since(1).is_err());
+        let expected = vec![(2, vec![b"b".to_vec()]), (3, vec![b"c".to_vec()])];
+        assert_eq!(expected, keys(db.get_updates_since(2).unwrap()));
+
+        // Retained log files are not replayed when reopening.
+        drop(db);
+        let mut db = DB::open("updatesdb", opt).unwrap();
+        assert_eq!(expected, keys(db.get_updates_since(2).unwrap()));
+        assert_eq!(Some(b"1".to_vec()), db.get(b"a"));
+        assert_eq!(Some(b"3".to_vec()), db.get(b"c"));
+    }
+
+    fn test_updates_iter_gap() {
+        let env: Rc<Box<dyn Env>> = Rc::new(Box::new(MemEnv::new()));
+        let path = Path::new("gapdb");
+        // Log 1 contains sequence numbers 1..=3, log 2 starts at 5.
+        let logs = vec![
+            (1, vec![(1, vec!["a"]), (2, vec!["b", "c"])]),
+            (2, vec![(5, vec!["d"])]),
+        ];
+        for (num, batches) in logs {
+            let file = env.open_writable_file(&log_file_name(path, num)).unwrap();
+            let mut lw = LogWriter::new(file);
+            for (seq, keys) in batches {
+                let mut wb = WriteBatch::new();
+                for k in keys {
+                    wb.put(k.as_bytes(), b"v");
+                }
+                lw.add_record(&wb.encode(seq)).unwrap();
+            }
+            lw.flush().unwrap();
+        }
+
+        let mut iter = UpdatesIter::new(env.clone(), path, vec![1, 2], 1, 5).unwrap();
+        assert_eq!(1, iter.next().unwrap().unwrap().0);
+        assert_eq!(2, iter.next().unwrap().unwrap().0);
+        assert_eq!(
+            StatusCode::Corruption,
+            iter.next().unwrap().unwrap_err().code
+        );
+        assert!(iter.next().is_none());
+
+        // Starting after the gap is fine.
+        let mut iter = UpdatesIter::new(env, path, vec![1, 2], 5, 5).unwrap();
+        assert_eq!(5, iter.next().unwrap().unwrap().0);
+        assert!(iter.next().is_none());
+    }
+}
@@ -5,11 +5,14 @@ use crate::cmp::{Cmp, InternalKeyCmp};
 use crate::error::Result;
 use crate::key_types::{parse_internal_key, InternalKey, LookupKey, UserKey, ValueType};
 use crate::table_cache::TableCache;
-use crate::table_reader::TableIterator;
-use crate::types::{FileMetaData, FileNum, LdbIterator, Shared, MAX_SEQUENCE_NUMBER, NUM_LEVELS};
+use crate::table_reader::{Table, TableIterator};
+use crate::types::{
+    FileMetaData, FileNum, LdbIterator, SequenceNumber, Shared, MAX_SEQUENCE_NUMBER, NUM_LEVELS,
+};
 
 use std::cmp::Ordering;
 use std::default::Default;
+use std::ops::Bound;
 use std::rc::Rc;
 
 /// FileMetaHandle is a reference-counted FileMetaData object with interior mutability. This is
@@ -23,6 +26,13 @@ pub struct GetStats {
     level: usize,
 }
 
+/// MultiGetState tracks the lookup of one key in Version::multi_get().
+struct MultiGetState {
+    result: Option<Result<Option<Vec<u8>>>>,
+    stats: GetStats,
+    last_read: Option<(FileMetaHandle, usize)>,
+}
+
 pub struct Version {
     table_cache: Shared<TableCache>,
     user_cmp: Rc<Box<dyn Cmp>>,
@@ -89,11 +99,10 @@ impl Version {
                     // We don't need to check the sequence number; get() will not return an entry
                     // with a higher sequence number than the one in the supplied key.
                     let (typ, _, foundkey) = parse_internal_key(&k);
-                    if typ == ValueType::TypeValue
-                        && self.user_cmp.cmp(foundkey, ukey) == Ordering::Equal
-                    {
-                        return Ok(Some((v, stats)));
-                    } else if typ == ValueType::TypeDeletion {
+                    if self.user_cmp.cmp(foundkey, ukey) == Ordering::Equal {
+                        if typ == ValueType::TypeValue {
+                            return Ok(Some((v, stats)));
+                        }
                         // Skip looking once we have found a deletion.
                         return Ok(None);
                     }
@@ -103,6 +112,152 @@ impl Version {
         Ok(None)
     }
 
+    /// get_sequence returns the sequence number of the newest entry for the user key of `key` in
+    /// the tables of this Version (which may be a deletion), without reading its value. Unlike
+    /// get(), errors from reading a table are returned instead of skipping the table.
+    pub fn get_sequence<'a>(&self, key: InternalKey<'a>) -> Result<Option<SequenceNumber>> {
+        let levels = self.get_overlapping(key);
+        let ukey = parse_internal_key(key).2;
+
+        for files in levels.iter() {
+            for f in files {
+                if let Some((k, _)) = self.table_cache.borrow_mut().get(f.borrow().num, key)? {
+                    let (_, seq, foundkey) = parse_internal_key(&k);
+                    if self.user_cmp.cmp(foundkey, ukey) == Ordering::Equal {
+                        return Ok(Some(seq));
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// multi_get looks up a batch of keys, which must be sorted by user key and share the same
+    /// sequence number. Unlike get(), the keys are distributed over the tables they may be in, and
+    /// each table is consulted once for all of its keys. The results correspond to the keys at the
+    /// same position.
+    pub fn multi_get<'a>(
+        &self,
+        keys: &[InternalKey<'a>],
+    ) -> Vec<Result<Option<(Vec<u8>, GetStats)>>> {
+        let mut state: Vec<MultiGetState> = keys
+            .iter()
+            .map(|_| MultiGetState {
+                result: None,
+                stats: GetStats {
+                    file: None,
+                    level: 0,
+                },
+                last_read: None,
+            })
+            .collect();
+
+        // Level 0 tables may overlap; look at them newest-first, so that the first entry found for
+        // a key is the newest one.
+        let mut level0 = self.files[0].clone();
+        level0.sort_by(|a, b| b.borrow().num.cmp(&a.borrow().num));
+        for f in level0.iter() {
+            let batch: Vec<usize> = (0..keys.len())
+                .filter(|&i| state[i].result.is_none() && self.file_contains(f, keys[i]))
+                .collect();
+            self.multi_get_from_file(f, 0, &batch, keys, &mut state);
+        }
+
+        let icmp = InternalKeyCmp(self.user_cmp.clone());
+        for level in 1..NUM_LEVELS {
+            // Tables in other levels are disjoint; as keys are sorted, the keys belonging to one
+            // table are adjacent.
+            let files = &self.files[level];
+            let mut batches: Vec<(FileMetaHandle, Vec<usize>)> = vec![];
+            for i in 0..keys.len() {
+                if state[i].result.is_some() {
+                    continue;
+                }
+                let f = match find_file(&icmp, files, keys[i]) {
+                    Some(ix) if self.file_contains(&files[ix], keys[i]) => &files[ix],
+                    _ => continue,
+                };
+                match batches.last_mut() {
+                    Some((ref last, ref mut batch)) if Rc::ptr_eq(last, f) => batch.push(i),
+                    _ => batches.push((f.clone(), vec![i])),
+                }
+            }
+            for (f, batch) in batches {
+                self.multi_get_from_file(&f, level, &batch, keys, &mut state);
+            }
+        }
+
+        state
+            .into_iter()
+            .map(|st| match st.result {
+                Some(Ok(Some(v))) => Ok(Some((v, st.stats))),
+                Some(Ok(None)) | None => Ok(None),
+                Some(Err(e)) => Err(e),
+            })
+            .collect()
+    }
+
+    /// file_contains returns true if the user key of ikey is within the key range of f.
+    fn file_contains(&self, f: &FileMetaHandle, ikey: InternalKey) -> bool {
+        let f = f.borrow();
+        let ukey = parse_internal_key(ikey).2;
+        self.user_cmp.cmp(ukey, parse_internal_key(&f.smallest).2) >= Ordering::Equal
+            && self.user_cmp.cmp(ukey, parse_internal_key(&f.largest).2) <= Ordering::Equal
+    }
+
+    /// multi_get_from_file looks up the keys at the indices in batch in table f, and records the
+    /// outcome for every key that was found (or found to be deleted).
+    fn multi_get_from_file<'a>(
+        &self,
+        f: &FileMetaHandle,
+        level: usize,
+        batch: &[usize],
+        keys: &[InternalKey<'a>],
+        state: &mut [MultiGetState],
+    ) {
+        if batch.is_empty() {
+            return;
+        }
+        for &i in batch {
+            let st = &mut state[i];
+            if st.last_read.is_some() && st.stats.file.is_none() {
+                let (file, level) = st.last_read.take().unwrap();
+                st.stats.file = Some(file);
+                st.stats.level = level;
+            }
+            st.last_read = Some((f.clone(), level));
+        }
+
+        let batch_keys: Vec<InternalKey> = batch.iter().map(|&i| keys[i]).collect();
+        let found = self
+            .table_cache
+            .borrow_mut()
+            .get_table(f.borrow().num)
+            .and_then(|t| t.multi_get(&batch_keys));
+        match found {
+            Ok(entries) => {
+                for (&i, entry) in batch.iter().zip(entries.into_iter()) {
+                    if let Some((k, v)) = entry {
+                        let (typ, _, foundkey) = parse_internal_key(&k);
+                        let ukey = parse_internal_key(keys[i]).2;
+                        if self.user_cmp.cmp(foundkey, ukey) != Ordering::Equal {
+                            continue;
+                        }
+                        state[i].result = match typ {
+                            ValueType::TypeValue => Some(Ok(Some(v))),
+                            ValueType::TypeDeletion => Some(Ok(None)),
+                        };
+                    }
+                }
+            }
+            Err(e) => {
+                for &i in batch {
+                    state[i].result = Some(Err(e.clone()));
+                }
+            }
+        }
+    }
+
     /// get_overlapping returns the files overlapping key in each level.
     fn get_overlapping<'a>(&self, key: InternalKey<'a>) -> [Vec<FileMetaHandle>; NUM_LEVELS] {
         let mut levels: [Vec<FileMetaHandle>; NUM_LEVELS] = Default::default();
@@ -364,24 +519,119 @@ impl Version {
     /// new_iters returns a set of iterators that can be merged to yield all entries in this
     /// version.
     pub fn new_iters(&self) -> Result<Vec<Box<dyn LdbIterator>>> {
+        self.new_range_iters(Bound::Unbounded, Bound::Unbounded)
+    }
+
+    /// new_range_iters is like new_iters, but only opens the tables whose key range overlaps the
+    /// range between `lower` and `upper` (user keys). The iterators may still yield entries
+    /// outside of that range.
+    pub fn new_range_iters(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<Vec<Box<dyn LdbIterator>>> {
         let mut iters: Vec<Box<dyn LdbIterator>> = vec![];
         for f in &self.files[0] {
-            iters.push(Box::new(
-                self.table_cache
-                    .borrow_mut()
-                    .get_table(f.borrow().num)?
-                    .iter(),
-            ));
+            if self.file_in_range(f, lower, upper) {
+                iters.push(Box::new(
+                    self.table_cache
+                        .borrow_mut()
+                        .get_table(f.borrow().num)?
+                        .iter(),
+                ));
+            }
         }
 
         for l in 1..NUM_LEVELS {
-            if !self.files[l].is_empty() {
-                iters.push(Box::new(self.new_concat_iter(l)));
+            let files: Vec<FileMetaHandle> = self.files[l]
+                .iter()
+                .filter(|f| self.file_in_range(f, lower, upper))
+                .cloned()
+                .collect();
+            if !files.is_empty() {
+                iters.push(Box::new(new_version_iter(
+                    files,
+                    self.table_cache.clone(),
+                    self.user_cmp.clone(),
+                )));
             }
         }
 
         Ok(iters)
     }
+
+    /// file_in_range returns true if the key range of f overlaps the range between lower and
+    /// upper.
+    fn file_in_range(&self, f: &FileMetaHandle, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> bool {
+        let f = f.borrow();
+        let ulargest = parse_internal_key(&f.largest).2;
+        match lower {
+            Bound::Included(l) if self.user_cmp.cmp(ulargest, l) == Ordering::Less => return false,
+            Bound::Excluded(l) if self.user_cmp.cmp(ulargest, l) != Ordering::Greater => {
+                return false
+            }
+            _ => {}
+        }
+        let usmallest = parse_internal_key(&f.smallest).2;
+        match upper {
+            Bound::Included(u) if self.user_cmp.cmp(usmallest, u) == Ordering::Greater => false,
+            Bound::Excluded(u) if self.user_cmp.cmp(usmallest, u) != Ordering::Less => false,
+            _ => true,
+        }
+    }
+
+    /// new_prefix_iters is like new_iters, but omits tables that can't contain any key starting
+    /// with `prefix`, and the table iterators skip blocks that can't contain such keys. The
+    /// iterators may still yield keys not starting with `prefix`.
+    pub fn new_prefix_iters(&self, prefix: &[u8]) -> Result<Vec<Box<dyn LdbIterator>>> {
+        let mut iters: Vec<Box<dyn LdbIterator>> = vec![];
+        for f in &self.files[0] {
+            if let Some(tbl) = self.prefix_table(f, prefix)? {
+                iters.push(Box::new(tbl.prefix_iter(prefix)));
+            }
+        }
+
+        for l in 1..NUM_LEVELS {
+            let mut files = vec![];
+            for f in &self.files[l] {
+                if self.prefix_table(f, prefix)?.is_some() {
+                    files.push(f.clone());
+                }
+            }
+            if !files.is_empty() {
+                let mut iter =
+                    new_version_iter(files, self.table_cache.clone(), self.user_cmp.clone());
+                iter.prefix = Some(prefix.to_vec());
+                iters.push(Box::new(iter));
+            }
+        }
+
+        Ok(iters)
+    }
+
+    /// prefix_table returns the table of f if its key range and filters allow for it to contain
+    /// keys starting with `prefix`.
+    fn prefix_table(&self, f: &FileMetaHandle, prefix: &[u8]) -> Result<Option<Table>> {
+        {
+            let f = f.borrow();
+            let (usmallest, ulargest) = (
+                parse_internal_key(&f.smallest).2,
+                parse_internal_key(&f.largest).2,
+            );
+            if self.user_cmp.cmp(ulargest, prefix) == Ordering::Less
+                || (self.user_cmp.cmp(usmallest, prefix) == Ordering::Greater
+                    && !usmallest.starts_with(prefix))
+            {
+                return Ok(None);
+            }
+        }
+        let tbl = self.table_cache.borrow_mut().get_table(f.borrow().num)?;
+        if tbl.prefix_may_match(prefix) {
+            Ok(Some(tbl))
+        } else {
+            Ok(None)
+        }
+    }
 }
 
 /// new_version_iter returns an iterator over the entries in the specified ordered list of table
@@ -397,6 +647,7 @@ pub fn new_version_iter(
         cmp: InternalKeyCmp(ucmp),
         current: None,
         current_ix: 0,
+        prefix: None,
     }
 }
 
@@ -413,6 +664,40 @@ pub struct VersionIter {
 
     current: Option<TableIterator>,
     current_ix: usize,
+    // If set, table iterators skip blocks not containing this prefix.
+    prefix: Option<Vec<u8>>,
+}
+
+impl VersionIter {
+    fn table_iter(&self, tbl: &Table) -> TableIterator {
+        match self.prefix {
+            Some(ref prefix) => tbl.prefix_iter(prefix),
+            None => tbl.iter(),
+        }
+    }
+
+    /// last_entry_before positions the iterator at the last entry of the last non-empty table
+    /// before the table with index `ix`.
+    fn last_entry_before(&mut self, mut ix: usize) -> bool {
+        while ix > 0 {
+            ix -= 1;
+            let tbl = self
+                .cache
+                .borrow_mut()
+                .get_table(self.files[ix].borrow().num);
+            if let Ok(tbl) = tbl {
+                let mut iter = self.table_iter(&tbl);
+                iter.seek_to_last();
+                if iter.valid() {
+                    self.current_ix = ix;
+                    self.current = Some(iter);
+                    return true;
+                }
+            }
+        }
+        self.reset();
+        false
+    }
 }
 
 impl LdbIterator for VersionIter {
@@ -437,7 +722,7 @@ impl LdbIterator for VersionIter {
             .borrow_mut()
             .get_table(self.files[self.current_ix].borrow().num)
         {
-            self.current = Some(tbl.iter());
+            self.current = Some(self.table_iter(&tbl));
         } else {
             return false;
         }
@@ -450,20 +735,40 @@ impl LdbIterator for VersionIter {
             false
         }
     }
+    fn key(&self) -> &[u8] {
+        match self.current {
+            Some(ref t) => t.key(),
+            None => &[],
+        }
+    }
+    fn value(&self) -> &[u8] {
+        match self.current {
+            Some(ref t) => t.value(),
+            None => &[],
+        }
+    }
     fn seek(&mut self, key: &[u8]) {
         if let Some(ix) = find_file(&self.cmp, &self.files, key) {
-            if let Ok(tbl) = self
+            let tbl = self
                 .cache
                 .borrow_mut()
-                .get_table(self.files[ix].borrow().num)
-            {
-                let mut iter = tbl.iter();
+                .get_table(self.files[ix].borrow().num);
+            if let Ok(tbl) = tbl {
+                let mut iter = self.table_iter(&tbl);
                 iter.seek(key);
                 if iter.valid() {
                     self.current_ix = ix;
                     self.current = Some(iter);
                     return;
                 }
+                // With a prefix, the rest of the table may have been skipped; continue with the
+                // next table.
+                if self.prefix.is_some() && ix + 1 < self.files.len() {
+                    self.current_ix = ix + 1;
+                    self.current = None;
+                    self.advance();
+                    return;
+                }
             }
         }
         self.reset();
@@ -479,22 +784,17 @@ impl LdbIterator for VersionIter {
         if let Some(ref mut t) = self.current {
             if t.prev() {
                 return true;
-            } else if self.current_ix > 0 {
-                let f = &self.files[self.current_ix - 1];
-                // Find previous table, seek to last entry.
-                if let Ok(tbl) = self.cache.borrow_mut().get_table(f.borrow().num) {
-                    let mut iter = tbl.iter();
-                    iter.seek(&f.borrow().largest);
-                    // The saved largest key must be in the table.
-                    assert!(iter.valid());
-                    self.current_ix -= 1;
-                    *t = iter;
-                    return true;
-                }
             }
         }
-        self.reset();
-        false
+        if self.current.is_some() {
+            self.last_entry_before(self.current_ix)
+        } else {
+            false
+        }
+    }
+    fn seek_to_last(&mut self) {
+        let n = self.files.len();
+        self.last_entry_before(n);
     }
 }
 
@@ -599,6 +899,8 @@ pub mod testutil {
             num,
             smallest: LookupKey::new(smallest, smallestix).internal_key().to_vec(),
             largest: LookupKey::new(largest, largestix).internal_key().to_vec(),
+            key_gen: 0,
+            content_hash: None,
         })
     }
 
@@ -730,14 +1032,18 @@ pub mod tests {
     use crate::merging_iter::MergingIter;
     use crate::options;
     use crate::test_util::{test_iterator_properties, LdbIteratorIter};
+    use crate::types::share;
     use teaclave_test_utils::*;
 
     pub fn run_tests() -> bool {
         run_tests!(
             test_version_max_next_level_overlapping,
             test_version_all_iters,
+            test_version_range_iters,
             test_version_summary,
             test_version_get_simple,
+            test_version_get_other_deletion,
+            test_version_multi_get,
             test_version_get_overlapping_basic,
             test_version_overlap_in_level,
             test_version_pick_memtable_output_level,
@@ -788,6 +1094,38 @@ pub mod tests {
         });
     }
 
+    fn test_version_range_iters() {
+        let v = make_version().0;
+        let mut opt = options::for_test();
+        opt.cmp = Rc::new(Box::new(InternalKeyCmp(Rc::new(Box::new(DefaultCmp)))));
+
+        // Only tables 4 and 5 (level 1) and 6 (level 2) overlap the range.
+        let iters = v
+            .new_range_iters(Bound::Included(&b"daa"[..]), Bound::Excluded(&b"eab"[..]))
+            .unwrap();
+        assert_eq!(2, iters.len());
+        let mut miter = MergingIter::new(opt.cmp.clone(), iters);
+        assert_eq!(LdbIteratorIter::wrap(&mut miter).count(), 9);
+
+        let iters = v
+            .new_range_iters(Bound::Included(&b"h"[..]), Bound::Unbounded)
+            .unwrap();
+        assert_eq!(1, iters.len());
+        let mut miter = MergingIter::new(opt.cmp.clone(), iters);
+        assert_eq!(LdbIteratorIter::wrap(&mut miter).count(), 4);
+
+        // The upper bound is exclusive.
+        let iters = v
+            .new_range_iters(Bound::Unbounded, Bound::Excluded(&b"aaa"[..]))
+            .unwrap();
+        assert_eq!(0, iters.len());
+        let iters = v
+            .new_range_iters(Bound::Unbounded, Bound::Included(&b"aaa"[..]))
+            .unwrap();
+        assert_eq!(2, iters.len());
+        assert_eq!(5, v.new_iters().unwrap().len());
+    }
+
     fn test_version_summary() {
         let v = make_version().0;
         let expected = "level 0: 2 files, 483 bytes ([(1, 232), (2, 251)]); level 1: 3 files, 651 \
@@ -826,6 +1164,71 @@ pub mod tests {
         }
     }
 
+    fn test_version_get_other_deletion() {
+        let opts = options::for_test();
+        let env = opts.env.clone();
+        // A deletion of "c" in a newer table must not hide "b" in an older one.
+        let f1: &[(&[u8], &[u8], ValueType)] = &[
+            (b"a", b"new", ValueType::TypeValue),
+            (b"c", b"", ValueType::TypeDeletion),
+        ];
+        let t1 = write_table(&env, f1, 10, 1);
+        let f2: &[(&[u8], &[u8], ValueType)] = &[(b"b", b"old", ValueType::TypeValue)];
+        let t2 = write_table(&env, f2, 1, 2);
+
+        let cache = TableCache::new("db", opts.clone(), 100);
+        let mut v = Version::new(share(cache), Rc::new(Box::new(DefaultCmp)));
+        v.files[1] = vec![t1];
+        v.files[2] = vec![t2];
+
+        let found = v.get(LookupKey::new(b"b", 100).internal_key()).unwrap();
+        assert_eq!(b"old".to_vec(), found.unwrap().0);
+        assert!(v
+            .get(LookupKey::new(b"c", 100).internal_key())
+            .unwrap()
+            .is_none());
+    }
+
+    fn test_version_multi_get() {
+        let v = make_version().0;
+        let keys: &[&[u8]] = &[
+            b"aaa", b"aab", b"aac", b"aba", b"daa", b"dac", b"gba", b"gbb", b"gca", b"zzz",
+        ];
+        let expected: &[Option<&[u8]>] = &[
+            Some(b"val1"),
+            Some(b"val2"),
+            None,
+            Some(b"val3"),
+            Some(b"val1"),
+            None,
+            Some(b"val3"),
+            None,
+            None,
+            None,
+        ];
+        let lkeys: Vec<LookupKey> = keys.iter().map(|k| LookupKey::new(k, 100)).collect();
+        let ikeys: Vec<InternalKey> = lkeys.iter().map(|k| k.internal_key()).collect();
+        let results = v.multi_get(&ikeys);
+        assert_eq!(keys.len(), results.len());
+        for (r, e) in results.into_iter().zip(expected.iter()) {
+            assert_eq!(*e, r.unwrap().as_ref().map(|(v, _)| v.as_slice()));
+        }
+
+        // Older versions are visible at a lower sequence number.
+        let lkeys: Vec<LookupKey> = [&b"aac"[..], b"aba"]
+            .iter()
+            .map(|k| LookupKey::new(k, 25))
+            .collect();
+        let ikeys: Vec<InternalKey> = lkeys.iter().map(|k| k.internal_key()).collect();
+        let vals: Vec<Option<Vec<u8>>> = v
+            .multi_get(&ikeys)
+            .into_iter()
+            .map(|r| r.unwrap().map(|(v, _)| v))
+            .collect();
+        assert_eq!(vec![Some(b"val3".to_vec()), Some(b"val4".to_vec())], vals);
+        assert!(v.multi_get(&[]).is_empty());
+    }
+
     fn test_version_get_overlapping_basic() {
         let v = make_version().0;
 
@@ -3,7 +3,8 @@ use std::prelude::v1::*;
 
 use crate::error::{err, Result, StatusCode};
 use crate::key_types::InternalKey;
-use crate::types::{FileMetaData, FileNum, SequenceNumber};
+use crate::state_digest::StateDigest;
+use crate::types::{FileMetaData, FileNum, KeyGeneration, SequenceNumber, TableHash};
 
 use integer_encoding::{VarIntReader, VarIntWriter};
 
@@ -26,6 +27,14 @@ enum EditTag {
     DeletedFile = 6,
     NewFile = 7,
     PrevLogNumber = 9, // sic!
+    // Not in the original: the key generation of a file added by a preceding NewFile tag.
+    NewFileKeyGeneration = 10,
+    // Not in the original: the value of the monotonic counter the edit is bound to.
+    CounterValue = 11,
+    // Not in the original: the content hash of a file added by a preceding NewFile tag.
+    NewFileContentHash = 12,
+    // Not in the original: the digest of the state after applying the edit.
+    StateDigest = 13,
 }
 
 fn tag_to_enum(t: u32) -> Option<EditTag> {
@@ -38,6 +47,10 @@ fn tag_to_enum(t: u32) -> Option<EditTag> {
         6 => Some(EditTag::DeletedFile),
         7 => Some(EditTag::NewFile),
         9 => Some(EditTag::PrevLogNumber),
+        10 => Some(EditTag::NewFileKeyGeneration),
+        11 => Some(EditTag::CounterValue),
+        12 => Some(EditTag::NewFileContentHash),
+        13 => Some(EditTag::StateDigest),
         _ => None,
     }
 }
@@ -67,6 +80,8 @@ pub struct VersionEdit {
     pub prev_log_number: Option<FileNum>,
     pub next_file_number: Option<FileNum>,
     pub last_seq: Option<SequenceNumber>,
+    pub counter: Option<u64>,
+    pub state_digest: Option<StateDigest>,
 
     pub compaction_ptrs: Vec<CompactionPointer>,
     pub deleted: HashSet<(usize, FileNum)>,
@@ -81,6 +96,8 @@ impl VersionEdit {
             prev_log_number: None,
             next_file_number: None,
             last_seq: None,
+            counter: None,
+            state_digest: None,
             compaction_ptrs: Vec::with_capacity(8),
             deleted: HashSet::with_capacity(8),
             new_files: Vec::with_capacity(8),
@@ -119,6 +136,14 @@ impl VersionEdit {
         self.next_file_number = Some(num)
     }
 
+    pub fn set_counter(&mut self, value: u64) {
+        self.counter = Some(value)
+    }
+
+    pub fn set_state_digest(&mut self, digest: StateDigest) {
+        self.state_digest = Some(digest)
+    }
+
     pub fn set_compact_pointer(&mut self, level: usize, key: InternalKey) {
         self.compaction_ptrs.push(CompactionPointer {
             level,
@@ -158,6 +183,17 @@ impl VersionEdit {
             buf.write_varint(ls).unwrap();
         }
 
+        if let Some(c) = self.counter {
+            buf.write_varint(EditTag::CounterValue as u32).unwrap();
+            buf.write_varint(c).unwrap();
+        }
+
+        if let Some(ref d) = self.state_digest {
+            buf.write_varint(EditTag::StateDigest as u32).unwrap();
+            buf.write_varint(d.len()).unwrap();
+            buf.write(d).unwrap();
+        }
+
         for cptr in self.compaction_ptrs.iter() {
             buf.write_varint(EditTag::CompactPointer as u32).unwrap();
             buf.write_varint(cptr.level).unwrap();
@@ -181,6 +217,22 @@ impl VersionEdit {
             buf.write(nf.1.smallest.as_ref()).unwrap();
             buf.write_varint(nf.1.largest.len()).unwrap();
             buf.write(nf.1.largest.as_ref()).unwrap();
+
+            // Omitted for generation 0, so that manifests stay readable by other implementations
+            // unless keys are rotated.
+            if nf.1.key_gen != 0 {
+                buf.write_varint(EditTag::NewFileKeyGeneration as u32)
+                    .unwrap();
+                buf.write_varint(nf.1.num).unwrap();
+                buf.write_varint(nf.1.key_gen).unwrap();
+            }
+            if let Some(ref h) = nf.1.content_hash {
+                buf.write_varint(EditTag::NewFileContentHash as u32)
+                    .unwrap();
+                buf.write_varint(nf.1.num).unwrap();
+                buf.write_varint(h.len()).unwrap();
+                buf.write(h).unwrap();
+            }
         }
 
         buf
@@ -234,6 +286,48 @@ impl VersionEdit {
                         }
                     }
 
+                    EditTag::CounterValue => {
+                        if let Ok(c) = reader.read_varint() {
+                            ve.counter = Some(c);
+                        } else {
+                            return err(StatusCode::IOError, "Couldn't read counter value");
+                        }
+                    }
+
+                    EditTag::StateDigest => {
+                        let buf = read_length_prefixed(&mut reader)?;
+                        let mut d = StateDigest::default();
+                        if buf.len() != d.len() {
+                            return err(StatusCode::Corruption, "Bad state digest length");
+                        }
+                        d.copy_from_slice(&buf);
+                        ve.state_digest = Some(d);
+                    }
+
+                    EditTag::NewFileContentHash => {
+                        let num: FileNum = match reader.read_varint() {
+                            Ok(num) => num,
+                            Err(_) => {
+                                return err(StatusCode::IOError, "Couldn't read file num");
+                            }
+                        };
+                        let buf = read_length_prefixed(&mut reader)?;
+                        let mut h = TableHash::default();
+                        if buf.len() != h.len() {
+                            return err(StatusCode::Corruption, "Bad content hash length");
+                        }
+                        h.copy_from_slice(&buf);
+                        match ve.new_files.iter_mut().rev().find(|f| f.1.num == num) {
+                            Some(f) => f.1.content_hash = Some(h),
+                            None => {
+                                return err(
+                                    StatusCode::Corruption,
+                                    "content hash for a file not added by the edit",
+                                )
+                            }
+                        }
+                    }
+
                     EditTag::CompactPointer => {
                         // Monads by indentation...
                         if let Ok(lvl) = reader.read_varint() {
@@ -272,6 +366,8 @@ impl VersionEdit {
                                             smallest,
                                             largest,
                                             allowed_seeks: 0,
+                                            key_gen: 0,
+                                            content_hash: None,
                                         },
                                     ))
                                 } else {
@@ -284,6 +380,30 @@ impl VersionEdit {
                             return err(StatusCode::IOError, "Couldn't read file level");
                         }
                     }
+
+                    EditTag::NewFileKeyGeneration => {
+                        let num: FileNum = match reader.read_varint() {
+                            Ok(num) => num,
+                            Err(_) => {
+                                return err(StatusCode::IOError, "Couldn't read file num");
+                            }
+                        };
+                        let gen: KeyGeneration = match reader.read_varint() {
+                            Ok(gen) => gen,
+                            Err(_) => {
+                                return err(StatusCode::IOError, "Couldn't read key generation");
+                            }
+                        };
+                        match ve.new_files.iter_mut().rev().find(|f| f.1.num == num) {
+                            Some(f) => f.1.key_gen = gen,
+                            None => {
+                                return err(
+                                    StatusCode::Corruption,
+                                    "key generation for a file not added by the edit",
+                                )
+                            }
+                        }
+                    }
                 }
             } else {
                 return err(
@@ -317,6 +437,8 @@ pub mod tests {
         ve.set_comparator_name(DefaultCmp.id());
         ve.set_log_num(123);
         ve.set_next_file(456);
+        ve.set_counter(789);
+        ve.set_state_digest([4; 32]);
         ve.set_compact_pointer(0, &[0, 1, 2]);
         ve.set_compact_pointer(1, &[3, 4, 5]);
         ve.set_compact_pointer(2, &[6, 7, 8]);
@@ -328,6 +450,20 @@ pub mod tests {
                 size: 234,
                 smallest: vec![5, 6, 7],
                 largest: vec![8, 9, 0],
+                key_gen: 0,
+                content_hash: None,
+            },
+        );
+        ve.add_file(
+            1,
+            FileMetaData {
+                allowed_seeks: 0,
+                num: 902,
+                size: 345,
+                smallest: vec![1],
+                largest: vec![2],
+                key_gen: 3,
+                content_hash: Some([7; 32]),
             },
         );
         ve.delete_file(1, 132);
@@ -339,6 +475,8 @@ pub mod tests {
         assert_eq!(decoded.comparator, Some(DefaultCmp.id().to_string()));
         assert_eq!(decoded.log_number, Some(123));
         assert_eq!(decoded.next_file_number, Some(456));
+        assert_eq!(decoded.counter, Some(789));
+        assert_eq!(decoded.state_digest, Some([4; 32]));
         assert_eq!(decoded.compaction_ptrs.len(), 3);
         assert_eq!(
             decoded.compaction_ptrs[0],
@@ -361,7 +499,7 @@ pub mod tests {
                 key: vec![6, 7, 8],
             }
         );
-        assert_eq!(decoded.new_files.len(), 1);
+        assert_eq!(decoded.new_files.len(), 2);
         assert_eq!(
             decoded.new_files[0],
             (
@@ -372,9 +510,13 @@ pub mod tests {
                     size: 234,
                     smallest: vec![5, 6, 7],
                     largest: vec![8, 9, 0],
+                    key_gen: 0,
+                    content_hash: None,
                 }
             )
         );
+        assert_eq!(3, decoded.new_files[1].1.key_gen);
+        assert_eq!(Some([7; 32]), decoded.new_files[1].1.content_hash);
         assert_eq!(decoded.deleted.len(), 1);
         assert!(decoded.deleted.contains(&(1, 132)));
     }
@@ -2,19 +2,22 @@
 use std::prelude::v1::*;
 
 use crate::cmp::{Cmp, InternalKeyCmp};
-use crate::env::Env;
+use crate::env::{path_to_str, Env};
 use crate::error::{err, Result, Status, StatusCode};
 use crate::key_types::{parse_internal_key, InternalKey, UserKey};
 use crate::log::{LogReader, LogWriter};
 use crate::merging_iter::MergingIter;
 use crate::options::Options;
+use crate::state_digest::{state_digest, StateDigest};
 use crate::table_cache::TableCache;
 use crate::types::{
-    parse_file_name, share, FileMetaData, FileNum, FileType, LdbIterator, Shared, NUM_LEVELS,
+    parse_file_name, share, FileMetaData, FileNum, FileType, LdbIterator, SequenceNumber, Shared,
+    NUM_LEVELS,
 };
 use crate::version::{new_version_iter, total_size, FileMetaHandle, Version};
 use crate::version_edit::VersionEdit;
 
+use std::cell::Ref;
 use std::cmp::Ordering;
 use std::collections::HashSet;
 use std::io::Write;
@@ -180,9 +183,13 @@ pub struct VersionSet {
     pub last_seq: u64,
     pub log_num: u64,
     pub prev_log_num: u64,
+    // Set for databases opened read-only, which must not advance the monotonic counter.
+    pub read_only: bool,
 
     current: Option<Shared<Version>>,
     compaction_ptrs: [Vec<u8>; NUM_LEVELS],
+    // The digest over the current version and last_seq, as of the last manifest write.
+    state_digest: StateDigest,
 
     descriptor_log: Option<LogWriter<Box<dyn Write>>>,
 }
@@ -203,9 +210,11 @@ impl VersionSet {
             last_seq: 0,
             log_num: 0,
             prev_log_num: 0,
+            read_only: false,
 
             current: Some(v),
             compaction_ptrs: Default::default(),
+            state_digest: StateDigest::default(),
             descriptor_log: None,
         }
     }
@@ -234,6 +243,11 @@ impl VersionSet {
         self.current.as_ref().unwrap().clone()
     }
 
+    /// Returns the state digest recorded by the last manifest write or the recovery.
+    pub fn state_digest(&self) -> StateDigest {
+        self.state_digest
+    }
+
     pub fn add_version(&mut self, v: Version) {
         self.current = Some(share(v));
     }
@@ -498,6 +512,12 @@ impl VersionSet {
         }
         edit.set_next_file(self.next_file_num);
         edit.set_last_seq(self.last_seq);
+        // The counter is incremented only once the edit has been written, so that a crash in
+        // between leaves a manifest that is one ahead of the counter, which recover() accepts.
+        let counter = self.opt.monotonic_counter.clone();
+        if let Some(ref c) = counter {
+            edit.set_counter(c.read()? + 1);
+        }
 
         let mut v = Version::new(self.cache.clone(), self.opt.cmp.clone());
         {
@@ -506,6 +526,8 @@ impl VersionSet {
             builder.save_to(&self.cmp, self.current.as_ref().unwrap(), &mut v);
         }
         self.finalize(&mut v);
+        let digest = version_digest(&v, self.last_seq);
+        edit.set_state_digest(digest);
 
         if self.descriptor_log.is_none() {
             let descname = manifest_file_name(&self.dbname, self.manifest_num);
@@ -524,13 +546,25 @@ impl VersionSet {
         set_current_file(&self.opt.env, &self.dbname, self.manifest_num)?;
 
         self.add_version(v);
+        self.state_digest = digest;
         // log_number was set above.
         self.log_num = edit.log_number.unwrap();
+        if let Some(c) = counter {
+            c.increment()?;
+        }
 
         // TODO: Roll back written files if something went wrong.
         Ok(())
     }
 
+    /// write_new_manifest switches to a new manifest, starting with a snapshot of the current
+    /// state, and points CURRENT to it. The previous manifest becomes obsolete.
+    pub fn write_new_manifest(&mut self) -> Result<()> {
+        self.manifest_num = self.new_file_number();
+        self.descriptor_log = None;
+        self.log_and_apply(VersionEdit::new())
+    }
+
     fn finalize(&self, v: &mut Version) {
         let mut best_lvl = None;
         let mut best_score = None;
@@ -587,6 +621,8 @@ impl VersionSet {
             let mut prev_log_number = None;
             let mut next_file_number = None;
             let mut last_seq = None;
+            let mut counter = None;
+            let mut digest = None;
 
             let mut buf = Vec::new();
             while let Ok(size) = logreader.read(&mut buf) {
@@ -607,6 +643,11 @@ impl VersionSet {
                 if let Some(pln) = edit.prev_log_number {
                     prev_log_number = Some(pln);
                 }
+                if let Some(c) = edit.counter {
+                    counter = Some(c);
+                }
+                // Only the digest of the last edit describes the recovered state.
+                digest = edit.state_digest;
             }
 
             if let Some(ln) = log_number {
@@ -640,11 +681,25 @@ impl VersionSet {
             } else {
                 self.prev_log_num = 0;
             }
+            self.check_counter(&descfilename, counter.unwrap_or(0))?;
         }
 
         let mut v = Version::new(self.cache.clone(), self.opt.cmp.clone());
         builder.save_to(&self.cmp, self.current.as_ref().unwrap(), &mut v);
         self.finalize(&mut v);
+        let computed = version_digest(&v, self.last_seq);
+        if let Some(d) = digest {
+            if d != computed {
+                return err(
+                    StatusCode::Corruption,
+                    &format!(
+                        "state digest in {} doesn't match the recovered state",
+                        path_to_str(&descfilename)
+                    ),
+                );
+            }
+        }
+        self.state_digest = computed;
         self.add_version(v);
         self.manifest_num = self.next_file_num - 1;
         log!(
@@ -662,6 +717,45 @@ impl VersionSet {
         Ok(!self.reuse_manifest(&descfilename, &current))
     }
 
+    /// check_counter fails with StatusCode::Rollback if the manifest, bound to the counter value
+    /// found, is older than the monotonic counter. If the counter is one behind because the
+    /// database crashed before incrementing it, it is brought up to date, unless the database is
+    /// read-only. A manifest further ahead doesn't belong to the counter and fails with
+    /// StatusCode::Corruption.
+    fn check_counter(&self, manifest: &Path, found: u64) -> Result<()> {
+        let c = match self.opt.monotonic_counter {
+            Some(ref c) => c,
+            None => return Ok(()),
+        };
+        let expected = c.read()?;
+        if found < expected {
+            return err(
+                StatusCode::Rollback,
+                &format!(
+                    "{} is bound to counter value {}, but the counter is at {}",
+                    path_to_str(manifest),
+                    found,
+                    expected
+                ),
+            );
+        }
+        if found > expected + 1 {
+            return err(
+                StatusCode::Corruption,
+                &format!(
+                    "{} is bound to counter value {}, more than one ahead of the counter at {}",
+                    path_to_str(manifest),
+                    found,
+                    expected
+                ),
+            );
+        }
+        if found > expected && !self.read_only {
+            c.increment()?;
+        }
+        Ok(())
+    }
+
     /// reuse_manifest checks whether the current manifest can be reused.
     fn reuse_manifest(
         &mut self,
@@ -857,7 +951,7 @@ pub fn manifest_file_name<P: AsRef<Path>>(dbname: P, file_num: FileNum) -> PathB
     dbname.as_ref().join(manifest_name(file_num)).to_owned()
 }
 
-fn temp_file_name<P: AsRef<Path>>(dbname: P, file_num: FileNum) -> PathBuf {
+pub fn temp_file_name<P: AsRef<Path>>(dbname: P, file_num: FileNum) -> PathBuf {
     dbname
         .as_ref()
         .join(format!("{:06}.dbtmp", file_num))
@@ -893,6 +987,8 @@ pub fn set_current_file<P: AsRef<Path>>(
         let mut f = env.open_writable_file(Path::new(&tempfile))?;
         f.write(manifest_base.as_os_str().as_bytes())?;
         f.write("\n".as_bytes())?;
+        // CURRENT must be complete on disk before it replaces the old one.
+        f.flush()?;
     }
     let currentfile = current_file_name(dbname);
     if let Err(e) = env.rename(Path::new(&tempfile), Path::new(&currentfile)) {
@@ -908,6 +1004,16 @@ fn sort_files_by_smallest<C: Cmp>(cmp: &C, files: &mut Vec<FileMetaHandle>) {
     files.sort_by(|a, b| cmp.cmp(&a.borrow().smallest, &b.borrow().smallest))
 }
 
+/// Returns the state digest over the tables of v and last_seq.
+fn version_digest(v: &Version, last_seq: SequenceNumber) -> StateDigest {
+    let files: Vec<Ref<FileMetaData>> = v
+        .files
+        .iter()
+        .flat_map(|level| level.iter().map(|f| f.borrow()))
+        .collect();
+    state_digest(files.iter().map(|f| &**f), last_seq)
+}
+
 /// merge_iters merges and collects the items from two sorted iterators.
 fn merge_iters<
     Item,
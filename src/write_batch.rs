@@ -1,6 +1,7 @@
 #[cfg(feature = "mesalock_sgx")]
 use std::prelude::v1::*;
 
+use crate::error::{err, Result, StatusCode};
 use crate::key_types::ValueType;
 use crate::memtable::MemTable;
 use crate::types::SequenceNumber;
@@ -11,6 +12,14 @@ use std::io::Write;
 const SEQNUM_OFFSET: usize = 0;
 const COUNT_OFFSET: usize = 8;
 const HEADER_SIZE: usize = 12;
+/// The maximum length of an encoded 64 bit varint.
+const MAX_VARINT_LEN: usize = 10;
+
+/// A Handler receives the operations stored in a WriteBatch, in order, from WriteBatch::iterate().
+pub trait Handler {
+    fn put(&mut self, key: &[u8], val: &[u8]);
+    fn delete(&mut self, key: &[u8]);
+}
 
 /// A WriteBatch contains entries to be written to a MemTable (for example) in a compact form.
 ///
@@ -29,7 +38,22 @@ impl WriteBatch {
         WriteBatch { entries: v }
     }
 
-    /// Initializes a WriteBatch with a serialized WriteBatch.
+    /// Returns a WriteBatch decoded from a serialized WriteBatch (as returned by encode()). The
+    /// encoding is checked, and an error with StatusCode::Corruption is returned if it is
+    /// malformed.
+    pub fn from_bytes(from: &[u8]) -> Result<WriteBatch> {
+        if from.len() < HEADER_SIZE {
+            return err(StatusCode::Corruption, "write batch shorter than header");
+        }
+        let wb = WriteBatch {
+            entries: from.to_vec(),
+        };
+        wb.for_each_entry(|_, _| {})?;
+        Ok(wb)
+    }
+
+    /// Initializes a WriteBatch with a serialized WriteBatch. The contents are not checked; use
+    /// from_bytes() for untrusted input.
     pub fn set_contents(&mut self, from: &[u8]) {
         self.entries.clear();
         self.entries.extend_from_slice(from);
@@ -61,9 +85,22 @@ impl WriteBatch {
         self.set_count(c + 1);
     }
 
+    /// Appends the operations of another batch to this one. A batch shorter than the header (as
+    /// left by set_contents()) has no operations.
+    pub fn append(&mut self, other: &WriteBatch) {
+        if other.entries.len() < HEADER_SIZE {
+            return;
+        }
+        self.entries
+            .extend_from_slice(&other.entries[HEADER_SIZE..]);
+        let c = self.count() + other.count();
+        self.set_count(c);
+    }
+
     /// Clear the contents of a WriteBatch.
     pub fn clear(&mut self) {
-        self.entries.clear()
+        self.entries.clear();
+        self.entries.resize(HEADER_SIZE, 0);
     }
 
     fn byte_size(&self) -> usize {
@@ -94,6 +131,40 @@ impl WriteBatch {
         }
     }
 
+    /// iterate calls handler for every operation in the batch, in order. If the batch is
+    /// malformed, an error with StatusCode::Corruption is returned once the malformed entry is
+    /// reached.
+    pub fn iterate(&self, handler: &mut dyn Handler) -> Result<()> {
+        self.for_each_entry(|k, v| match v {
+            Some(v) => handler.put(k, v),
+            None => handler.delete(k),
+        })
+    }
+
+    /// for_each_entry decodes all entries and checks that their number matches the count in the
+    /// header.
+    fn for_each_entry<F: FnMut(&[u8], Option<&[u8]>)>(&self, mut f: F) -> Result<()> {
+        let mut ix = HEADER_SIZE;
+        let mut found = 0;
+        while ix < self.entries.len() {
+            let (k, v, next) = decode_entry(&self.entries, ix)?;
+            f(k, v);
+            ix = next;
+            found += 1;
+        }
+        if found != self.count() {
+            return err(
+                StatusCode::Corruption,
+                &format!(
+                    "write batch has {} entries, expected {}",
+                    found,
+                    self.count()
+                ),
+            );
+        }
+        Ok(())
+    }
+
     pub fn insert_into_memtable(&self, mut seq: SequenceNumber, mt: &mut MemTable) {
         for (k, v) in self.iter() {
             match v {
@@ -115,7 +186,7 @@ pub struct WriteBatchIter<'a> {
     ix: usize,
 }
 
-/// The iterator also plays the role of the decoder.
+/// The iterator also plays the role of the decoder. It stops at the first malformed entry.
 impl<'a> Iterator for WriteBatchIter<'a> {
     type Item = (&'a [u8], Option<&'a [u8]>);
     fn next(&mut self) -> Option<Self::Item> {
@@ -123,25 +194,53 @@ impl<'a> Iterator for WriteBatchIter<'a> {
             return None;
         }
 
-        let tag = self.batch.entries[self.ix];
-        self.ix += 1;
+        match decode_entry(&self.batch.entries, self.ix) {
+            Ok((k, v, next)) => {
+                self.ix = next;
+                Some((k, v))
+            }
+            Err(_) => {
+                self.ix = self.batch.entries.len();
+                None
+            }
+        }
+    }
+}
 
-        let (klen, l) = usize::decode_var(&self.batch.entries[self.ix..]);
-        self.ix += l;
-        let k = &self.batch.entries[self.ix..self.ix + klen];
-        self.ix += klen;
+/// decode_entry decodes the entry at offset ix of a serialized batch. It returns the key, the
+/// value (None for deletions) and the offset of the next entry.
+fn decode_entry(entries: &[u8], mut ix: usize) -> Result<(&[u8], Option<&[u8]>, usize)> {
+    let tag = entries[ix];
+    ix += 1;
+    if tag != ValueType::TypeValue as u8 && tag != ValueType::TypeDeletion as u8 {
+        return err(StatusCode::Corruption, "unknown tag in write batch");
+    }
 
-        if tag == ValueType::TypeValue as u8 {
-            let (vlen, m) = usize::decode_var(&self.batch.entries[self.ix..]);
-            self.ix += m;
-            let v = &self.batch.entries[self.ix..self.ix + vlen];
-            self.ix += vlen;
+    let (k, next) = decode_length_prefixed(entries, ix)?;
+    ix = next;
+    if tag == ValueType::TypeValue as u8 {
+        let (v, next) = decode_length_prefixed(entries, ix)?;
+        Ok((k, Some(v), next))
+    } else {
+        Ok((k, None, ix))
+    }
+}
 
-            return Some((k, Some(v)));
-        } else {
-            return Some((k, None));
-        }
+/// decode_length_prefixed decodes a varint-length-prefixed slice at offset ix, returning the slice
+/// and the offset after it.
+fn decode_length_prefixed(entries: &[u8], ix: usize) -> Result<(&[u8], usize)> {
+    let src = &entries[ix..];
+    let src = &src[..src.len().min(MAX_VARINT_LEN)];
+    let (len, l) = usize::decode_var(src);
+    // A complete varint ends with a byte without continuation bit.
+    if l == 0 || src[l - 1] & 0x80 != 0 {
+        return err(StatusCode::Corruption, "bad length in write batch");
+    }
+    let start = ix + l;
+    if entries.len() - start < len {
+        return err(StatusCode::Corruption, "truncated entry in write batch");
     }
+    Ok((&entries[start..start + len], start + len))
 }
 
 #[cfg(feature = "enclave_unit_test")]
@@ -151,7 +250,12 @@ pub mod tests {
     use teaclave_test_utils::*;
 
     pub fn run_tests() -> bool {
-        run_tests!(test_write_batch,)
+        run_tests!(
+            test_write_batch,
+            test_write_batch_iterate,
+            test_write_batch_from_bytes,
+            test_write_batch_append,
+        )
     }
 
     fn test_write_batch() {
@@ -192,4 +296,105 @@ pub mod tests {
         assert_eq!(i, 5);
         assert_eq!(b.encode(1).len(), 49);
     }
+
+    struct Recorder(Vec<String>);
+
+    impl Handler for Recorder {
+        fn put(&mut self, key: &[u8], val: &[u8]) {
+            self.0.push(format!(
+                "put {} {}",
+                String::from_utf8_lossy(key),
+                String::from_utf8_lossy(val)
+            ));
+        }
+        fn delete(&mut self, key: &[u8]) {
+            self.0
+                .push(format!("delete {}", String::from_utf8_lossy(key)));
+        }
+    }
+
+    fn test_write_batch_iterate() {
+        let mut b = WriteBatch::new();
+        b.put(b"abc", b"def");
+        b.delete(b"xyz");
+        b.put(b"empty", b"");
+
+        let mut r = Recorder(vec![]);
+        b.iterate(&mut r).unwrap();
+        assert_eq!(vec!["put abc def", "delete xyz", "put empty "], r.0);
+
+        b.clear();
+        assert_eq!(0, b.count());
+        b.put(b"a", b"b");
+        let mut r = Recorder(vec![]);
+        b.iterate(&mut r).unwrap();
+        assert_eq!(vec!["put a b"], r.0);
+    }
+
+    fn test_write_batch_from_bytes() {
+        let mut b = WriteBatch::new();
+        b.put(b"abc", b"def");
+        b.delete(b"xyz");
+        let encoded = b.encode(7);
+
+        let b = WriteBatch::from_bytes(&encoded).unwrap();
+        assert_eq!(7, b.sequence());
+        assert_eq!(2, b.count());
+        assert_eq!(2, b.iter().count());
+        assert!(WriteBatch::from_bytes(&WriteBatch::new().encode(1)).is_ok());
+
+        let corrupt = |data: &[u8]| {
+            let e = WriteBatch::from_bytes(data).err().unwrap();
+            assert_eq!(StatusCode::Corruption, e.code);
+        };
+        // Too short for the header.
+        corrupt(&encoded[..HEADER_SIZE - 1]);
+        // Truncated records, or missing records (the first record ends after 9 bytes).
+        for l in HEADER_SIZE..encoded.len() {
+            corrupt(&encoded[..l]);
+        }
+        let mut wrong_count = encoded.clone();
+        wrong_count[COUNT_OFFSET] = 3;
+        corrupt(&wrong_count);
+        // Unknown tag.
+        let mut bad_tag = encoded.clone();
+        bad_tag[HEADER_SIZE] = 7;
+        corrupt(&bad_tag);
+        // Key length beyond the end, and an unterminated varint.
+        let mut bad_len = encoded.clone();
+        bad_len[HEADER_SIZE + 1] = 100;
+        corrupt(&bad_len);
+        let mut bad_varint = encoded[..HEADER_SIZE + 1].to_vec();
+        bad_varint.extend_from_slice(&[0xff; 12]);
+        corrupt(&bad_varint);
+
+        // iterate() reports the corruption as well, and iter() stops early.
+        let mut b = WriteBatch::new();
+        b.set_contents(&bad_len);
+        assert!(b.iterate(&mut Recorder(vec![])).is_err());
+        assert_eq!(0, b.iter().count());
+    }
+
+    fn test_write_batch_append() {
+        let mut a = WriteBatch::new();
+        a.put(b"abc", b"def");
+        let mut b = WriteBatch::new();
+        b.delete(b"abc");
+        b.put(b"xyz", b"123");
+
+        a.append(&b);
+        assert_eq!(3, a.count());
+        let mut r = Recorder(vec![]);
+        a.iterate(&mut r).unwrap();
+        assert_eq!(vec!["put abc def", "delete abc", "put xyz 123"], r.0);
+
+        a.append(&WriteBatch::new());
+        assert_eq!(3, a.count());
+        assert!(WriteBatch::from_bytes(&a.encode(1)).is_ok());
+
+        let mut short = WriteBatch::new();
+        short.set_contents(b"abc");
+        a.append(&short);
+        assert_eq!(3, a.count());
+    }
 }
@@ -0,0 +1,501 @@
+#[cfg(feature = "mesalock_sgx")]
+use std::prelude::v1::*;
+
+use crate::cmp::Cmp;
+use crate::db_impl::DB;
+use crate::db_iter::DBIterator;
+use crate::error::Result;
+use crate::key_types::{parse_internal_key, LookupKey, ValueType};
+use crate::memtable::{MemTable, MemtableIterator};
+use crate::types::{Direction, LdbIterator, MAX_SEQUENCE_NUMBER};
+use crate::write_batch::WriteBatch;
+
+use std::cmp::Ordering;
+use std::rc::Rc;
+
+/// A WriteBatchWithIndex is a WriteBatch that can be read back while it is being built. Besides
+/// the encoded batch, it keeps an index of its entries in a MemTable (i.e. a SkipMap), in which
+/// every entry is stored with its position in the batch as sequence number; so a lookup finds the
+/// most recent write to a key.
+pub struct WriteBatchWithIndex {
+    cmp: Rc<Box<dyn Cmp>>,
+    batch: WriteBatch,
+    index: MemTable,
+}
+
+impl WriteBatchWithIndex {
+    /// Returns an empty batch, indexed by the user comparator `cmp` (usually Options::cmp).
+    pub fn new(cmp: Rc<Box<dyn Cmp>>) -> WriteBatchWithIndex {
+        WriteBatchWithIndex {
+            index: MemTable::new(cmp.clone()),
+            cmp,
+            batch: WriteBatch::new(),
+        }
+    }
+
+    /// Adds an entry to the batch, to be added to the database.
+    pub fn put(&mut self, k: &[u8], v: &[u8]) {
+        self.batch.put(k, v);
+        let seq = self.batch.count() as u64;
+        self.index.add(seq, ValueType::TypeValue, k, v);
+    }
+
+    /// Marks an entry to be deleted from the database.
+    pub fn delete(&mut self, k: &[u8]) {
+        self.batch.delete(k);
+        let seq = self.batch.count() as u64;
+        self.index.add(seq, ValueType::TypeDeletion, k, b"");
+    }
+
+    /// Returns how many operations are in the batch.
+    pub fn count(&self) -> u32 {
+        self.batch.count()
+    }
+
+    /// Returns the underlying WriteBatch.
+    pub fn write_batch(&self) -> &WriteBatch {
+        &self.batch
+    }
+
+    /// Returns the underlying WriteBatch, e.g. in order to pass it to DB::write().
+    pub fn into_write_batch(self) -> WriteBatch {
+        self.batch
+    }
+
+    /// get_from_batch looks up the most recent write to key in the batch. It returns None if the
+    /// batch doesn't contain key, and Some(None) if the key was deleted by the batch.
+    pub fn get_from_batch(&self, key: &[u8]) -> Option<Option<Vec<u8>>> {
+        match self.index.get(&LookupKey::new(key, MAX_SEQUENCE_NUMBER)) {
+            (Some(v), _) => Some(Some(v)),
+            (None, true) => Some(None),
+            (None, false) => None,
+        }
+    }
+
+    /// get_from_batch_and_db returns the value of key as if the batch had been written to db
+    /// already: entries from the batch take precedence, and keys deleted by the batch are not
+    /// found.
+    pub fn get_from_batch_and_db(&self, db: &mut DB, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        match self.get_from_batch(key) {
+            Some(v) => Ok(v),
+            None => {
+                let ss = db.get_snapshot();
+                db.get_at(&ss, key)
+            }
+        }
+    }
+
+    /// iter_with_base returns an iterator over the entries of `base` with the batch applied on
+    /// top. Writes to the batch after the iterator was created may or may not be visible.
+    pub fn iter_with_base(&self, base: DBIterator) -> BatchOverlayIter {
+        BatchOverlayIter {
+            cmp: self.cmp.clone(),
+            base,
+            delta: DeltaIter {
+                cmp: self.cmp.clone(),
+                iter: self.index.iter(),
+            },
+            dir: Direction::Forward,
+            current: Source::Base,
+            valid: false,
+        }
+    }
+}
+
+/// DeltaIter iterates over the newest entry for every key in a batch index. It is positioned at
+/// the newest entry of the current key, and also returns deletions.
+struct DeltaIter {
+    cmp: Rc<Box<dyn Cmp>>,
+    iter: MemtableIterator,
+}
+
+impl DeltaIter {
+    fn valid(&self) -> bool {
+        self.iter.valid()
+    }
+    fn key(&self) -> &[u8] {
+        parse_internal_key(self.iter.key()).2
+    }
+    fn value(&self) -> &[u8] {
+        self.iter.value()
+    }
+    fn is_deletion(&self) -> bool {
+        parse_internal_key(self.iter.key()).0 == ValueType::TypeDeletion
+    }
+    fn reset(&mut self) {
+        self.iter.reset();
+    }
+    fn seek(&mut self, key: &[u8]) {
+        self.iter
+            .seek(LookupKey::new(key, MAX_SEQUENCE_NUMBER).internal_key());
+    }
+    fn seek_to_first(&mut self) {
+        self.iter.seek_to_first();
+    }
+    fn seek_to_last(&mut self) {
+        self.iter.seek_to_last();
+        self.to_newest();
+    }
+    fn advance(&mut self) {
+        let key = self.key().to_vec();
+        while self.iter.advance() {
+            if self.cmp.cmp(self.key(), &key) != Ordering::Equal {
+                break;
+            }
+        }
+    }
+    fn prev(&mut self) {
+        // This lands on the oldest entry of the previous key.
+        self.iter.prev();
+        self.to_newest();
+    }
+    /// seek_for_prev positions the iterator at the last key <= key.
+    fn seek_for_prev(&mut self, key: &[u8]) {
+        self.seek(key);
+        if !self.valid() {
+            self.seek_to_last();
+        } else if self.cmp.cmp(self.key(), key) == Ordering::Greater {
+            self.prev();
+        }
+    }
+    fn to_newest(&mut self) {
+        if self.valid() {
+            let key = self.key().to_vec();
+            self.seek(&key);
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Source {
+    Base,
+    Delta,
+}
+
+/// BatchOverlayIter iterates over the entries of a DBIterator with the contents of a
+/// WriteBatchWithIndex applied on top of them: entries from the batch replace entries with the
+/// same key, and deletions in the batch hide entries of the base iterator.
+pub struct BatchOverlayIter {
+    cmp: Rc<Box<dyn Cmp>>,
+    base: DBIterator,
+    delta: DeltaIter,
+    dir: Direction,
+    // Which of the iterators the current entry is taken from.
+    current: Source,
+    valid: bool,
+}
+
+impl BatchOverlayIter {
+    /// update_current chooses the iterator the current entry is taken from, skipping deleted keys.
+    /// Both iterators must be positioned at or after the current key, in iteration direction.
+    fn update_current(&mut self) {
+        loop {
+            if !self.delta.valid() {
+                self.current = Source::Base;
+                self.valid = self.base.valid();
+                return;
+            }
+            let ord = if self.base.valid() {
+                let ord = self.cmp.cmp(self.delta.key(), self.base.key());
+                if self.dir == Direction::Reverse {
+                    ord.reverse()
+                } else {
+                    ord
+                }
+            } else {
+                Ordering::Less
+            };
+            match ord {
+                Ordering::Greater => {
+                    self.current = Source::Base;
+                    self.valid = true;
+                    return;
+                }
+                _ if !self.delta.is_deletion() => {
+                    self.current = Source::Delta;
+                    self.valid = true;
+                    return;
+                }
+                Ordering::Equal => {
+                    // The base entry is deleted by the batch.
+                    self.step_base();
+                    self.step_delta();
+                }
+                Ordering::Less => self.step_delta(),
+            }
+        }
+    }
+
+    fn step_base(&mut self) {
+        if self.dir == Direction::Forward {
+            self.base.advance();
+        } else {
+            self.base.prev();
+        }
+    }
+
+    fn step_delta(&mut self) {
+        if self.dir == Direction::Forward {
+            self.delta.advance();
+        } else {
+            self.delta.prev();
+        }
+    }
+
+    /// step moves past the current entry in the iteration direction.
+    fn step(&mut self) {
+        if self.current == Source::Delta {
+            if self.base.valid()
+                && self.cmp.cmp(self.base.key(), self.delta.key()) == Ordering::Equal
+            {
+                self.step_base();
+            }
+            self.step_delta();
+        } else {
+            self.step_base();
+        }
+        self.update_current();
+    }
+}
+
+impl LdbIterator for BatchOverlayIter {
+    fn advance(&mut self) -> bool {
+        if !self.valid {
+            self.seek_to_first();
+            return self.valid;
+        }
+        if self.dir == Direction::Reverse {
+            // Position both iterators at or after the current key.
+            let key = self.key().to_vec();
+            self.seek(&key);
+        }
+        self.step();
+        self.valid
+    }
+    fn prev(&mut self) -> bool {
+        if !self.valid {
+            return false;
+        }
+        if self.dir == Direction::Forward {
+            // Position both iterators at or before the current key.
+            let key = self.key().to_vec();
+            self.dir = Direction::Reverse;
+            self.base.seek(&key);
+            if !self.base.valid() {
+                self.base.seek_to_last();
+            } else if self.cmp.cmp(self.base.key(), &key) == Ordering::Greater {
+                self.base.prev();
+            }
+            self.delta.seek_for_prev(&key);
+            self.update_current();
+        }
+        self.step();
+        self.valid
+    }
+    fn seek(&mut self, key: &[u8]) {
+        self.dir = Direction::Forward;
+        self.base.seek(key);
+        self.delta.seek(key);
+        self.update_current();
+    }
+    fn seek_to_first(&mut self) {
+        self.dir = Direction::Forward;
+        self.base.seek_to_first();
+        self.delta.seek_to_first();
+        self.update_current();
+    }
+    fn seek_to_last(&mut self) {
+        self.dir = Direction::Reverse;
+        self.base.seek_to_last();
+        self.delta.seek_to_last();
+        self.update_current();
+    }
+    fn reset(&mut self) {
+        self.base.reset();
+        self.delta.reset();
+        self.dir = Direction::Forward;
+        self.valid = false;
+    }
+    fn valid(&self) -> bool {
+        self.valid
+    }
+    fn current(&self, key: &mut Vec<u8>, val: &mut Vec<u8>) -> bool {
+        if !self.valid {
+            return false;
+        }
+        key.clear();
+        key.extend_from_slice(self.key());
+        val.clear();
+        val.extend_from_slice(self.value());
+        true
+    }
+    fn key(&self) -> &[u8] {
+        if !self.valid {
+            &[]
+        } else if self.current == Source::Delta {
+            self.delta.key()
+        } else {
+            self.base.key()
+        }
+    }
+    fn value(&self) -> &[u8] {
+        if !self.valid {
+            &[]
+        } else if self.current == Source::Delta {
+            self.delta.value()
+        } else {
+            self.base.value()
+        }
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use crate::options;
+    use crate::test_util::{test_iterator_properties, LdbIteratorIter};
+    use crate::types::current_key_val;
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_write_batch_with_index_get,
+            test_write_batch_with_index_iter,
+            test_write_batch_with_index_iter_reverse,
+            test_write_batch_with_index_iter_properties,
+        )
+    }
+
+    fn build() -> (DB, WriteBatchWithIndex) {
+        let opt = options::for_test();
+        let mut db = DB::open("wbwidb", opt.clone()).unwrap();
+        for k in &[&b"aaa"[..], b"bbb", b"ccc", b"ddd", b"eee"] {
+            db.put(k, b"db").unwrap();
+        }
+        let mut wb = WriteBatchWithIndex::new(opt.cmp.clone());
+        wb.put(b"abc", b"batch");
+        wb.put(b"bbb", b"batch0");
+        wb.put(b"bbb", b"batch");
+        wb.delete(b"ccc");
+        wb.delete(b"xyz");
+        wb.put(b"eee", b"batch");
+        wb.delete(b"eee");
+        wb.put(b"fff", b"batch");
+        (db, wb)
+    }
+
+    fn test_write_batch_with_index_get() {
+        let (mut db, wb) = build();
+        assert_eq!(8, wb.count());
+        assert_eq!(8, wb.write_batch().iter().count());
+
+        assert_eq!(Some(Some(b"batch".to_vec())), wb.get_from_batch(b"bbb"));
+        assert_eq!(Some(None), wb.get_from_batch(b"ccc"));
+        assert_eq!(Some(None), wb.get_from_batch(b"eee"));
+        assert_eq!(None, wb.get_from_batch(b"aaa"));
+
+        let cases: &[(&[u8], Option<&[u8]>)] = &[
+            (b"aaa", Some(b"db")),
+            (b"abc", Some(b"batch")),
+            (b"bbb", Some(b"batch")),
+            (b"ccc", None),
+            (b"ddd", Some(b"db")),
+            (b"eee", None),
+            (b"fff", Some(b"batch")),
+            (b"xyz", None),
+            (b"zzz", None),
+        ];
+        for (k, v) in cases {
+            assert_eq!(
+                v.map(|v| v.to_vec()),
+                wb.get_from_batch_and_db(&mut db, k).unwrap()
+            );
+        }
+
+        db.write(wb.into_write_batch(), false).unwrap();
+        for (k, v) in cases {
+            assert_eq!(v.map(|v| v.to_vec()), db.get(k));
+        }
+    }
+
+    fn expected() -> Vec<(Vec<u8>, Vec<u8>)> {
+        vec![
+            (b"aaa".to_vec(), b"db".to_vec()),
+            (b"abc".to_vec(), b"batch".to_vec()),
+            (b"bbb".to_vec(), b"batch".to_vec()),
+            (b"ddd".to_vec(), b"db".to_vec()),
+            (b"fff".to_vec(), b"batch".to_vec()),
+        ]
+    }
+
+    fn test_write_batch_with_index_iter() {
+        let (mut db, wb) = build();
+        let mut iter = wb.iter_with_base(db.new_iter().unwrap());
+        let entries: Vec<(Vec<u8>, Vec<u8>)> = LdbIteratorIter::wrap(&mut iter).collect();
+        assert_eq!(expected(), entries);
+
+        iter.seek(b"bb");
+        assert_eq!(b"bbb", iter.key());
+        assert_eq!(b"batch", iter.value());
+        assert!(iter.advance());
+        assert_eq!(b"ddd", iter.key());
+        iter.seek(b"ccc");
+        assert_eq!(b"ddd", iter.key());
+        iter.seek(b"eee");
+        assert_eq!(b"fff", iter.key());
+        assert!(!iter.advance());
+        iter.seek(b"zzz");
+        assert!(!iter.valid());
+
+        // An empty batch doesn't change anything.
+        let empty = WriteBatchWithIndex::new(options::for_test().cmp);
+        let mut iter = empty.iter_with_base(db.new_iter().unwrap());
+        assert_eq!(5, LdbIteratorIter::wrap(&mut iter).count());
+    }
+
+    fn test_write_batch_with_index_iter_reverse() {
+        let (mut db, wb) = build();
+        let mut iter = wb.iter_with_base(db.new_iter().unwrap());
+        let mut bwd = vec![];
+        iter.seek_to_last();
+        while iter.valid() {
+            bwd.push(current_key_val(&iter).unwrap());
+            iter.prev();
+        }
+        let mut fwd = expected();
+        fwd.reverse();
+        assert_eq!(fwd, bwd);
+
+        // Change directions in the middle.
+        iter.seek(b"bbb");
+        assert!(iter.prev());
+        assert_eq!(b"abc", iter.key());
+        assert!(iter.prev());
+        assert_eq!(b"aaa", iter.key());
+        assert!(iter.advance());
+        assert_eq!(b"abc", iter.key());
+        assert!(iter.advance());
+        assert_eq!(b"bbb", iter.key());
+        assert!(iter.advance());
+        assert_eq!(b"ddd", iter.key());
+        assert!(iter.prev());
+        assert_eq!(b"bbb", iter.key());
+        assert!(iter.prev());
+        assert!(iter.prev());
+        assert!(!iter.prev());
+        assert!(!iter.valid());
+    }
+
+    fn test_write_batch_with_index_iter_properties() {
+        let opt = options::for_test();
+        let mut db = DB::open("wbwidb", opt.clone()).unwrap();
+        db.put(b"aaa", b"1").unwrap();
+        db.put(b"ccc", b"3").unwrap();
+        db.put(b"ddd", b"4").unwrap();
+        let mut wb = WriteBatchWithIndex::new(opt.cmp.clone());
+        wb.put(b"bbb", b"2");
+        wb.delete(b"ddd");
+        wb.put(b"eee", b"5");
+        test_iterator_properties(wb.iter_with_base(db.new_iter().unwrap()));
+    }
+}